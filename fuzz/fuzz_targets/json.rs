@@ -0,0 +1,12 @@
+//! Reads keyed values from arbitrary bytes as JSON, which reaches both the
+//! map and sequence states of `KeyValueVisitor`.
+
+#![no_main]
+
+use keyedes_fuzz::deserialize;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut deserializer = serde_json::Deserializer::from_slice(data);
+    let _ = deserialize(&mut deserializer);
+});
@@ -0,0 +1,28 @@
+//! Drives the map states of `KeyValueVisitor` with fields in any order,
+//! repeated or missing, and content of any shape.
+
+#![no_main]
+
+use keyedes_fuzz::{check_buffered, deserialize, Field, Node};
+use libfuzzer_sys::fuzz_target;
+use serde::de::value::MapDeserializer;
+use serde_value::{DeserializerError, Value};
+
+fuzz_target!(|entries: Vec<(Field, Node)>| {
+    let content_first = entries
+        .iter()
+        .find(|(field, _)| !matches!(field, Field::Other(_)))
+        .is_some_and(|(field, _)| matches!(field, Field::Content));
+
+    let entries: Vec<(Value, Value)> = entries
+        .into_iter()
+        .map(|(field, node)| (field.into_value(), node.into_value()))
+        .collect();
+    let deserializer = MapDeserializer::<_, DeserializerError>::new(entries.into_iter());
+
+    if let Ok(shape) = deserialize(deserializer) {
+        if content_first {
+            check_buffered(&shape);
+        }
+    }
+});
@@ -0,0 +1,16 @@
+//! Drives the sequence states of `KeyValueVisitor` with too few or too many
+//! elements and keys of any shape.
+
+#![no_main]
+
+use keyedes_fuzz::{deserialize, Node};
+use libfuzzer_sys::fuzz_target;
+use serde::de::value::SeqDeserializer;
+use serde_value::{DeserializerError, Value};
+
+fuzz_target!(|elements: Vec<Node>| {
+    let elements: Vec<Value> = elements.into_iter().map(Node::into_value).collect();
+    let deserializer = SeqDeserializer::<_, DeserializerError>::new(elements.into_iter());
+
+    let _ = deserialize(deserializer);
+});
@@ -0,0 +1,149 @@
+//! Shared pieces of the fuzz targets of `KeyValueVisitor`.
+//!
+//! The targets feed hostile keyed values to
+//! [`deserialize_by_key_with_limits()`] and check that whatever content was
+//! buffered before the key stayed within the [`LIMITS`].
+
+use std::collections::BTreeMap;
+
+use keyedes::limits::Limits;
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use serde::de::Error as _;
+use serde::Deserializer;
+use serde_value::Value;
+
+pub const TYPE_NAME: &str = "Box<dyn Shape>";
+pub const FIELD_NAMES: &[&str; 2] = &["kind", "shape"];
+
+pub const LIMITS: Limits = Limits {
+    max_content_size: 4096,
+    max_depth: 16,
+    max_key_length: 32,
+};
+
+/// A value to be turned into a [`Value`], small enough for the fuzzer to
+/// explore while still nesting arbitrarily.
+#[derive(Arbitrary, Debug)]
+pub enum Node {
+    Unit,
+    Bool(bool),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    Char(char),
+    Str(String),
+    Bytes(Vec<u8>),
+    None,
+    Some(Box<Node>),
+    Newtype(Box<Node>),
+    Seq(Vec<Node>),
+    Map(Vec<(Node, Node)>),
+}
+
+impl Node {
+    pub fn into_value(self) -> Value {
+        match self {
+            Node::Unit => Value::Unit,
+            Node::Bool(v) => Value::Bool(v),
+            Node::U64(v) => Value::U64(v),
+            Node::I64(v) => Value::I64(v),
+            Node::F64(v) => Value::F64(v),
+            Node::Char(v) => Value::Char(v),
+            Node::Str(v) => Value::String(v),
+            Node::Bytes(v) => Value::Bytes(v),
+            Node::None => Value::Option(None),
+            Node::Some(v) => Value::Option(Some(Box::new(v.into_value()))),
+            Node::Newtype(v) => Value::Newtype(Box::new(v.into_value())),
+            Node::Seq(v) => Value::Seq(v.into_iter().map(Node::into_value).collect()),
+            Node::Map(v) => Value::Map(
+                v.into_iter()
+                    .map(|(k, v)| (k.into_value(), v.into_value()))
+                    .collect::<BTreeMap<_, _>>(),
+            ),
+        }
+    }
+}
+
+/// A field of a keyed value, with the known names more likely than others.
+#[derive(Arbitrary, Debug)]
+pub enum Field {
+    Tag,
+    Content,
+    Other(String),
+}
+
+impl Field {
+    pub fn into_value(self) -> Value {
+        Value::String(match self {
+            Field::Tag => FIELD_NAMES[0].to_owned(),
+            Field::Content => FIELD_NAMES[1].to_owned(),
+            Field::Other(name) => name,
+        })
+    }
+}
+
+/// What a keyed value deserialized to.
+#[derive(Debug)]
+pub enum Shape {
+    Any(Value),
+    Points(Vec<(i32, i32)>),
+    Unit,
+}
+
+/// Looks up the deserialization function of the key, with `any` reading
+/// whatever content is there so that it can be checked against the limits.
+pub fn shape(
+    key: String,
+    deserializer: &mut dyn erased_serde::Deserializer,
+) -> Result<Shape, keyedes::Error> {
+    match key.as_str() {
+        "any" => erased_serde::deserialize(deserializer).map(Shape::Any),
+        "points" => erased_serde::deserialize(deserializer).map(Shape::Points),
+        "unit" => erased_serde::deserialize::<()>(deserializer).map(|()| Shape::Unit),
+        _ => Err(keyedes::unknown_key()),
+    }
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Shape, keyedes::Error>
+where
+    D: Deserializer<'de>,
+{
+    keyedes::deserialize_by_key_with_limits(TYPE_NAME, FIELD_NAMES, LIMITS, shape, deserializer)
+        .map_err(keyedes::Error::custom)
+}
+
+/// Panics if the content of `shape` could not have been buffered within the
+/// limits.
+pub fn check_buffered(shape: &Shape) {
+    if let Shape::Any(value) = shape {
+        assert!(size(value) <= LIMITS.max_content_size, "{:?}", value);
+        assert!(depth(value) <= LIMITS.max_depth, "{:?}", value);
+    }
+}
+
+/// The size of a value as counted by [`Limits::max_content_size`].
+fn size(value: &Value) -> usize {
+    match value {
+        Value::String(v) => v.len(),
+        Value::Bytes(v) => v.len(),
+        Value::Option(Some(v)) | Value::Newtype(v) => 1 + size(v),
+        Value::Seq(v) => 1 + v.iter().map(size).sum::<usize>(),
+        Value::Map(v) => 1 + v.iter().map(|(k, v)| size(k) + size(v)).sum::<usize>(),
+        _ => 1,
+    }
+}
+
+fn depth(value: &Value) -> usize {
+    match value {
+        Value::Option(Some(v)) | Value::Newtype(v) => 1 + depth(v),
+        Value::Seq(v) => 1 + v.iter().map(depth).max().unwrap_or(0),
+        Value::Map(v) => {
+            1 + v
+                .iter()
+                .map(|(k, v)| depth(k).max(depth(v)))
+                .max()
+                .unwrap_or(0)
+        }
+        _ => 0,
+    }
+}
@@ -0,0 +1,464 @@
+//! Ready-made adapters for fields containing objects of a [`Registry`].
+//!
+//! The [`Keyed`] type can be used with `#[serde(with = "...")]` for a boxed
+//! object or any combination of `Option`, `Vec`, `BTreeMap`, and `HashMap`
+//! around one. `Arc` and `Rc` are supported as well. With the `serde_with`
+//! feature, `Keyed` also implements `SerializeAs` and `DeserializeAs`.
+//!
+//! ```
+//! # mod outer {
+//! use std::collections::{BTreeMap, HashMap};
+//! use std::sync::Arc;
+//!
+//! use keyedes::{DesFnSync, Keyed, Registry};
+//! use once_cell::sync::Lazy;
+//! use serde::{Deserialize, Serialize};
+//!
+//! trait Shape: erased_serde::Serialize {
+//!     fn key(&self) -> &'static str;
+//! }
+//!
+//! static SHAPES: Lazy<HashMap<String, DesFnSync<Box<dyn Shape>>>> = Lazy::new(|| {
+//!     let mut map = HashMap::<String, DesFnSync<Box<dyn Shape>>>::new();
+//!     // fill out the map
+//!     map
+//! });
+//!
+//! struct Shapes;
+//!
+//! impl Registry for Shapes {
+//!     type Object = dyn Shape;
+//!     type Key = String;
+//!
+//!     const TYPE_NAME: &'static str = "Box<dyn Shape>";
+//!     const FIELD_NAMES: &'static [&'static str; 2] = &["id", "data"];
+//!
+//!     fn key(object: &dyn Shape) -> String {
+//!         object.key().to_owned()
+//!     }
+//!
+//!     fn deserialize(
+//!         key: String,
+//!         deserializer: &mut dyn erased_serde::Deserializer,
+//!     ) -> Result<Box<dyn Shape>, keyedes::Error> {
+//!         SHAPES
+//!             .get(&key)
+//!             .ok_or_else(keyedes::unknown_key)
+//!             .and_then(|f| f(deserializer))
+//!     }
+//! }
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Drawing {
+//!     #[serde(with = "Keyed::<Shapes>")]
+//!     background: Option<Box<dyn Shape>>,
+//!     #[serde(with = "Keyed::<Shapes>")]
+//!     shapes: Vec<Box<dyn Shape>>,
+//!     #[serde(with = "Keyed::<Shapes>")]
+//!     named: BTreeMap<String, Arc<dyn Shape>>,
+//! }
+//! # }
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{deserialize_by_key, serialize_with_key, Registry};
+
+/// Adapter for serializing a field through the registry `R`.
+///
+/// Use it with `#[serde(with = "keyedes::Keyed::<R>")]` on any field whose
+/// type implements [`KeyedValue<R>`].
+pub struct Keyed<R>(PhantomData<R>);
+
+impl<R> Keyed<R> {
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: KeyedValue<R>,
+        S: Serializer,
+    {
+        value.serialize_keyed(serializer)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: KeyedValue<R>,
+        D: Deserializer<'de>,
+    {
+        T::deserialize_keyed(deserializer)
+    }
+}
+
+/// A type that can be serialized and deserialized through the registry `R`.
+///
+/// This is implemented for pointers to `R::Object` and for standard
+/// collections of other implementors. The parameter `R` may also be a marker
+/// wrapping a registry, like [`Shared`](crate::shared::Shared), to select a
+/// different representation.
+pub trait KeyedValue<R>: Sized {
+    fn serialize_keyed<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer;
+
+    fn deserialize_keyed<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>;
+}
+
+/// A way of writing single objects of a registry, selected by a marker type.
+///
+/// Every [`Registry`] is a representation of its own objects. Markers that
+/// pick a different format implement this trait for the boxed case only, and
+/// get [`KeyedValue`] for `Box`, `Arc`, and `Rc` pointers to
+/// [`Object`](Representation::Object) from the impls here.
+pub trait Representation {
+    type Object: ?Sized;
+
+    fn serialize_object<S>(object: &Self::Object, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer;
+
+    fn deserialize_object<'de, D>(deserializer: D) -> Result<Box<Self::Object>, D::Error>
+    where
+        D: Deserializer<'de>;
+}
+
+impl<R: Registry> Representation for R {
+    type Object = R::Object;
+
+    fn serialize_object<S>(object: &R::Object, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_with_key(
+            R::TYPE_NAME,
+            R::FIELD_NAMES,
+            &R::key(object),
+            object,
+            serializer,
+        )
+    }
+
+    fn deserialize_object<'de, D>(deserializer: D) -> Result<Box<R::Object>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_by_key(R::TYPE_NAME, R::FIELD_NAMES, R::deserialize, deserializer)
+    }
+}
+
+impl<M: Representation> KeyedValue<M> for Box<M::Object> {
+    fn serialize_keyed<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        M::serialize_object(self, serializer)
+    }
+
+    fn deserialize_keyed<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        M::deserialize_object(deserializer)
+    }
+}
+
+impl<M: Representation> KeyedValue<M> for Arc<M::Object> {
+    fn serialize_keyed<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        M::serialize_object(self, serializer)
+    }
+
+    fn deserialize_keyed<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        M::deserialize_object(deserializer).map(Arc::from)
+    }
+}
+
+impl<M: Representation> KeyedValue<M> for Rc<M::Object> {
+    fn serialize_keyed<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        M::serialize_object(self, serializer)
+    }
+
+    fn deserialize_keyed<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        M::deserialize_object(deserializer).map(Rc::from)
+    }
+}
+
+impl<R, T: KeyedValue<R>> KeyedValue<R> for Option<T> {
+    fn serialize_keyed<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Some(value) => serializer.serialize_some(&SerializeKeyed::<R, T>::new(value)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    fn deserialize_keyed<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = Option::<DeserializeKeyed<R, T>>::deserialize(deserializer)?;
+        Ok(value.map(|v| v.0))
+    }
+}
+
+impl<R, T: KeyedValue<R>> KeyedValue<R> for Vec<T> {
+    fn serialize_keyed<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(self.iter().map(SerializeKeyed::<R, T>::new))
+    }
+
+    fn deserialize_keyed<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let values = Vec::<DeserializeKeyed<R, T>>::deserialize(deserializer)?;
+        Ok(values.into_iter().map(|v| v.0).collect())
+    }
+}
+
+impl<R, K, T> KeyedValue<R> for BTreeMap<K, T>
+where
+    K: Serialize + for<'de> Deserialize<'de> + Ord,
+    T: KeyedValue<R>,
+{
+    fn serialize_keyed<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_map(
+            self.iter()
+                .map(|(k, v)| (k, SerializeKeyed::<R, T>::new(v))),
+        )
+    }
+
+    fn deserialize_keyed<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let values = BTreeMap::<K, DeserializeKeyed<R, T>>::deserialize(deserializer)?;
+        Ok(values.into_iter().map(|(k, v)| (k, v.0)).collect())
+    }
+}
+
+impl<R, K, T, H> KeyedValue<R> for HashMap<K, T, H>
+where
+    K: Serialize + for<'de> Deserialize<'de> + Eq + Hash,
+    T: KeyedValue<R>,
+    H: BuildHasher + Default,
+{
+    fn serialize_keyed<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_map(
+            self.iter()
+                .map(|(k, v)| (k, SerializeKeyed::<R, T>::new(v))),
+        )
+    }
+
+    fn deserialize_keyed<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let values = HashMap::<K, DeserializeKeyed<R, T>, H>::deserialize(deserializer)?;
+        Ok(values.into_iter().map(|(k, v)| (k, v.0)).collect())
+    }
+}
+
+struct SerializeKeyed<'a, R, T>(&'a T, PhantomData<R>);
+
+impl<'a, R, T> SerializeKeyed<'a, R, T> {
+    fn new(value: &'a T) -> Self {
+        SerializeKeyed(value, PhantomData)
+    }
+}
+
+impl<'a, R, T: KeyedValue<R>> Serialize for SerializeKeyed<'a, R, T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.0.serialize_keyed(serializer)
+    }
+}
+
+struct DeserializeKeyed<R, T>(T, PhantomData<R>);
+
+impl<'de, R, T: KeyedValue<R>> Deserialize<'de> for DeserializeKeyed<R, T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        T::deserialize_keyed(deserializer).map(|v| DeserializeKeyed(v, PhantomData))
+    }
+}
+
+#[cfg(feature = "serde_with")]
+impl<R, T: KeyedValue<R>> serde_with::SerializeAs<T> for Keyed<R> {
+    fn serialize_as<S>(source: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        source.serialize_keyed(serializer)
+    }
+}
+
+#[cfg(feature = "serde_with")]
+impl<'de, R, T: KeyedValue<R>> serde_with::DeserializeAs<'de, T> for Keyed<R> {
+    fn deserialize_as<D>(deserializer: D) -> Result<T, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        T::deserialize_keyed(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{deserialize_into_boxed_trait, DesFn, Error};
+
+    trait TestTrait: erased_serde::Serialize {
+        fn key(&self) -> &'static str;
+        fn name(&self) -> String;
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct TestStructA {
+        name: String,
+    }
+
+    impl TestTrait for TestStructA {
+        fn key(&self) -> &'static str {
+            "A"
+        }
+        fn name(&self) -> String {
+            self.name.clone()
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct TestUnitC;
+
+    impl TestTrait for TestUnitC {
+        fn key(&self) -> &'static str {
+            "C"
+        }
+        fn name(&self) -> String {
+            "just a c".to_string()
+        }
+    }
+
+    struct TestRegistry;
+
+    impl Registry for TestRegistry {
+        type Object = dyn TestTrait;
+        type Key = String;
+
+        const TYPE_NAME: &'static str = "Box<dyn TestTrait>";
+        const FIELD_NAMES: &'static [&'static str; 2] = &["id", "data"];
+
+        fn key(object: &dyn TestTrait) -> String {
+            object.key().to_string()
+        }
+
+        fn deserialize(
+            key: String,
+            deserializer: &mut dyn erased_serde::Deserializer,
+        ) -> Result<Box<dyn TestTrait>, Error> {
+            let f: DesFn<Box<dyn TestTrait>> = match key.as_str() {
+                "A" => deserialize_into_boxed_trait!(TestStructA),
+                "C" => deserialize_into_boxed_trait!(TestUnitC),
+                _ => return Err(crate::unknown_key()),
+            };
+            f(deserializer)
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Wrapper {
+        #[serde(with = "Keyed::<TestRegistry>")]
+        single: Box<dyn TestTrait>,
+        #[serde(with = "Keyed::<TestRegistry>")]
+        optional: Option<Box<dyn TestTrait>>,
+        #[serde(with = "Keyed::<TestRegistry>")]
+        list: Vec<Arc<dyn TestTrait>>,
+        #[serde(with = "Keyed::<TestRegistry>")]
+        map: BTreeMap<String, Rc<dyn TestTrait>>,
+        #[serde(with = "Keyed::<TestRegistry>")]
+        nested: Option<Vec<Option<Box<dyn TestTrait>>>>,
+    }
+
+    const JSON: &str = concat!(
+        r#"{"single":{"id":"A","data":{"name":"chuck norris"}},"#,
+        r#""optional":null,"#,
+        r#""list":[{"id":"C","data":null},{"id":"A","data":{"name":"bruce lee"}}],"#,
+        r#""map":{"x":{"id":"C","data":null}},"#,
+        r#""nested":[null,{"id":"A","data":{"name":"jackie chan"}}]}"#,
+    );
+
+    #[test]
+    fn keyed_adapters_round_trip() {
+        let wrapper: Wrapper = serde_json::from_str(JSON).unwrap();
+        assert_eq!(wrapper.single.name(), "chuck norris");
+        assert!(wrapper.optional.is_none());
+        assert_eq!(wrapper.list[0].name(), "just a c");
+        assert_eq!(wrapper.list[1].name(), "bruce lee");
+        assert_eq!(wrapper.map["x"].name(), "just a c");
+        let nested = wrapper.nested.as_ref().unwrap();
+        assert!(nested[0].is_none());
+        assert_eq!(nested[1].as_ref().unwrap().name(), "jackie chan");
+
+        assert_eq!(serde_json::to_string(&wrapper).unwrap(), JSON);
+    }
+
+    #[test]
+    fn keyed_adapters_return_error_on_unknown_key() {
+        let json = r#"[{"id":"A","data":{"name":"x"}},{"id":"Z","data":null}]"#;
+        let mut deserializer = serde_json::Deserializer::from_str(json);
+        let result =
+            Keyed::<TestRegistry>::deserialize::<Vec<Box<dyn TestTrait>>, _>(&mut deserializer);
+        assert!(result.is_err());
+    }
+
+    #[cfg(feature = "serde_with")]
+    #[test]
+    fn keyed_implements_serde_with_traits() {
+        #[serde_with::serde_as]
+        #[derive(Serialize, Deserialize)]
+        struct Wrapper {
+            #[serde_as(as = "Vec<Keyed<TestRegistry>>")]
+            list: Vec<Box<dyn TestTrait>>,
+            #[serde_as(as = "Option<Keyed<TestRegistry>>")]
+            optional: Option<Arc<dyn TestTrait>>,
+        }
+
+        let json = r#"{"list":[{"id":"A","data":{"name":"x"}}],"optional":{"id":"C","data":null}}"#;
+        let wrapper: Wrapper = serde_json::from_str(json).unwrap();
+        assert_eq!(wrapper.list[0].name(), "x");
+        assert_eq!(wrapper.optional.as_ref().unwrap().name(), "just a c");
+        assert_eq!(serde_json::to_string(&wrapper).unwrap(), json);
+    }
+}
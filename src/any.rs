@@ -0,0 +1,432 @@
+//! Representation of keyed values like the JSON mapping of protobuf's `Any`.
+//!
+//! The key is a type URL written in an `@type` field next to the fields of
+//! the value, like the internally tagged representation in
+//! [`internal`](crate::internal):
+//!
+//! ```json
+//! {"@type": "type.example.com/shapes.Circle", "radius": 2.0}
+//! ```
+//!
+//! Well-known types that protobuf maps to something other than a JSON object,
+//! like `google.protobuf.Duration` or the wrappers like
+//! `google.protobuf.StringValue`, are instead written in a `value` field:
+//!
+//! ```json
+//! {"@type": "type.googleapis.com/google.protobuf.Duration", "value": "1.5s"}
+//! ```
+//!
+//! Other values that aren't structs or maps can't be written this way and
+//! will return an error.
+//!
+//! A [`Registry`] with [`TypeUrl`] keys can use this representation with
+//! [`Keyed<ProtoAny<R>>`](crate::Keyed). Registries will usually resolve type
+//! URLs by their [name](TypeUrl::name) alone, since the prefix is only
+//! informational.
+//!
+//! ```
+//! # mod outer {
+//! use keyedes::any::ProtoAny;
+//! use keyedes::Keyed;
+//! use serde::{Deserialize, Serialize};
+//!
+//! trait Message: erased_serde::Serialize {}
+//! # struct Messages;
+//! # impl keyedes::Registry for Messages {
+//! #     type Object = dyn Message;
+//! #     type Key = keyedes::any::TypeUrl;
+//! #     const TYPE_NAME: &'static str = "Box<dyn Message>";
+//! #     const FIELD_NAMES: &'static [&'static str; 2] = &["@type", "value"];
+//! #     fn key(_: &dyn Message) -> keyedes::any::TypeUrl {
+//! #         keyedes::any::TypeUrl::new("type.example.com", "Message")
+//! #     }
+//! #     fn deserialize(
+//! #         _: keyedes::any::TypeUrl,
+//! #         _: &mut dyn erased_serde::Deserializer,
+//! #     ) -> Result<Box<dyn Message>, keyedes::Error> {
+//! #         Err(keyedes::unknown_key())
+//! #     }
+//! # }
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Envelope {
+//!     #[serde(with = "Keyed::<ProtoAny<Messages>>")]
+//!     details: Vec<Box<dyn Message>>,
+//! }
+//! # }
+//! ```
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use serde::de::{Error as _, Unexpected, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_value::{Value, ValueDeserializer};
+
+use crate::adapters::Representation;
+use crate::internal::{
+    deserialize_tagged, serialize_tagged, FieldsDeserializer, FieldsMap, TaggedFields,
+};
+use crate::private::ErasedSerdeSerializeWrapper;
+use crate::{Error, Registry};
+
+/// The type URL prefix used for types published by Google.
+pub const GOOGLE_APIS_PREFIX: &str = "type.googleapis.com";
+
+const TYPE_FIELD: &str = "@type";
+const VALUE_FIELD: &str = "value";
+
+/// The well-known types that have a JSON mapping other than an object.
+const WELL_KNOWN_TYPES: &[&str] = &[
+    "google.protobuf.Any",
+    "google.protobuf.BoolValue",
+    "google.protobuf.BytesValue",
+    "google.protobuf.DoubleValue",
+    "google.protobuf.Duration",
+    "google.protobuf.FieldMask",
+    "google.protobuf.FloatValue",
+    "google.protobuf.Int32Value",
+    "google.protobuf.Int64Value",
+    "google.protobuf.ListValue",
+    "google.protobuf.StringValue",
+    "google.protobuf.Struct",
+    "google.protobuf.Timestamp",
+    "google.protobuf.UInt32Value",
+    "google.protobuf.UInt64Value",
+    "google.protobuf.Value",
+];
+
+/// Returns whether values of the named type are written in a `value` field.
+#[must_use]
+pub fn is_well_known(name: &str) -> bool {
+    WELL_KNOWN_TYPES.contains(&name)
+}
+
+/// Marker for serializing objects of `R` like protobuf's `Any`.
+///
+/// See the [module documentation](self) for details.
+pub struct ProtoAny<R>(PhantomData<R>);
+
+/// A type URL split into its prefix and the fully qualified type name after
+/// the last `/`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TypeUrl {
+    pub prefix: String,
+    pub name: String,
+}
+
+impl TypeUrl {
+    #[must_use]
+    pub fn new(prefix: impl Into<String>, name: impl Into<String>) -> TypeUrl {
+        TypeUrl {
+            prefix: prefix.into(),
+            name: name.into(),
+        }
+    }
+
+    /// Splits a type URL at the last `/`.
+    ///
+    /// This will return an error if there is no `/` or nothing after it.
+    pub fn parse(url: &str) -> Result<TypeUrl, Error> {
+        match url.rfind('/') {
+            Some(index) if index + 1 < url.len() => {
+                Ok(TypeUrl::new(&url[..index], &url[index + 1..]))
+            }
+            _ => Err(Error::custom(format_args!("invalid type URL {:?}", url))),
+        }
+    }
+}
+
+impl fmt::Display for TypeUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.prefix, self.name)
+    }
+}
+
+impl FromStr for TypeUrl {
+    type Err = Error;
+
+    fn from_str(url: &str) -> Result<TypeUrl, Error> {
+        TypeUrl::parse(url)
+    }
+}
+
+impl Serialize for TypeUrl {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TypeUrl {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TypeUrlVisitor;
+
+        impl<'de> Visitor<'de> for TypeUrlVisitor {
+            type Value = TypeUrl;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("type URL")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                TypeUrl::parse(value)
+                    .map_err(|_| E::invalid_value(Unexpected::Str(value), &"a type URL"))
+            }
+        }
+
+        deserializer.deserialize_str(TypeUrlVisitor)
+    }
+}
+
+/// Will serialize the value with its type URL in an `@type` field.
+///
+/// Values of [well-known types](is_well_known) are written in a `value`
+/// field; others must be structs or maps and have their fields written next
+/// to the type URL.
+pub fn serialize_as_any<S, V>(
+    type_url: &TypeUrl,
+    value: &V,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    V: ?Sized + erased_serde::Serialize,
+    S: Serializer,
+{
+    if is_well_known(&type_url.name) {
+        let mut state = serializer.serialize_map(Some(2))?;
+        state.serialize_entry(TYPE_FIELD, type_url)?;
+        state.serialize_entry(VALUE_FIELD, &ErasedSerdeSerializeWrapper(value))?;
+        state.end()
+    } else {
+        serialize_tagged(TYPE_FIELD, type_url, None, value, serializer)
+    }
+}
+
+/// Will deserialize a value with its type URL in an `@type` field.
+///
+/// The function `f` will be called with the parsed type URL and a
+/// deserializer for the value, which is taken from the `value` field for
+/// [well-known types](is_well_known) or from the other fields otherwise.
+pub fn deserialize_from_any<'de, D, V, F>(f: F, deserializer: D) -> Result<V, D::Error>
+where
+    D: Deserializer<'de>,
+    F: Fn(TypeUrl, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+{
+    let TaggedFields { key, fields }: TaggedFields<_, Vec<_>> =
+        deserialize_tagged(TYPE_FIELD, deserializer)?;
+    let type_url: TypeUrl =
+        key.ok_or_else(|| D::Error::custom(format_args!("missing field `{}`", TYPE_FIELD)))?;
+
+    if is_well_known(&type_url.name) {
+        let mut value = None;
+        for (field, field_value) in fields {
+            match field {
+                Value::String(ref name) if name == VALUE_FIELD => {
+                    if value.replace(field_value).is_some() {
+                        return Err(D::Error::duplicate_field(VALUE_FIELD));
+                    }
+                }
+                field => {
+                    return Err(D::Error::custom(format_args!(
+                        "unexpected field {:?} next to `{}` of a well-known type",
+                        field, VALUE_FIELD
+                    )))
+                }
+            }
+        }
+        let value = value.ok_or_else(|| D::Error::missing_field(VALUE_FIELD))?;
+        f(
+            type_url,
+            &mut <dyn erased_serde::Deserializer>::erase(ValueDeserializer::<D::Error>::new(value)),
+        )
+    } else {
+        f(
+            type_url,
+            &mut <dyn erased_serde::Deserializer>::erase(
+                FieldsDeserializer::<FieldsMap<D::Error>>::in_order(fields),
+            ),
+        )
+    }
+    .map_err(D::Error::custom)
+}
+
+impl<R> Representation for ProtoAny<R>
+where
+    R: Registry<Key = TypeUrl>,
+{
+    type Object = R::Object;
+
+    fn serialize_object<S>(object: &R::Object, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_as_any(&R::key(object), object, serializer)
+    }
+
+    fn deserialize_object<'de, D>(deserializer: D) -> Result<Box<R::Object>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_from_any(R::deserialize, deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use once_cell::sync::Lazy;
+
+    use crate::{deserialize_into_boxed_trait, DesFnSync, Keyed};
+
+    trait Message: erased_serde::Serialize {
+        fn type_url(&self) -> TypeUrl;
+        fn describe(&self) -> String;
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Circle {
+        radius: f64,
+    }
+
+    impl Message for Circle {
+        fn type_url(&self) -> TypeUrl {
+            TypeUrl::new("type.example.com", "shapes.Circle")
+        }
+
+        fn describe(&self) -> String {
+            format!("circle {}", self.radius)
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct StringValue(String);
+
+    impl Message for StringValue {
+        fn type_url(&self) -> TypeUrl {
+            TypeUrl::new(GOOGLE_APIS_PREFIX, "google.protobuf.StringValue")
+        }
+
+        fn describe(&self) -> String {
+            format!("string {}", self.0)
+        }
+    }
+
+    static MAP: Lazy<HashMap<&'static str, DesFnSync<Box<dyn Message>>>> = Lazy::new(|| {
+        let mut map = HashMap::<&'static str, DesFnSync<Box<dyn Message>>>::new();
+        map.insert("shapes.Circle", deserialize_into_boxed_trait!(Circle));
+        map.insert(
+            "google.protobuf.StringValue",
+            deserialize_into_boxed_trait!(StringValue),
+        );
+        map
+    });
+
+    struct Messages;
+
+    impl Registry for Messages {
+        type Object = dyn Message;
+        type Key = TypeUrl;
+
+        const TYPE_NAME: &'static str = "Box<dyn Message>";
+        const FIELD_NAMES: &'static [&'static str; 2] = &["@type", "value"];
+
+        fn key(object: &dyn Message) -> TypeUrl {
+            object.type_url()
+        }
+
+        fn deserialize(
+            key: TypeUrl,
+            deserializer: &mut dyn erased_serde::Deserializer,
+        ) -> Result<Box<dyn Message>, Error> {
+            MAP.get(key.name.as_str())
+                .ok_or_else(crate::unknown_key)
+                .and_then(|f| f(deserializer))
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Details {
+        #[serde(with = "Keyed::<ProtoAny<Messages>>")]
+        details: Vec<Box<dyn Message>>,
+    }
+
+    fn describe(json: &str) -> Result<Vec<String>, serde_json::Error> {
+        let details: Details = serde_json::from_str(json)?;
+        Ok(details.details.iter().map(|m| m.describe()).collect())
+    }
+
+    #[test]
+    fn any_values_round_trip() {
+        let details = Details {
+            details: vec![
+                Box::new(Circle { radius: 2.5 }),
+                Box::new(StringValue("hi".to_string())),
+            ],
+        };
+
+        let json = serde_json::to_string(&details).unwrap();
+        assert_eq!(
+            json,
+            r#"{"details":[{"@type":"type.example.com/shapes.Circle","radius":2.5},{"@type":"type.googleapis.com/google.protobuf.StringValue","value":"hi"}]}"#
+        );
+        assert_eq!(describe(&json).unwrap(), ["circle 2.5", "string hi"]);
+
+        let json = r#"{"details":[{"radius":1.0,"@type":"other.prefix/shapes.Circle"}]}"#;
+        assert_eq!(describe(json).unwrap(), ["circle 1"]);
+    }
+
+    #[test]
+    fn any_values_return_errors() {
+        let error = describe(r#"{"details":[{"@type":"shapes.Circle","radius":1.0}]}"#)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("expected a type URL"));
+
+        let error = describe(r#"{"details":[{"radius":1.0}]}"#).err().unwrap();
+        assert!(error.to_string().contains("missing field `@type`"));
+
+        let json = r#"{"details":[{"@type":"a/google.protobuf.StringValue","value":"hi","x":1}]}"#;
+        let error = describe(json).err().unwrap();
+        assert!(error.to_string().contains("unexpected field"));
+
+        let json =
+            r#"{"details":[{"@type":"a/google.protobuf.StringValue","value":"a","value":"b"}]}"#;
+        let error = describe(json).err().unwrap();
+        assert!(error.to_string().contains("duplicate field `value`"));
+
+        let json = r#"{"details":[{"@type":"a/shapes.Circle","radius":1.0,"radius":2.0}]}"#;
+        let error = describe(json).err().unwrap();
+        assert!(error.to_string().contains("duplicate field `radius`"));
+
+        let json = r#"{"details":[{"@type":"a/shapes.Square","size":1.0}]}"#;
+        let error = describe(json).err().unwrap();
+        assert!(error.to_string().contains("unknown deserialization key"));
+    }
+
+    #[test]
+    fn type_urls_are_split_at_the_last_slash() {
+        let url = TypeUrl::parse("example.com/a/b/pkg.Msg").unwrap();
+        assert_eq!(url, TypeUrl::new("example.com/a/b", "pkg.Msg"));
+        assert_eq!(url.to_string(), "example.com/a/b/pkg.Msg");
+
+        let url = TypeUrl::parse("/pkg.Msg").unwrap();
+        assert_eq!(url, TypeUrl::new("", "pkg.Msg"));
+
+        assert!(TypeUrl::parse("pkg.Msg").is_err());
+        assert!(TypeUrl::parse("example.com/").is_err());
+    }
+}
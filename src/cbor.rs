@@ -0,0 +1,352 @@
+//! Representation of keyed values as CBOR semantic tags.
+//!
+//! Instead of a struct with key and value fields, a numeric key is written as
+//! the tag number of a CBOR tagged item with the value as its content. For
+//! example, a value with key `1000` is encoded as `tag(1000, {...})`, which
+//! is written in CBOR diagnostic notation as:
+//!
+//! ```text
+//! 1000({"radius": 2.0})
+//! ```
+//!
+//! This uses the same convention as `ciborium` for tags: a tuple variant
+//! named `"@@TAGGED@@"` of an enum named `"@@TAG@@"`. Other formats will
+//! write that enum as-is, so this representation is only really useful for
+//! CBOR.
+//!
+//! A [`Registry`] with `u64` keys can use this representation with
+//! [`Keyed<Tagged<R>>`](crate::Keyed):
+//!
+//! ```
+//! # mod outer {
+//! use keyedes::cbor::Tagged;
+//! use keyedes::Keyed;
+//! use serde::{Deserialize, Serialize};
+//!
+//! trait Shape: erased_serde::Serialize {}
+//! # struct Shapes;
+//! # impl keyedes::Registry for Shapes {
+//! #     type Object = dyn Shape;
+//! #     type Key = u64;
+//! #     const TYPE_NAME: &'static str = "Box<dyn Shape>";
+//! #     const FIELD_NAMES: &'static [&'static str; 2] = &["id", "data"];
+//! #     fn key(_: &dyn Shape) -> u64 { 0 }
+//! #     fn deserialize(
+//! #         _: u64,
+//! #         _: &mut dyn erased_serde::Deserializer,
+//! #     ) -> Result<Box<dyn Shape>, keyedes::Error> {
+//! #         Err(keyedes::unknown_key())
+//! #     }
+//! # }
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Drawing {
+//!     #[serde(with = "Keyed::<Tagged<Shapes>>")]
+//!     shapes: Vec<Box<dyn Shape>>,
+//! }
+//! # }
+//! ```
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{DeserializeSeed, EnumAccess, Error as _, SeqAccess, VariantAccess, Visitor};
+use serde::ser::SerializeTupleVariant;
+use serde::{Deserializer, Serializer};
+
+use crate::adapters::Representation;
+use crate::private::{ErasedSerdeSerializeWrapper, ValueDeserializeSeed};
+use crate::{Error, Registry};
+
+const TYPE_NAME: &str = "@@TAG@@";
+const VARIANTS: &[&str] = &["@@TAGGED@@", "@@UNTAGGED@@"];
+
+/// Marker for serializing objects of `R` as CBOR tags, using their keys as
+/// the tag numbers.
+///
+/// See the [module documentation](self) for details.
+pub struct Tagged<R>(PhantomData<R>);
+
+/// Will serialize the value as a CBOR item tagged with the key.
+pub fn serialize_as_tag<S, V>(tag: u64, value: &V, serializer: S) -> Result<S::Ok, S::Error>
+where
+    V: ?Sized + erased_serde::Serialize,
+    S: Serializer,
+{
+    let mut state = serializer.serialize_tuple_variant(TYPE_NAME, 0, VARIANTS[0], 2)?;
+    state.serialize_field(&tag)?;
+    state.serialize_field(&ErasedSerdeSerializeWrapper(value))?;
+    state.end()
+}
+
+/// Will deserialize a CBOR tagged item, using the tag number as the key.
+///
+/// The function `f` will be called with the key and a deserializer for the
+/// tagged content that can be used to get the final value. Items without a
+/// tag will return an error.
+pub fn deserialize_from_tag<'de, D, V, F>(f: F, deserializer: D) -> Result<V, D::Error>
+where
+    D: Deserializer<'de>,
+    F: Fn(u64, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+{
+    deserializer.deserialize_enum(
+        TYPE_NAME,
+        VARIANTS,
+        TagVisitor {
+            deserialization_fn: f,
+            _dummy: PhantomData,
+        },
+    )
+}
+
+struct TagVisitor<F, V> {
+    deserialization_fn: F,
+    _dummy: PhantomData<fn() -> V>,
+}
+
+impl<'de, F, V> Visitor<'de> for TagVisitor<F, V>
+where
+    F: Fn(u64, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+{
+    type Value = V;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("tagged item")
+    }
+
+    fn visit_enum<A>(self, data: A) -> Result<Self::Value, A::Error>
+    where
+        A: EnumAccess<'de>,
+    {
+        match data.variant_seed(TaggedSeed)? {
+            (true, variant) => variant.tuple_variant(2, self),
+            (false, _) => Err(A::Error::custom("missing tag")),
+        }
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let tag: u64 = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+
+        seq.next_element_seed(ValueDeserializeSeed {
+            field: tag,
+            deserialization_fn: &self.deserialization_fn,
+            _dummy: PhantomData,
+        })?
+        .ok_or_else(|| A::Error::invalid_length(1, &self))
+    }
+}
+
+/// Identifies whether the item has a tag.
+struct TaggedSeed;
+
+impl<'de> DeserializeSeed<'de> for TaggedSeed {
+    type Value = bool;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_identifier(self)
+    }
+}
+
+impl<'de> Visitor<'de> for TaggedSeed {
+    type Value = bool;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("tag identifier")
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        match value {
+            0 => Ok(true),
+            1 => Ok(false),
+            _ => Err(E::invalid_value(
+                serde::de::Unexpected::Unsigned(value),
+                &"variant index 0 <= i < 2",
+            )),
+        }
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        match value {
+            "@@TAGGED@@" => Ok(true),
+            "@@UNTAGGED@@" => Ok(false),
+            _ => Err(E::unknown_variant(value, VARIANTS)),
+        }
+    }
+}
+
+impl<R> Representation for Tagged<R>
+where
+    R: Registry<Key = u64>,
+{
+    type Object = R::Object;
+
+    fn serialize_object<S>(object: &R::Object, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_as_tag(R::key(object), object, serializer)
+    }
+
+    fn deserialize_object<'de, D>(deserializer: D) -> Result<Box<R::Object>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_from_tag(R::deserialize, deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use once_cell::sync::Lazy;
+    use serde::{Deserialize, Serialize};
+
+    use crate::{deserialize_into_boxed_trait, DesFnSync, Keyed};
+
+    trait TestTrait: erased_serde::Serialize {
+        fn key(&self) -> u64;
+        fn name(&self) -> String;
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct TestStructA {
+        name: String,
+    }
+
+    impl TestTrait for TestStructA {
+        fn key(&self) -> u64 {
+            1000
+        }
+
+        fn name(&self) -> String {
+            self.name.clone()
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct TestStructB(String);
+
+    impl TestTrait for TestStructB {
+        fn key(&self) -> u64 {
+            7
+        }
+
+        fn name(&self) -> String {
+            self.0.clone()
+        }
+    }
+
+    static MAP: Lazy<HashMap<u64, DesFnSync<Box<dyn TestTrait>>>> = Lazy::new(|| {
+        let mut map = HashMap::<u64, DesFnSync<Box<dyn TestTrait>>>::new();
+        map.insert(1000, deserialize_into_boxed_trait!(TestStructA));
+        map.insert(7, deserialize_into_boxed_trait!(TestStructB));
+        map
+    });
+
+    struct TestRegistry;
+
+    impl Registry for TestRegistry {
+        type Object = dyn TestTrait;
+        type Key = u64;
+
+        const TYPE_NAME: &'static str = "Box<dyn TestTrait>";
+        const FIELD_NAMES: &'static [&'static str; 2] = &["id", "data"];
+
+        fn key(object: &dyn TestTrait) -> u64 {
+            object.key()
+        }
+
+        fn deserialize(
+            key: u64,
+            deserializer: &mut dyn erased_serde::Deserializer,
+        ) -> Result<Box<dyn TestTrait>, Error> {
+            MAP.get(&key)
+                .ok_or_else(crate::unknown_key)
+                .and_then(|f| f(deserializer))
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Container {
+        #[serde(with = "Keyed::<Tagged<TestRegistry>>")]
+        items: Vec<Box<dyn TestTrait>>,
+        #[serde(with = "Keyed::<Tagged<TestRegistry>>")]
+        shared: Arc<dyn TestTrait>,
+    }
+
+    #[test]
+    fn tagged_values_round_trip_through_cbor() {
+        let container = Container {
+            items: vec![
+                Box::new(TestStructA {
+                    name: "chuck norris".to_string(),
+                }),
+                Box::new(TestStructB("Broccoli".to_string())),
+            ],
+            shared: Arc::new(TestStructB("Pizza".to_string())),
+        };
+
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&container, &mut bytes).unwrap();
+
+        let value: ciborium::Value = ciborium::from_reader(&bytes[..]).unwrap();
+        let items = value.as_map().unwrap()[0].1.as_array().unwrap();
+        assert_eq!(items[0].as_tag().unwrap().0, 1000);
+        assert_eq!(items[1].as_tag().unwrap().0, 7);
+        assert_eq!(
+            items[1].as_tag().unwrap().1,
+            &ciborium::Value::Text("Broccoli".to_string())
+        );
+
+        let container: Container = ciborium::from_reader(&bytes[..]).unwrap();
+        assert_eq!(container.items[0].name(), "chuck norris");
+        assert_eq!(container.items[1].name(), "Broccoli");
+        assert_eq!(container.shared.name(), "Pizza");
+    }
+
+    #[test]
+    fn tagged_values_return_error_for_unknown_or_missing_tags() {
+        #[derive(Deserialize)]
+        struct Single(#[serde(with = "Keyed::<Tagged<TestRegistry>>")] Box<dyn TestTrait>);
+
+        let known = ciborium::Value::Tag(7, Box::new(ciborium::Value::Text("Pizza".to_string())));
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&known, &mut bytes).unwrap();
+        let single = ciborium::from_reader::<Single, _>(&bytes[..]).unwrap();
+        assert_eq!(single.0.name(), "Pizza");
+
+        let unknown = ciborium::Value::Tag(3, Box::new(ciborium::Value::Null));
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&unknown, &mut bytes).unwrap();
+        let error = ciborium::from_reader::<Single, _>(&bytes[..])
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("unknown deserialization key"));
+
+        let untagged = ciborium::Value::Text("Broccoli".to_string());
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&untagged, &mut bytes).unwrap();
+        let error = ciborium::from_reader::<Single, _>(&bytes[..])
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("missing tag"));
+    }
+}
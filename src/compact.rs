@@ -0,0 +1,404 @@
+//! Keys written as strings or compact numeric ids depending on the format.
+//!
+//! A [`CompactKey`] is written as its name by human-readable formats like
+//! JSON or YAML and as a small numeric id by the others, like `bincode` or
+//! `postcard`, as reported by
+//! [`Serializer::is_human_readable()`](serde::Serializer::is_human_readable).
+//! Human-readable formats accept either form when reading.
+//!
+//! The ids come from a [`KeyTable`] that should never reassign an id once it
+//! has been used, since ids are all that binary formats store. Using
+//! `CompactKey` as the key of a [`Registry`](crate::Registry), or passing it
+//! to [`serialize_with_key()`](crate::serialize_with_key) and
+//! [`deserialize_by_key()`](crate::deserialize_by_key), will pick the right
+//! form automatically.
+//!
+//! ```
+//! use keyedes::compact::{CompactKey, CompactKeys, KeyTable};
+//! use once_cell::sync::Lazy;
+//!
+//! struct ShapeKeys;
+//!
+//! static SHAPE_KEYS: Lazy<KeyTable> =
+//!     Lazy::new(|| KeyTable::new(&[("circle", 1), ("square", 2)]).unwrap());
+//!
+//! impl CompactKeys for ShapeKeys {
+//!     fn table() -> &'static KeyTable {
+//!         &SHAPE_KEYS
+//!     }
+//! }
+//!
+//! let key = CompactKey::<ShapeKeys>::new("square").unwrap();
+//! assert_eq!(serde_json::to_string(&key).unwrap(), r#""square""#);
+//! assert_eq!(bincode::serialize(&key).unwrap(), [2, 0, 0, 0]);
+//! ```
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+use serde::de::{Error as _, Unexpected, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::Error;
+
+/// A stable mapping between key names and numeric ids.
+#[derive(Clone, Debug, Default)]
+pub struct KeyTable {
+    ids: HashMap<&'static str, u32>,
+    names: HashMap<u32, &'static str>,
+}
+
+impl KeyTable {
+    /// Creates a table from pairs of names and ids.
+    ///
+    /// This will return an error if a name or id appears more than once.
+    pub fn new(entries: &[(&'static str, u32)]) -> Result<KeyTable, Error> {
+        let mut table = KeyTable::default();
+        for &(name, id) in entries {
+            table.insert(name, id)?;
+        }
+        Ok(table)
+    }
+
+    /// Adds a name with its id to the table.
+    ///
+    /// This will return an error if either is already in the table.
+    pub fn insert(&mut self, name: &'static str, id: u32) -> Result<(), Error> {
+        if let Some(existing) = self.ids.get(name) {
+            return Err(Error::custom(format_args!(
+                "key {:?} is assigned to both id {} and id {}",
+                name, existing, id
+            )));
+        }
+        if let Some(existing) = self.names.get(&id) {
+            return Err(Error::custom(format_args!(
+                "id {} is assigned to both key {:?} and key {:?}",
+                id, existing, name
+            )));
+        }
+
+        self.ids.insert(name, id);
+        self.names.insert(id, name);
+        Ok(())
+    }
+
+    /// Returns the id for the name, if it is in the table.
+    #[must_use]
+    pub fn id(&self, name: &str) -> Option<u32> {
+        self.ids.get(name).copied()
+    }
+
+    /// Returns the name for the id, if it is in the table.
+    #[must_use]
+    pub fn name(&self, id: u32) -> Option<&'static str> {
+        self.names.get(&id).copied()
+    }
+
+    /// Returns the name in the table that is equal to `name`.
+    fn find(&self, name: &str) -> Option<&'static str> {
+        self.ids.get_key_value(name).map(|(name, _)| *name)
+    }
+}
+
+/// Provides the [`KeyTable`] for a [`CompactKey`].
+///
+/// This is usually implemented on a unit struct, possibly the same as the
+/// [`Registry`](crate::Registry), returning a table from a static.
+pub trait CompactKeys {
+    fn table() -> &'static KeyTable;
+}
+
+/// A key from the table of `T`, written as a name or an id depending on the
+/// format.
+///
+/// See the [module documentation](self) for details.
+pub struct CompactKey<T> {
+    name: &'static str,
+    _table: PhantomData<fn() -> T>,
+}
+
+impl<T: CompactKeys> CompactKey<T> {
+    /// Returns the key with the given name, or `None` if it isn't in the
+    /// table.
+    #[must_use]
+    pub fn new(name: &str) -> Option<CompactKey<T>> {
+        T::table().find(name).map(CompactKey::from_static)
+    }
+
+    /// Returns the key with the given id, or `None` if it isn't in the table.
+    #[must_use]
+    pub fn from_id(id: u32) -> Option<CompactKey<T>> {
+        T::table().name(id).map(CompactKey::from_static)
+    }
+
+    fn from_static(name: &'static str) -> CompactKey<T> {
+        CompactKey {
+            name,
+            _table: PhantomData,
+        }
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    #[must_use]
+    pub fn id(&self) -> u32 {
+        // keys are only created from names in the table
+        T::table().id(self.name).unwrap()
+    }
+}
+
+impl<T> Clone for CompactKey<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for CompactKey<T> {}
+
+impl<T> PartialEq for CompactKey<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl<T> Eq for CompactKey<T> {}
+
+impl<T> Hash for CompactKey<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for CompactKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.name, f)
+    }
+}
+
+impl<T> fmt::Display for CompactKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+impl<T: CompactKeys> Serialize for CompactKey<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(self.name)
+        } else {
+            serializer.serialize_u32(self.id())
+        }
+    }
+}
+
+impl<'de, T: CompactKeys> Deserialize<'de> for CompactKey<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(CompactKeyVisitor(PhantomData))
+        } else {
+            deserializer.deserialize_u32(CompactKeyVisitor(PhantomData))
+        }
+    }
+}
+
+struct CompactKeyVisitor<T>(PhantomData<fn() -> T>);
+
+impl<'de, T: CompactKeys> Visitor<'de> for CompactKeyVisitor<T> {
+    type Value = CompactKey<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("key name or id")
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        u32::try_from(value)
+            .ok()
+            .and_then(CompactKey::from_id)
+            .ok_or_else(|| E::invalid_value(Unexpected::Unsigned(value), &"a known key id"))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        u32::try_from(value)
+            .ok()
+            .and_then(CompactKey::from_id)
+            .ok_or_else(|| E::invalid_value(Unexpected::Signed(value), &"a known key id"))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        CompactKey::new(value)
+            .ok_or_else(|| E::invalid_value(Unexpected::Str(value), &"a known key name"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use once_cell::sync::Lazy;
+
+    use crate::{deserialize_into_boxed_trait, DesFnSync, Keyed, Registry};
+
+    trait TestTrait: erased_serde::Serialize {
+        fn key(&self) -> &'static str;
+        fn name(&self) -> String;
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct TestStructA {
+        name: String,
+    }
+
+    impl TestTrait for TestStructA {
+        fn key(&self) -> &'static str {
+            "A"
+        }
+
+        fn name(&self) -> String {
+            self.name.clone()
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct TestStructB(String);
+
+    impl TestTrait for TestStructB {
+        fn key(&self) -> &'static str {
+            "B"
+        }
+
+        fn name(&self) -> String {
+            self.0.clone()
+        }
+    }
+
+    static KEYS: Lazy<KeyTable> = Lazy::new(|| KeyTable::new(&[("A", 1), ("B", 2)]).unwrap());
+
+    static MAP: Lazy<HashMap<&'static str, DesFnSync<Box<dyn TestTrait>>>> = Lazy::new(|| {
+        let mut map = HashMap::<&'static str, DesFnSync<Box<dyn TestTrait>>>::new();
+        map.insert("A", deserialize_into_boxed_trait!(TestStructA));
+        map.insert("B", deserialize_into_boxed_trait!(TestStructB));
+        map
+    });
+
+    struct TestRegistry;
+
+    impl CompactKeys for TestRegistry {
+        fn table() -> &'static KeyTable {
+            &KEYS
+        }
+    }
+
+    impl Registry for TestRegistry {
+        type Object = dyn TestTrait;
+        type Key = CompactKey<TestRegistry>;
+
+        const TYPE_NAME: &'static str = "Box<dyn TestTrait>";
+        const FIELD_NAMES: &'static [&'static str; 2] = &["id", "data"];
+
+        fn key(object: &dyn TestTrait) -> CompactKey<TestRegistry> {
+            CompactKey::new(object.key()).unwrap()
+        }
+
+        fn deserialize(
+            key: CompactKey<TestRegistry>,
+            deserializer: &mut dyn erased_serde::Deserializer,
+        ) -> Result<Box<dyn TestTrait>, Error> {
+            MAP.get(key.name())
+                .ok_or_else(crate::unknown_key)
+                .and_then(|f| f(deserializer))
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Container {
+        #[serde(with = "Keyed::<TestRegistry>")]
+        items: Vec<Box<dyn TestTrait>>,
+    }
+
+    fn test_container() -> Container {
+        Container {
+            items: vec![
+                Box::new(TestStructA {
+                    name: "chuck norris".to_string(),
+                }),
+                Box::new(TestStructB("Broccoli".to_string())),
+            ],
+        }
+    }
+
+    fn names(container: &Container) -> Vec<String> {
+        container.items.iter().map(|item| item.name()).collect()
+    }
+
+    #[test]
+    fn compact_keys_depend_on_format() {
+        let json = serde_json::to_string(&test_container()).unwrap();
+        assert_eq!(
+            json,
+            r#"{"items":[{"id":"A","data":{"name":"chuck norris"}},{"id":"B","data":"Broccoli"}]}"#
+        );
+        let container: Container = serde_json::from_str(&json).unwrap();
+        assert_eq!(names(&container), ["chuck norris", "Broccoli"]);
+
+        let json =
+            r#"{"items":[{"id":1,"data":{"name":"chuck norris"}},{"id":"B","data":"Broccoli"}]}"#;
+        let container: Container = serde_json::from_str(json).unwrap();
+        assert_eq!(names(&container), ["chuck norris", "Broccoli"]);
+
+        let bytes = postcard::to_stdvec(&test_container()).unwrap();
+        assert_eq!(&bytes[..2], [2, 1]);
+        let container: Container = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(names(&container), ["chuck norris", "Broccoli"]);
+
+        let bytes = bincode::serialize(&test_container()).unwrap();
+        let container: Container = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(names(&container), ["chuck norris", "Broccoli"]);
+    }
+
+    #[test]
+    fn compact_keys_reject_unknown_names_and_ids() {
+        let json = r#"{"items":[{"id":3,"data":null}]}"#;
+        let error = serde_json::from_str::<Container>(json).err().unwrap();
+        assert!(error.to_string().contains("expected a known key id"));
+
+        let json = r#"{"items":[{"id":"C","data":null}]}"#;
+        let error = serde_json::from_str::<Container>(json).err().unwrap();
+        assert!(error.to_string().contains("expected a known key name"));
+    }
+
+    #[test]
+    fn key_tables_reject_collisions() {
+        let error = KeyTable::new(&[("A", 1), ("B", 1)]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            r#"id 1 is assigned to both key "A" and key "B""#
+        );
+
+        let error = KeyTable::new(&[("A", 1), ("A", 2)]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            r#"key "A" is assigned to both id 1 and id 2"#
+        );
+    }
+}
@@ -0,0 +1,1088 @@
+//! Representations compatible with the wire formats of the
+//! [`typetag`](https://crates.io/crates/typetag) crate.
+//!
+//! This lets data written by `typetag` be read with a [`Registry`] and the
+//! other way around, so trait objects can be moved from one to the other
+//! without migrating the data. The key of the registry is what `typetag`
+//! calls the name of the type, usually the name of the Rust type.
+//!
+//! With `#[typetag::serde]`, values are externally tagged, written as a map
+//! with the key as its only entry: `{"Circle": {"radius": 2.0}}`. Use
+//! [`Keyed<External<R>>`](crate::Keyed) for this.
+//!
+//! With `#[typetag::serde(tag = "type")]`, values are internally tagged. Use
+//! [`Keyed<Internal<R>>`](crate::Keyed), which takes the tag from the first
+//! of the [`FIELD_NAMES`](Registry::FIELD_NAMES). The value is written next to
+//! the key depending on what it is:
+//!
+//! | value                       | written as                              |
+//! |-----------------------------|-----------------------------------------|
+//! | struct or map               | `{"type": "A", "x": 1, "y": 2}`         |
+//! | unit, unit struct or `None` | `{"type": "A"}`                         |
+//! | enum variant                | `{"type": "A", "Variant": payload}`     |
+//! | anything else               | `{"type": "A", "value": value}`         |
+//!
+//! Newtype structs are written as what they contain. Reading internally
+//! tagged values buffers the fields, so it requires a self-describing format.
+//!
+//! With `#[typetag::serde(tag = "type", content = "value")]`, values are
+//! adjacently tagged, which is what [`Keyed<R>`](crate::Keyed) already does
+//! when the field names of `R` are the tag and the content.
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{Error as _, MapAccess, Visitor};
+use serde::ser::{
+    Impossible, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant,
+    SerializeTuple, SerializeTupleStruct, SerializeTupleVariant,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_value::{Value, ValueDeserializer};
+
+use crate::adapters::Representation;
+use crate::internal::{deserialize_tagged, fields_map, FieldsMap, TaggedFields};
+use crate::private::{ErasedSerdeSerializeWrapper, ValueDeserializeSeed};
+use crate::{Error, Registry};
+
+/// The field `typetag` writes internally tagged values to when they aren't
+/// structs, maps, units or enums.
+const VALUE_FIELD: &str = "value";
+
+/// Marker for serializing objects of `R` like `typetag` does by default.
+///
+/// See the [module documentation](self) for details.
+pub struct External<R>(PhantomData<R>);
+
+/// Marker for serializing objects of `R` like `typetag` does with a `tag`.
+///
+/// See the [module documentation](self) for details.
+pub struct Internal<R>(PhantomData<R>);
+
+/// Will serialize a map with the key as its only entry.
+pub fn serialize_externally_tagged<S, K, V>(
+    key: &K,
+    value: &V,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    K: ?Sized + Serialize,
+    V: ?Sized + erased_serde::Serialize,
+    S: Serializer,
+{
+    let mut state = serializer.serialize_map(Some(1))?;
+    state.serialize_entry(key, &ErasedSerdeSerializeWrapper(value))?;
+    state.end()
+}
+
+/// Will deserialize a map with the key as its first entry.
+///
+/// The function `f` will be called with the deserialized key and a
+/// deserializer for the value of the entry.
+pub fn deserialize_externally_tagged<'de, D, K, V, F>(
+    type_name: &'static str,
+    f: F,
+    deserializer: D,
+) -> Result<V, D::Error>
+where
+    D: Deserializer<'de>,
+    K: Deserialize<'de>,
+    F: Fn(K, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+{
+    deserializer.deserialize_map(ExternalVisitor {
+        type_name,
+        deserialization_fn: f,
+        _dummy: PhantomData,
+    })
+}
+
+struct ExternalVisitor<F, K, V> {
+    type_name: &'static str,
+    deserialization_fn: F,
+    _dummy: PhantomData<fn(K) -> V>,
+}
+
+impl<'de, F, K, V> Visitor<'de> for ExternalVisitor<F, K, V>
+where
+    F: Fn(K, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+    K: Deserialize<'de>,
+{
+    type Value = V;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "externally tagged {}", self.type_name)
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let key = map.next_key()?.ok_or_else(|| {
+            A::Error::custom(format_args!(
+                "expected externally tagged {}",
+                self.type_name
+            ))
+        })?;
+        map.next_value_seed(ValueDeserializeSeed {
+            field: key,
+            deserialization_fn: &self.deserialization_fn,
+            _dummy: PhantomData,
+        })
+    }
+}
+
+/// Will serialize a map with the key in the `tag` field and the value next
+/// to it, as described in the [module documentation](self).
+pub fn serialize_internally_tagged<S, K, V>(
+    tag: &str,
+    key: &K,
+    value: &V,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    K: ?Sized + Serialize,
+    V: ?Sized + erased_serde::Serialize,
+    S: Serializer,
+{
+    let value = ErasedSerdeSerializeWrapper(value);
+    value.serialize(InternalSerializer {
+        tag,
+        key,
+        value: &value,
+        serializer,
+    })
+}
+
+/// Will deserialize a map with the key in the `tag` field and the value next
+/// to it, as described in the [module documentation](self).
+///
+/// The function `f` will be called with the deserialized key and a
+/// deserializer for the buffered fields that can be used to get the final
+/// value.
+pub fn deserialize_internally_tagged<'de, D, K, V, F>(
+    tag: &str,
+    f: F,
+    deserializer: D,
+) -> Result<V, D::Error>
+where
+    D: Deserializer<'de>,
+    K: Deserialize<'de>,
+    F: Fn(K, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+{
+    let TaggedFields { key, fields } = deserialize_tagged(tag, deserializer)?;
+    let key = key.ok_or_else(|| D::Error::custom(format_args!("missing field `{}`", tag)))?;
+
+    f(
+        key,
+        &mut <dyn erased_serde::Deserializer>::erase(InternalDeserializer::<D::Error> {
+            fields,
+            _error: PhantomData,
+        }),
+    )
+    .map_err(D::Error::custom)
+}
+
+/// Writes the tag and the value it's given, keeping the whole value around
+/// for those that can't be written as they are visited.
+struct InternalSerializer<'a, S, K: ?Sized, V: ?Sized> {
+    tag: &'a str,
+    key: &'a K,
+    value: &'a V,
+    serializer: S,
+}
+
+impl<'a, S, K, V> InternalSerializer<'a, S, K, V>
+where
+    S: Serializer,
+    K: ?Sized + Serialize,
+    V: ?Sized + Serialize,
+{
+    fn write_unit(self) -> Result<S::Ok, S::Error> {
+        let mut state = self.serializer.serialize_map(Some(1))?;
+        state.serialize_entry(self.tag, self.key)?;
+        state.end()
+    }
+
+    fn write_entry<T>(self, field: &str, value: &T) -> Result<S::Ok, S::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        let mut state = self.serializer.serialize_map(Some(2))?;
+        state.serialize_entry(self.tag, self.key)?;
+        state.serialize_entry(field, value)?;
+        state.end()
+    }
+
+    fn write_value<T>(self, value: &T) -> Result<S::Ok, S::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.write_entry(VALUE_FIELD, value)
+    }
+
+    fn rewrite(self, variant: Option<&'static str>) -> Rewrite<'a, S, K, V> {
+        Rewrite {
+            serializer: self,
+            variant,
+        }
+    }
+}
+
+macro_rules! write_value {
+    ($($method:ident($ty:ty))*) => {
+        $(
+            fn $method(self, v: $ty) -> Result<S::Ok, S::Error> {
+                self.write_value(&v)
+            }
+        )*
+    };
+}
+
+impl<'a, S, K, V> Serializer for InternalSerializer<'a, S, K, V>
+where
+    S: Serializer,
+    K: ?Sized + Serialize,
+    V: ?Sized + Serialize,
+{
+    type Ok = S::Ok;
+    type Error = S::Error;
+    type SerializeSeq = Rewrite<'a, S, K, V>;
+    type SerializeTuple = Rewrite<'a, S, K, V>;
+    type SerializeTupleStruct = Rewrite<'a, S, K, V>;
+    type SerializeTupleVariant = Rewrite<'a, S, K, V>;
+    type SerializeMap = S::SerializeMap;
+    type SerializeStruct = StructAsMap<S::SerializeMap>;
+    type SerializeStructVariant = Rewrite<'a, S, K, V>;
+
+    write_value! {
+        serialize_bool(bool)
+        serialize_i8(i8)
+        serialize_i16(i16)
+        serialize_i32(i32)
+        serialize_i64(i64)
+        serialize_i128(i128)
+        serialize_u8(u8)
+        serialize_u16(u16)
+        serialize_u32(u32)
+        serialize_u64(u64)
+        serialize_u128(u128)
+        serialize_f32(f32)
+        serialize_f64(f64)
+        serialize_char(char)
+        serialize_str(&str)
+        serialize_bytes(&[u8])
+    }
+
+    fn serialize_none(self) -> Result<S::Ok, S::Error> {
+        self.write_unit()
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<S::Ok, S::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.write_value(value)
+    }
+
+    fn serialize_unit(self) -> Result<S::Ok, S::Error> {
+        self.write_unit()
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<S::Ok, S::Error> {
+        self.write_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<S::Ok, S::Error> {
+        self.write_entry(variant, &())
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<S::Ok, S::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.write_entry(variant, value)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, S::Error> {
+        Ok(self.rewrite(None))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, S::Error> {
+        Ok(self.rewrite(None))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, S::Error> {
+        Ok(self.rewrite(None))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, S::Error> {
+        Ok(self.rewrite(Some(variant)))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<S::SerializeMap, S::Error> {
+        let mut state = self.serializer.serialize_map(len.map(|len| len + 1))?;
+        state.serialize_entry(self.tag, self.key)?;
+        Ok(state)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, S::Error> {
+        let mut state = self.serializer.serialize_map(Some(len + 1))?;
+        state.serialize_entry(self.tag, self.key)?;
+        Ok(StructAsMap(state))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, S::Error> {
+        Ok(self.rewrite(Some(variant)))
+    }
+}
+
+/// Writes the fields of a struct as entries of a map.
+struct StructAsMap<M>(M);
+
+impl<M> SerializeStruct for StructAsMap<M>
+where
+    M: SerializeMap,
+{
+    type Ok = M::Ok;
+    type Error = M::Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), M::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.0.serialize_entry(key, value)
+    }
+
+    fn end(self) -> Result<M::Ok, M::Error> {
+        self.0.end()
+    }
+}
+
+/// Skips the elements it's given and writes the whole value again as an
+/// entry once it ends, rather than buffering the elements.
+struct Rewrite<'a, S, K: ?Sized, V: ?Sized> {
+    serializer: InternalSerializer<'a, S, K, V>,
+    variant: Option<&'static str>,
+}
+
+impl<'a, S, K, V> Rewrite<'a, S, K, V>
+where
+    S: Serializer,
+    K: ?Sized + Serialize,
+    V: ?Sized + Serialize,
+{
+    fn write(self) -> Result<S::Ok, S::Error> {
+        let value = self.serializer.value;
+        match self.variant {
+            Some(variant) => self.serializer.write_entry(variant, &VariantPayload(value)),
+            None => self.serializer.write_value(value),
+        }
+    }
+}
+
+macro_rules! impl_rewrite {
+    ($($trait:ident::$method:ident($($key:ty)?))*) => {
+        $(
+            impl<'a, S, K, V> $trait for Rewrite<'a, S, K, V>
+            where
+                S: Serializer,
+                K: ?Sized + Serialize,
+                V: ?Sized + Serialize,
+            {
+                type Ok = S::Ok;
+                type Error = S::Error;
+
+                fn $method<T>(&mut self, $(_key: $key,)? _value: &T) -> Result<(), S::Error>
+                where
+                    T: ?Sized + Serialize,
+                {
+                    Ok(())
+                }
+
+                fn end(self) -> Result<S::Ok, S::Error> {
+                    self.write()
+                }
+            }
+        )*
+    };
+}
+
+impl_rewrite! {
+    SerializeSeq::serialize_element()
+    SerializeTuple::serialize_element()
+    SerializeTupleStruct::serialize_field()
+    SerializeTupleVariant::serialize_field()
+    SerializeStructVariant::serialize_field(&'static str)
+}
+
+/// Serializes only the payload of an enum variant, with tuple and struct
+/// variants written as tuple structs and structs named after the variant.
+struct VariantPayload<'a, V: ?Sized>(&'a V);
+
+impl<'a, V> Serialize for VariantPayload<'a, V>
+where
+    V: ?Sized + Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.0.serialize(PayloadSerializer(serializer))
+    }
+}
+
+struct PayloadSerializer<S>(S);
+
+fn not_a_variant<E: serde::ser::Error>() -> E {
+    E::custom("expected an enum variant")
+}
+
+macro_rules! not_a_variant {
+    ($($method:ident($($arg:ty),*))*) => {
+        $(
+            fn $method(self, $(_: $arg),*) -> Result<S::Ok, S::Error> {
+                Err(not_a_variant())
+            }
+        )*
+    };
+}
+
+impl<S> Serializer for PayloadSerializer<S>
+where
+    S: Serializer,
+{
+    type Ok = S::Ok;
+    type Error = S::Error;
+    type SerializeSeq = Impossible<S::Ok, S::Error>;
+    type SerializeTuple = Impossible<S::Ok, S::Error>;
+    type SerializeTupleStruct = Impossible<S::Ok, S::Error>;
+    type SerializeTupleVariant = TupleVariantPayload<S::SerializeTupleStruct>;
+    type SerializeMap = Impossible<S::Ok, S::Error>;
+    type SerializeStruct = Impossible<S::Ok, S::Error>;
+    type SerializeStructVariant = StructVariantPayload<S::SerializeStruct>;
+
+    not_a_variant! {
+        serialize_bool(bool)
+        serialize_i8(i8)
+        serialize_i16(i16)
+        serialize_i32(i32)
+        serialize_i64(i64)
+        serialize_i128(i128)
+        serialize_u8(u8)
+        serialize_u16(u16)
+        serialize_u32(u32)
+        serialize_u64(u64)
+        serialize_u128(u128)
+        serialize_f32(f32)
+        serialize_f64(f64)
+        serialize_char(char)
+        serialize_str(&str)
+        serialize_bytes(&[u8])
+        serialize_none()
+        serialize_unit()
+        serialize_unit_struct(&'static str)
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<S::Ok, S::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<S::Ok, S::Error> {
+        self.0.serialize_unit()
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<S::Ok, S::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self.0)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, S::Error> {
+        Err(not_a_variant())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, S::Error> {
+        Err(not_a_variant())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, S::Error> {
+        Err(not_a_variant())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, S::Error> {
+        self.0
+            .serialize_tuple_struct(variant, len)
+            .map(TupleVariantPayload)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, S::Error> {
+        Err(not_a_variant())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, S::Error> {
+        Err(not_a_variant())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, S::Error> {
+        self.0
+            .serialize_struct(variant, len)
+            .map(StructVariantPayload)
+    }
+}
+
+struct TupleVariantPayload<T>(T);
+
+impl<T> SerializeTupleVariant for TupleVariantPayload<T>
+where
+    T: SerializeTupleStruct,
+{
+    type Ok = T::Ok;
+    type Error = T::Error;
+
+    fn serialize_field<U>(&mut self, value: &U) -> Result<(), T::Error>
+    where
+        U: ?Sized + Serialize,
+    {
+        self.0.serialize_field(value)
+    }
+
+    fn end(self) -> Result<T::Ok, T::Error> {
+        self.0.end()
+    }
+}
+
+struct StructVariantPayload<T>(T);
+
+impl<T> SerializeStructVariant for StructVariantPayload<T>
+where
+    T: SerializeStruct,
+{
+    type Ok = T::Ok;
+    type Error = T::Error;
+
+    fn serialize_field<U>(&mut self, key: &'static str, value: &U) -> Result<(), T::Error>
+    where
+        U: ?Sized + Serialize,
+    {
+        self.0.serialize_field(key, value)
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), T::Error> {
+        self.0.skip_field(key)
+    }
+
+    fn end(self) -> Result<T::Ok, T::Error> {
+        self.0.end()
+    }
+}
+
+/// Deserializes the buffered fields next to an internal tag, reading values
+/// that aren't structs, maps, units or enums from the `value` field.
+///
+/// The fields are kept in the order they were read, so that repeated fields
+/// are rejected by the value like `typetag` does.
+struct InternalDeserializer<E> {
+    fields: Vec<(Value, Value)>,
+    _error: PhantomData<fn() -> E>,
+}
+
+impl<E> InternalDeserializer<E>
+where
+    E: serde::de::Error,
+{
+    fn value(self) -> Result<ValueDeserializer<E>, E> {
+        let mut values = self
+            .fields
+            .into_iter()
+            .filter(|(field, _)| matches!(field, Value::String(field) if field == VALUE_FIELD));
+        match (values.next(), values.next()) {
+            (Some((_, value)), None) => Ok(ValueDeserializer::new(value)),
+            (Some(_), Some(_)) => Err(E::duplicate_field(VALUE_FIELD)),
+            (None, _) => Err(E::missing_field(VALUE_FIELD)),
+        }
+    }
+
+    fn fields(self) -> FieldsMap<E> {
+        fields_map(self.fields)
+    }
+}
+
+macro_rules! deserialize_value {
+    ($($method:ident($($arg:ident: $ty:ty),*))*) => {
+        $(
+            fn $method<V>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value, E>
+            where
+                V: Visitor<'de>,
+            {
+                self.value()?.$method($($arg,)* visitor)
+            }
+        )*
+    };
+}
+
+impl<'de, E> Deserializer<'de> for InternalDeserializer<E>
+where
+    E: serde::de::Error,
+{
+    type Error = E;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+    {
+        self.fields().deserialize_any(visitor)
+    }
+
+    deserialize_value! {
+        deserialize_bool()
+        deserialize_i8()
+        deserialize_i16()
+        deserialize_i32()
+        deserialize_i64()
+        deserialize_i128()
+        deserialize_u8()
+        deserialize_u16()
+        deserialize_u32()
+        deserialize_u64()
+        deserialize_u128()
+        deserialize_f32()
+        deserialize_f64()
+        deserialize_char()
+        deserialize_str()
+        deserialize_string()
+        deserialize_bytes()
+        deserialize_byte_buf()
+        deserialize_seq()
+        deserialize_tuple(len: usize)
+        deserialize_tuple_struct(name: &'static str, len: usize)
+        deserialize_identifier()
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+    {
+        if self.fields.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self.value()?)
+        }
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+    {
+        self.fields().deserialize_map(visitor)
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+    {
+        self.fields().deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+    {
+        self.fields().deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+}
+
+impl<R> Representation for External<R>
+where
+    R: Registry,
+{
+    type Object = R::Object;
+
+    fn serialize_object<S>(object: &R::Object, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_externally_tagged(&R::key(object), object, serializer)
+    }
+
+    fn deserialize_object<'de, D>(deserializer: D) -> Result<Box<R::Object>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_externally_tagged(R::TYPE_NAME, R::deserialize, deserializer)
+    }
+}
+
+impl<R> Representation for Internal<R>
+where
+    R: Registry,
+{
+    type Object = R::Object;
+
+    fn serialize_object<S>(object: &R::Object, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_internally_tagged(R::FIELD_NAMES[0], &R::key(object), object, serializer)
+    }
+
+    fn deserialize_object<'de, D>(deserializer: D) -> Result<Box<R::Object>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_internally_tagged(R::FIELD_NAMES[0], R::deserialize, deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::fmt::Debug;
+
+    use once_cell::sync::Lazy;
+
+    use crate::{deserialize_into_boxed_trait, DesFnSync, Keyed};
+
+    trait Shape: erased_serde::Serialize + Debug {
+        fn name(&self) -> &'static str;
+    }
+
+    #[typetag::serde]
+    trait TypetagExternal: Debug {}
+
+    #[typetag::serde(tag = "type")]
+    trait TypetagInternal: Debug {}
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Circle {
+        radius: f64,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Count(u32);
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Label(Option<String>);
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Pair(i32, i32);
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Empty;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Tags(Vec<String>);
+
+    #[derive(Debug, Serialize, Deserialize)]
+    enum Mode {
+        Fast,
+        Speed(u8),
+        Point(i32, i32),
+        Rect { w: u32, h: u32 },
+    }
+
+    macro_rules! shapes {
+        ($($ty:ident)*) => {
+            $(
+                impl Shape for $ty {
+                    fn name(&self) -> &'static str {
+                        stringify!($ty)
+                    }
+                }
+
+                #[typetag::serde]
+                impl TypetagExternal for $ty {}
+
+                #[typetag::serde]
+                impl TypetagInternal for $ty {}
+            )*
+
+            static MAP: Lazy<HashMap<&'static str, DesFnSync<Box<dyn Shape>>>> = Lazy::new(|| {
+                let mut map = HashMap::<&'static str, DesFnSync<Box<dyn Shape>>>::new();
+                $(map.insert(stringify!($ty), deserialize_into_boxed_trait!($ty));)*
+                map
+            });
+        };
+    }
+
+    shapes!(Circle Count Label Pair Empty Tags Mode);
+
+    struct Shapes;
+
+    impl Registry for Shapes {
+        type Object = dyn Shape;
+        type Key = String;
+
+        const TYPE_NAME: &'static str = "Box<dyn Shape>";
+        const FIELD_NAMES: &'static [&'static str; 2] = &["type", "value"];
+
+        fn key(object: &dyn Shape) -> String {
+            object.name().to_owned()
+        }
+
+        fn deserialize(
+            key: String,
+            deserializer: &mut dyn erased_serde::Deserializer,
+        ) -> Result<Box<dyn Shape>, Error> {
+            MAP.get(key.as_str())
+                .ok_or_else(crate::unknown_key)
+                .and_then(|f| f(deserializer))
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Externals(#[serde(with = "Keyed::<External<Shapes>>")] Vec<Box<dyn Shape>>);
+
+    #[derive(Serialize, Deserialize)]
+    struct Internals(#[serde(with = "Keyed::<Internal<Shapes>>")] Vec<Box<dyn Shape>>);
+
+    macro_rules! fixtures {
+        ($($value:expr),* $(,)?) => {
+            (
+                vec![$(Box::new($value) as Box<dyn Shape>),*],
+                vec![$(Box::new($value) as Box<dyn TypetagExternal>),*],
+                vec![$(Box::new($value) as Box<dyn TypetagInternal>),*],
+            )
+        };
+    }
+
+    fn debug<T: ?Sized + Debug>(values: &[Box<T>]) -> Vec<String> {
+        values.iter().map(|v| format!("{:?}", v)).collect()
+    }
+
+    #[test]
+    fn conforms_to_typetag() {
+        let (shapes, externals, internals) = fixtures![
+            Circle { radius: 2.5 },
+            Count(3),
+            Label(Some("a".to_owned())),
+            Label(None),
+            Pair(1, -2),
+            Empty,
+            Tags(vec!["x".to_owned(), "y".to_owned()]),
+            Mode::Fast,
+            Mode::Speed(4),
+            Mode::Point(5, 6),
+            Mode::Rect { w: 7, h: 8 },
+        ];
+        let expected = debug(&shapes);
+        assert_eq!(debug(&externals), expected);
+
+        let shapes = Externals(shapes);
+        let json = serde_json::to_string(&shapes).unwrap();
+        assert_eq!(json, serde_json::to_string(&externals).unwrap());
+        let read: Vec<Box<dyn TypetagExternal>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(debug(&read), expected);
+        let read: Externals = serde_json::from_str(&json).unwrap();
+        assert_eq!(debug(&read.0), expected);
+
+        let bytes = bincode::serialize(&shapes).unwrap();
+        assert_eq!(bytes, bincode::serialize(&externals).unwrap());
+        let read: Externals = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(debug(&read.0), expected);
+
+        let shapes = Internals(shapes.0);
+        let json = serde_json::to_string(&shapes).unwrap();
+        assert_eq!(json, serde_json::to_string(&internals).unwrap());
+        let read: Vec<Box<dyn TypetagInternal>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(debug(&read), expected);
+        let read: Internals = serde_json::from_str(&json).unwrap();
+        assert_eq!(debug(&read.0), expected);
+    }
+
+    #[test]
+    fn reads_typetag_fixtures() {
+        let json = r#"[
+            {"type":"Circle","radius":1.0},
+            {"radius":2.0,"type":"Circle"},
+            {"value":3,"type":"Count"},
+            {"type":"Label"},
+            {"type":"Pair","value":[1,2]},
+            {"type":"Empty"},
+            {"type":"Tags","value":[]},
+            {"type":"Mode","Fast":null},
+            {"Rect":{"w":1,"h":2},"type":"Mode"}
+        ]"#;
+        let read: Internals = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            debug(&read.0),
+            [
+                "Circle { radius: 1.0 }",
+                "Circle { radius: 2.0 }",
+                "Count(3)",
+                "Label(None)",
+                "Pair(1, 2)",
+                "Empty",
+                "Tags([])",
+                "Fast",
+                "Rect { w: 1, h: 2 }",
+            ]
+        );
+
+        let json = r#"[{"Mode":{"Point":[1,2]}},{"Empty":null}]"#;
+        let read: Externals = serde_json::from_str(json).unwrap();
+        assert_eq!(debug(&read.0), ["Point(1, 2)", "Empty"]);
+    }
+
+    #[test]
+    fn rejects_repeated_fields_like_typetag() {
+        for json in [
+            r#"[{"type":"Circle","radius":1.0,"radius":2.0}]"#,
+            r#"[{"radius":1.0,"type":"Circle","radius":2.0}]"#,
+        ] {
+            let error = serde_json::from_str::<Vec<Box<dyn TypetagInternal>>>(json)
+                .err()
+                .unwrap();
+            assert!(error.to_string().contains("duplicate field `radius`"));
+            let error = serde_json::from_str::<Internals>(json).err().unwrap();
+            assert!(error.to_string().contains("duplicate field `radius`"));
+        }
+
+        let json = r#"[{"type":"Count","value":1,"value":2}]"#;
+        assert!(serde_json::from_str::<Vec<Box<dyn TypetagInternal>>>(json).is_err());
+        let error = serde_json::from_str::<Internals>(json).err().unwrap();
+        assert!(error.to_string().contains("duplicate field `value`"));
+    }
+
+    #[test]
+    fn returns_errors() {
+        let error = serde_json::from_str::<Externals>("[{}]").err().unwrap();
+        assert!(error
+            .to_string()
+            .contains("expected externally tagged Box<dyn Shape>"));
+
+        let error = serde_json::from_str::<Internals>(r#"[{"value":3}]"#)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("missing field `type`"));
+
+        let error = serde_json::from_str::<Internals>(r#"[{"type":"Count"}]"#)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("missing field `value`"));
+
+        let error = serde_json::from_str::<Internals>(r#"[{"type":"Square"}]"#)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("unknown deserialization key"));
+    }
+}
@@ -0,0 +1,618 @@
+//! Buffering of serialized values to replay them into another serializer.
+//!
+//! Unlike [`serde_value::Value`], the buffered [`Content`] keeps the names
+//! and lengths of structs, tuples and variants, and is recorded with the
+//! same [`is_human_readable()`](Serializer::is_human_readable) as the
+//! serializer it will be replayed into, so the replayed output is the same
+//! as if the value had been written there directly.
+
+use std::marker::PhantomData;
+
+use serde::ser::{
+    SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
+    SerializeTupleStruct, SerializeTupleVariant,
+};
+use serde::{Serialize, Serializer};
+
+/// A value recorded by a [`ContentSerializer`].
+pub(crate) enum Content {
+    Bool(bool),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    I128(i128),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    U128(u128),
+    F32(f32),
+    F64(f64),
+    Char(char),
+    String(String),
+    Bytes(Vec<u8>),
+    None,
+    Some(Box<Content>),
+    Unit,
+    UnitStruct(&'static str),
+    UnitVariant(&'static str, u32, &'static str),
+    NewtypeStruct(&'static str, Box<Content>),
+    NewtypeVariant(&'static str, u32, &'static str, Box<Content>),
+    Seq(Option<usize>, Vec<Content>),
+    Tuple(Vec<Content>),
+    TupleStruct(&'static str, Vec<Content>),
+    TupleVariant(&'static str, u32, &'static str, Vec<Content>),
+    Map(Option<usize>, Vec<(Content, Content)>),
+    Struct(&'static str, Fields),
+    StructVariant(&'static str, u32, &'static str, Fields),
+}
+
+/// The fields of a struct, with `None` for skipped ones.
+pub(crate) type Fields = Vec<(&'static str, Option<Content>)>;
+
+/// Records `value` for a serializer with the given readability.
+pub(crate) fn to_content<T, E>(value: &T, human_readable: bool) -> Result<Content, E>
+where
+    T: ?Sized + Serialize,
+    E: serde::ser::Error,
+{
+    value.serialize(ContentSerializer {
+        human_readable,
+        _error: PhantomData,
+    })
+}
+
+impl Serialize for Content {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match *self {
+            Content::Bool(v) => serializer.serialize_bool(v),
+            Content::I8(v) => serializer.serialize_i8(v),
+            Content::I16(v) => serializer.serialize_i16(v),
+            Content::I32(v) => serializer.serialize_i32(v),
+            Content::I64(v) => serializer.serialize_i64(v),
+            Content::I128(v) => serializer.serialize_i128(v),
+            Content::U8(v) => serializer.serialize_u8(v),
+            Content::U16(v) => serializer.serialize_u16(v),
+            Content::U32(v) => serializer.serialize_u32(v),
+            Content::U64(v) => serializer.serialize_u64(v),
+            Content::U128(v) => serializer.serialize_u128(v),
+            Content::F32(v) => serializer.serialize_f32(v),
+            Content::F64(v) => serializer.serialize_f64(v),
+            Content::Char(v) => serializer.serialize_char(v),
+            Content::String(ref v) => serializer.serialize_str(v),
+            Content::Bytes(ref v) => serializer.serialize_bytes(v),
+            Content::None => serializer.serialize_none(),
+            Content::Some(ref v) => serializer.serialize_some(&**v),
+            Content::Unit => serializer.serialize_unit(),
+            Content::UnitStruct(name) => serializer.serialize_unit_struct(name),
+            Content::UnitVariant(name, index, variant) => {
+                serializer.serialize_unit_variant(name, index, variant)
+            }
+            Content::NewtypeStruct(name, ref v) => serializer.serialize_newtype_struct(name, &**v),
+            Content::NewtypeVariant(name, index, variant, ref v) => {
+                serializer.serialize_newtype_variant(name, index, variant, &**v)
+            }
+            Content::Seq(len, ref elements) => {
+                let mut state = serializer.serialize_seq(len)?;
+                for element in elements {
+                    state.serialize_element(element)?;
+                }
+                state.end()
+            }
+            Content::Tuple(ref elements) => {
+                let mut state = serializer.serialize_tuple(elements.len())?;
+                for element in elements {
+                    state.serialize_element(element)?;
+                }
+                state.end()
+            }
+            Content::TupleStruct(name, ref fields) => {
+                let mut state = serializer.serialize_tuple_struct(name, fields.len())?;
+                for field in fields {
+                    state.serialize_field(field)?;
+                }
+                state.end()
+            }
+            Content::TupleVariant(name, index, variant, ref fields) => {
+                let mut state =
+                    serializer.serialize_tuple_variant(name, index, variant, fields.len())?;
+                for field in fields {
+                    state.serialize_field(field)?;
+                }
+                state.end()
+            }
+            Content::Map(len, ref entries) => {
+                let mut state = serializer.serialize_map(len)?;
+                for (key, value) in entries {
+                    state.serialize_entry(key, value)?;
+                }
+                state.end()
+            }
+            Content::Struct(name, ref fields) => {
+                let mut state = serializer.serialize_struct(name, written(fields))?;
+                for (key, value) in fields {
+                    match value {
+                        Some(value) => state.serialize_field(key, value)?,
+                        None => state.skip_field(key)?,
+                    }
+                }
+                state.end()
+            }
+            Content::StructVariant(name, index, variant, ref fields) => {
+                let mut state =
+                    serializer.serialize_struct_variant(name, index, variant, written(fields))?;
+                for (key, value) in fields {
+                    match value {
+                        Some(value) => state.serialize_field(key, value)?,
+                        None => state.skip_field(key)?,
+                    }
+                }
+                state.end()
+            }
+        }
+    }
+}
+
+/// The number of fields that weren't skipped.
+fn written(fields: &Fields) -> usize {
+    fields.iter().filter(|(_, value)| value.is_some()).count()
+}
+
+/// Records everything it's given as [`Content`].
+pub(crate) struct ContentSerializer<E> {
+    human_readable: bool,
+    _error: PhantomData<fn() -> E>,
+}
+
+impl<E> ContentSerializer<E> {
+    fn content<T>(&self, value: &T) -> Result<Content, E>
+    where
+        T: ?Sized + Serialize,
+        E: serde::ser::Error,
+    {
+        to_content(value, self.human_readable)
+    }
+}
+
+impl<E> Serializer for ContentSerializer<E>
+where
+    E: serde::ser::Error,
+{
+    type Ok = Content;
+    type Error = E;
+    type SerializeSeq = SerializeContent<E>;
+    type SerializeTuple = SerializeContent<E>;
+    type SerializeTupleStruct = SerializeContent<E>;
+    type SerializeTupleVariant = SerializeContent<E>;
+    type SerializeMap = SerializeContentMap<E>;
+    type SerializeStruct = SerializeContentStruct<E>;
+    type SerializeStructVariant = SerializeContentStruct<E>;
+
+    fn serialize_bool(self, v: bool) -> Result<Content, E> {
+        Ok(Content::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Content, E> {
+        Ok(Content::I8(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Content, E> {
+        Ok(Content::I16(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Content, E> {
+        Ok(Content::I32(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Content, E> {
+        Ok(Content::I64(v))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Content, E> {
+        Ok(Content::I128(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Content, E> {
+        Ok(Content::U8(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Content, E> {
+        Ok(Content::U16(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Content, E> {
+        Ok(Content::U32(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Content, E> {
+        Ok(Content::U64(v))
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Content, E> {
+        Ok(Content::U128(v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Content, E> {
+        Ok(Content::F32(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Content, E> {
+        Ok(Content::F64(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Content, E> {
+        Ok(Content::Char(v))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Content, E> {
+        Ok(Content::String(v.to_owned()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Content, E> {
+        Ok(Content::Bytes(v.to_owned()))
+    }
+
+    fn serialize_none(self) -> Result<Content, E> {
+        Ok(Content::None)
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<Content, E>
+    where
+        T: ?Sized + Serialize,
+    {
+        Ok(Content::Some(Box::new(self.content(value)?)))
+    }
+
+    fn serialize_unit(self) -> Result<Content, E> {
+        Ok(Content::Unit)
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<Content, E> {
+        Ok(Content::UnitStruct(name))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Content, E> {
+        Ok(Content::UnitVariant(name, variant_index, variant))
+    }
+
+    fn serialize_newtype_struct<T>(self, name: &'static str, value: &T) -> Result<Content, E>
+    where
+        T: ?Sized + Serialize,
+    {
+        Ok(Content::NewtypeStruct(name, Box::new(self.content(value)?)))
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Content, E>
+    where
+        T: ?Sized + Serialize,
+    {
+        Ok(Content::NewtypeVariant(
+            name,
+            variant_index,
+            variant,
+            Box::new(self.content(value)?),
+        ))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeContent<E>, E> {
+        Ok(SerializeContent::new(
+            self,
+            len.unwrap_or(0),
+            move |elements| Content::Seq(len, elements),
+        ))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeContent<E>, E> {
+        Ok(SerializeContent::new(self, len, Content::Tuple))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<SerializeContent<E>, E> {
+        Ok(SerializeContent::new(self, len, move |fields| {
+            Content::TupleStruct(name, fields)
+        }))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeContent<E>, E> {
+        Ok(SerializeContent::new(self, len, move |fields| {
+            Content::TupleVariant(name, variant_index, variant, fields)
+        }))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<SerializeContentMap<E>, E> {
+        Ok(SerializeContentMap {
+            serializer: self,
+            len,
+            entries: Vec::with_capacity(len.unwrap_or(0)),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<SerializeContentStruct<E>, E> {
+        Ok(SerializeContentStruct::new(self, len, move |fields| {
+            Content::Struct(name, fields)
+        }))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeContentStruct<E>, E> {
+        Ok(SerializeContentStruct::new(self, len, move |fields| {
+            Content::StructVariant(name, variant_index, variant, fields)
+        }))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.human_readable
+    }
+}
+
+/// Records the elements of sequences, tuples and tuple structs or variants.
+pub(crate) struct SerializeContent<E> {
+    serializer: ContentSerializer<E>,
+    elements: Vec<Content>,
+    end: Box<dyn FnOnce(Vec<Content>) -> Content>,
+}
+
+impl<E> SerializeContent<E>
+where
+    E: serde::ser::Error,
+{
+    fn new(
+        serializer: ContentSerializer<E>,
+        len: usize,
+        end: impl FnOnce(Vec<Content>) -> Content + 'static,
+    ) -> Self {
+        SerializeContent {
+            serializer,
+            elements: Vec::with_capacity(len),
+            end: Box::new(end),
+        }
+    }
+
+    fn push<T>(&mut self, value: &T) -> Result<(), E>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.elements.push(self.serializer.content(value)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Content, E> {
+        Ok((self.end)(self.elements))
+    }
+}
+
+impl<E> SerializeSeq for SerializeContent<E>
+where
+    E: serde::ser::Error,
+{
+    type Ok = Content;
+    type Error = E;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), E>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Content, E> {
+        self.finish()
+    }
+}
+
+impl<E> SerializeTuple for SerializeContent<E>
+where
+    E: serde::ser::Error,
+{
+    type Ok = Content;
+    type Error = E;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), E>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Content, E> {
+        self.finish()
+    }
+}
+
+impl<E> SerializeTupleStruct for SerializeContent<E>
+where
+    E: serde::ser::Error,
+{
+    type Ok = Content;
+    type Error = E;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), E>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Content, E> {
+        self.finish()
+    }
+}
+
+impl<E> SerializeTupleVariant for SerializeContent<E>
+where
+    E: serde::ser::Error,
+{
+    type Ok = Content;
+    type Error = E;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), E>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Content, E> {
+        self.finish()
+    }
+}
+
+/// Records the entries of maps.
+pub(crate) struct SerializeContentMap<E> {
+    serializer: ContentSerializer<E>,
+    len: Option<usize>,
+    entries: Vec<(Content, Content)>,
+    key: Option<Content>,
+}
+
+impl<E> SerializeMap for SerializeContentMap<E>
+where
+    E: serde::ser::Error,
+{
+    type Ok = Content;
+    type Error = E;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), E>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.key = Some(self.serializer.content(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), E>
+    where
+        T: ?Sized + Serialize,
+    {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| E::custom("serialize_value called before serialize_key"))?;
+        self.entries.push((key, self.serializer.content(value)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Content, E> {
+        Ok(Content::Map(self.len, self.entries))
+    }
+}
+
+/// Records the fields of structs and struct variants, including skipped
+/// ones.
+pub(crate) struct SerializeContentStruct<E> {
+    serializer: ContentSerializer<E>,
+    fields: Fields,
+    end: Box<dyn FnOnce(Fields) -> Content>,
+}
+
+impl<E> SerializeContentStruct<E>
+where
+    E: serde::ser::Error,
+{
+    fn new(
+        serializer: ContentSerializer<E>,
+        len: usize,
+        end: impl FnOnce(Fields) -> Content + 'static,
+    ) -> Self {
+        SerializeContentStruct {
+            serializer,
+            fields: Vec::with_capacity(len),
+            end: Box::new(end),
+        }
+    }
+
+    fn push<T>(&mut self, key: &'static str, value: &T) -> Result<(), E>
+    where
+        T: ?Sized + Serialize,
+    {
+        let value = self.serializer.content(value)?;
+        self.fields.push((key, Some(value)));
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Content, E> {
+        Ok((self.end)(self.fields))
+    }
+}
+
+impl<E> SerializeStruct for SerializeContentStruct<E>
+where
+    E: serde::ser::Error,
+{
+    type Ok = Content;
+    type Error = E;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), E>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push(key, value)
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), E> {
+        self.fields.push((key, None));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Content, E> {
+        self.finish()
+    }
+}
+
+impl<E> SerializeStructVariant for SerializeContentStruct<E>
+where
+    E: serde::ser::Error,
+{
+    type Ok = Content;
+    type Error = E;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), E>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push(key, value)
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), E> {
+        self.fields.push((key, None));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Content, E> {
+        self.finish()
+    }
+}
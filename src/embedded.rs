@@ -0,0 +1,319 @@
+//! Representation of keyed values with the value embedded as a string in
+//! another format.
+//!
+//! This writes the same struct as [`serialize_with_key()`], but the value is
+//! first encoded with a [`TextCodec`] and stored as a string:
+//!
+//! ```json
+//! {"type": "point", "json": "{\"x\":1,\"y\":2}"}
+//! ```
+//!
+//! Any [`Codec`](crate::envelope::Codec) producing bytes can be embedded as
+//! base64 text with `Base64<C>`, which requires the `base64` feature. To embed the bytes
+//! themselves, use [`envelope`](crate::envelope) instead.
+//!
+//! With [`Keyed<Embedded<R, C>>`](crate::Keyed), the registry decodes the
+//! value from a deserializer of the inner format.
+//!
+//! ```
+//! use keyedes::embedded::{deserialize_from_embedded, serialize_with_embedded, TextCodec};
+//! use keyedes::Error;
+//!
+//! struct Json;
+//!
+//! impl TextCodec for Json {
+//!     fn encode(value: &dyn erased_serde::Serialize) -> Result<String, Error> {
+//!         serde_json::to_string(value).map_err(serde::ser::Error::custom)
+//!     }
+//!
+//!     fn decode<T, F>(text: &str, f: F) -> Result<T, Error>
+//!     where
+//!         F: FnOnce(&mut dyn erased_serde::Deserializer) -> Result<T, Error>,
+//!     {
+//!         let mut deserializer = serde_json::Deserializer::from_str(text);
+//!         f(&mut <dyn erased_serde::Deserializer>::erase(&mut deserializer))
+//!     }
+//! }
+//!
+//! let mut json = Vec::new();
+//! serialize_with_embedded::<Json, _, _, _>(
+//!     "Point",
+//!     &["type", "json"],
+//!     "point",
+//!     &(1, 2),
+//!     &mut serde_json::Serializer::new(&mut json),
+//! )
+//! .unwrap();
+//! assert_eq!(json, br#"{"type":"point","json":"[1,2]"}"#);
+//! ```
+//!
+//! [`serialize_with_key()`]: crate::serialize_with_key
+
+use std::marker::PhantomData;
+
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::adapters::Representation;
+#[cfg(feature = "base64")]
+use crate::envelope::Codec;
+use crate::private::ErasedSerdeSerializeWrapper;
+use crate::{deserialize_by_key, serialize_with_key, Error, Registry};
+
+/// A nested encoding for values embedded as strings.
+pub trait TextCodec {
+    /// Encodes the value to a string.
+    fn encode(value: &dyn erased_serde::Serialize) -> Result<String, Error>;
+
+    /// Calls `f` with a deserializer for the string.
+    fn decode<T, F>(text: &str, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut dyn erased_serde::Deserializer) -> Result<T, Error>;
+}
+
+/// Embeds the bytes of the codec `C` as standard base64 text.
+///
+/// Requires the `base64` feature.
+#[cfg(feature = "base64")]
+pub struct Base64<C>(PhantomData<C>);
+
+#[cfg(feature = "base64")]
+impl<C: Codec> TextCodec for Base64<C> {
+    fn encode(value: &dyn erased_serde::Serialize) -> Result<String, Error> {
+        use base64::Engine;
+
+        C::encode(value).map(|bytes| base64::engine::general_purpose::STANDARD.encode(bytes))
+    }
+
+    fn decode<T, F>(text: &str, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut dyn erased_serde::Deserializer) -> Result<T, Error>,
+    {
+        use base64::Engine;
+
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(text)
+            .map_err(<Error as serde::de::Error>::custom)?;
+        C::decode(&bytes, f)
+    }
+}
+
+/// Marker for serializing objects of `R` with their values embedded as
+/// strings encoded with `C`.
+///
+/// See the [module documentation](self) for details.
+pub struct Embedded<R, C>(PhantomData<(R, C)>);
+
+/// Will serialize a struct with the key and the value encoded with `C` as a
+/// string.
+pub fn serialize_with_embedded<C, S, K, V>(
+    type_name: &'static str,
+    field_names: &'static [&'static str; 2],
+    key: &K,
+    value: &V,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    C: TextCodec,
+    K: ?Sized + Serialize,
+    V: ?Sized + erased_serde::Serialize,
+    S: Serializer,
+{
+    let text = C::encode(&ErasedSerdeSerializeWrapper(value)).map_err(S::Error::custom)?;
+    serialize_with_key(type_name, field_names, key, &text, serializer)
+}
+
+/// Will deserialize a struct with the key and the value encoded with `C` as a
+/// string.
+///
+/// The function `f` will be called with the deserialized key and a
+/// deserializer for the decoded value.
+pub fn deserialize_from_embedded<'de, C, D, K, V, F>(
+    type_name: &'static str,
+    field_names: &'static [&'static str; 2],
+    f: F,
+    deserializer: D,
+) -> Result<V, D::Error>
+where
+    C: TextCodec,
+    D: Deserializer<'de>,
+    K: Deserialize<'de>,
+    F: Fn(K, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+{
+    deserialize_by_key(
+        type_name,
+        field_names,
+        |key, deserializer| {
+            let text: String = erased_serde::deserialize(deserializer)?;
+            C::decode(&text, |deserializer| f(key, deserializer))
+        },
+        deserializer,
+    )
+}
+
+impl<R, C> Representation for Embedded<R, C>
+where
+    R: Registry,
+    C: TextCodec,
+{
+    type Object = R::Object;
+
+    fn serialize_object<S>(object: &R::Object, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_with_embedded::<C, _, _, _>(
+            R::TYPE_NAME,
+            R::FIELD_NAMES,
+            &R::key(object),
+            object,
+            serializer,
+        )
+    }
+
+    fn deserialize_object<'de, D>(deserializer: D) -> Result<Box<R::Object>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_from_embedded::<C, _, _, _, _>(
+            R::TYPE_NAME,
+            R::FIELD_NAMES,
+            R::deserialize,
+            deserializer,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use once_cell::sync::Lazy;
+
+    use crate::{deserialize_into_boxed_trait, DesFnSync, Keyed};
+
+    trait TestTrait: erased_serde::Serialize {
+        fn key(&self) -> &'static str;
+        fn describe(&self) -> String;
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    impl TestTrait for Point {
+        fn key(&self) -> &'static str {
+            "point"
+        }
+
+        fn describe(&self) -> String {
+            format!("point {} {}", self.x, self.y)
+        }
+    }
+
+    static MAP: Lazy<HashMap<String, DesFnSync<Box<dyn TestTrait>>>> = Lazy::new(|| {
+        let mut map = HashMap::<String, DesFnSync<Box<dyn TestTrait>>>::new();
+        map.insert("point".to_string(), deserialize_into_boxed_trait!(Point));
+        map
+    });
+
+    struct TestRegistry;
+
+    impl Registry for TestRegistry {
+        type Object = dyn TestTrait;
+        type Key = String;
+
+        const TYPE_NAME: &'static str = "Box<dyn TestTrait>";
+        const FIELD_NAMES: &'static [&'static str; 2] = &["type", "json"];
+
+        fn key(object: &dyn TestTrait) -> String {
+            object.key().to_string()
+        }
+
+        fn deserialize(
+            key: String,
+            deserializer: &mut dyn erased_serde::Deserializer,
+        ) -> Result<Box<dyn TestTrait>, Error> {
+            MAP.get(&key)
+                .ok_or_else(crate::unknown_key)
+                .and_then(|f| f(deserializer))
+        }
+    }
+
+    struct Json;
+
+    impl TextCodec for Json {
+        fn encode(value: &dyn erased_serde::Serialize) -> Result<String, Error> {
+            serde_json::to_string(value).map_err(Error::custom)
+        }
+
+        fn decode<T, F>(text: &str, f: F) -> Result<T, Error>
+        where
+            F: FnOnce(&mut dyn erased_serde::Deserializer) -> Result<T, Error>,
+        {
+            let mut deserializer = serde_json::Deserializer::from_str(text);
+            f(&mut <dyn erased_serde::Deserializer>::erase(
+                &mut deserializer,
+            ))
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Container {
+        #[serde(with = "Keyed::<Embedded<TestRegistry, Json>>")]
+        items: Vec<Box<dyn TestTrait>>,
+    }
+
+    #[test]
+    fn embedded_json_round_trips() {
+        let container = Container {
+            items: vec![Box::new(Point { x: 1, y: -2 })],
+        };
+
+        let json = serde_json::to_string(&container).unwrap();
+        assert_eq!(
+            json,
+            r#"{"items":[{"type":"point","json":"{\"x\":1,\"y\":-2}"}]}"#
+        );
+
+        for json in [
+            json.as_str(),
+            r#"{"items":[{"json":"{\"x\":1,\"y\":-2}","type":"point"}]}"#,
+        ] {
+            let container: Container = serde_json::from_str(json).unwrap();
+            assert_eq!(container.items[0].describe(), "point 1 -2");
+        }
+
+        let error =
+            serde_json::from_str::<Container>(r#"{"items":[{"type":"point","json":"{\"x\":1}"}]}"#)
+                .err()
+                .unwrap();
+        assert!(error.to_string().contains("missing field `y`"));
+    }
+
+    #[cfg(feature = "base64")]
+    #[test]
+    fn embedded_base64_bincode_round_trips() {
+        use crate::testing::Bincode;
+
+        #[derive(Serialize, Deserialize)]
+        struct Single(
+            #[serde(with = "Keyed::<Embedded<TestRegistry, Base64<Bincode>>>")] Box<dyn TestTrait>,
+        );
+
+        let json = serde_json::to_string(&Single(Box::new(Point { x: 1, y: -2 }))).unwrap();
+        assert_eq!(json, r#"{"type":"point","json":"AgM="}"#);
+
+        let single: Single = serde_json::from_str(&json).unwrap();
+        assert_eq!(single.0.describe(), "point 1 -2");
+
+        let error = serde_json::from_str::<Single>(r#"{"type":"point","json":"A?"}"#)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("Invalid symbol"));
+    }
+}
@@ -0,0 +1,1138 @@
+//! Converting between keyed values and plain enums.
+//!
+//! Data written with [`serialize_with_key()`] can be read into an ordinary
+//! `#[derive(Deserialize)]` enum without going through a registry or trait
+//! objects. A [`KeyedDeserializer`] answers `deserialize_enum` by reading the
+//! key as the variant name and the value as its payload, and forwards
+//! everything else to the deserializer it wraps:
+//!
+//! ```
+//! use keyedes::enums::KeyedDeserializer;
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize, Debug, PartialEq)]
+//! enum Shape {
+//!     Circle { radius: f64 },
+//!     Count(u32),
+//!     Empty,
+//! }
+//!
+//! let mut json = serde_json::Deserializer::from_str(r#"{"id":"Count","data":3}"#);
+//! let shape = Shape::deserialize(KeyedDeserializer::new(
+//!     "Shape",
+//!     &["id", "data"],
+//!     &mut json,
+//! ))
+//! .unwrap();
+//! assert_eq!(shape, Shape::Count(3));
+//! ```
+//!
+//! Like [`deserialize_by_key()`], the key and value may be in a sequence or
+//! in a map in either order, and a unit variant may leave out the value.
+//! Only the outermost enum is read this way; [`deserialize_enum_by_key()`]
+//! can be used with `#[serde(deserialize_with)]` for nested ones.
+//!
+//! Going the other way, a [`KeyedSerializer`] writes the unit, newtype, tuple
+//! and struct variants of an enum as the same struct [`serialize_with_key()`]
+//! does for a trait object, so data can move between the two without a
+//! migration. Unit variants are written with a unit value, while tuple and
+//! struct variants are buffered and written as a tuple or a struct named
+//! after the variant, with the same output as writing that tuple or struct
+//! with [`serialize_with_key()`], even for binary formats. Again only the
+//! outermost enum is written this way, and [`serialize_enum_with_key()`] can
+//! be used with `#[serde(serialize_with)]`.
+//!
+//! ```
+//! use keyedes::enums::KeyedSerializer;
+//! use serde::Serialize;
+//!
+//! #[derive(Serialize)]
+//! enum Shape {
+//!     Circle { radius: f64 },
+//! }
+//!
+//! let mut json = Vec::new();
+//! Shape::Circle { radius: 2.0 }
+//!     .serialize(KeyedSerializer::new(
+//!         "Shape",
+//!         &["id", "data"],
+//!         &mut serde_json::Serializer::new(&mut json),
+//!     ))
+//!     .unwrap();
+//! assert_eq!(json, br#"{"id":"Circle","data":{"radius":2.0}}"#);
+//! ```
+//!
+//! [`serialize_with_key()`]: crate::serialize_with_key
+//! [`deserialize_by_key()`]: crate::deserialize_by_key
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{
+    DeserializeSeed, EnumAccess, Error as _, MapAccess, SeqAccess, VariantAccess, Visitor,
+};
+use serde::ser::{SerializeStructVariant, SerializeTupleVariant};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_value::{Value, ValueDeserializer};
+
+use crate::content::{to_content, Content, Fields};
+use crate::private::{next_tag_or_content, MissingFieldDeserializer, TagOrContentField};
+use crate::serialize_with_key;
+
+const EXPECTING: &str = "adjacently tagged enum";
+
+/// A deserializer that reads enums from keyed values and forwards everything
+/// else.
+///
+/// See the [module documentation](self) for details.
+pub struct KeyedDeserializer<D> {
+    type_name: &'static str,
+    field_names: &'static [&'static str; 2],
+    deserializer: D,
+}
+
+impl<D> KeyedDeserializer<D> {
+    /// Wraps the deserializer, reading enums as structs with the given type
+    /// and field names.
+    pub fn new(
+        type_name: &'static str,
+        field_names: &'static [&'static str; 2],
+        deserializer: D,
+    ) -> KeyedDeserializer<D> {
+        KeyedDeserializer {
+            type_name,
+            field_names,
+            deserializer,
+        }
+    }
+
+    /// Gets the wrapped deserializer.
+    pub fn into_inner(self) -> D {
+        self.deserializer
+    }
+}
+
+/// Will deserialize an enum from a keyed value, using the key as the
+/// variant name.
+pub fn deserialize_enum_by_key<'de, D, T>(
+    type_name: &'static str,
+    field_names: &'static [&'static str; 2],
+    deserializer: D,
+) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(KeyedDeserializer::new(type_name, field_names, deserializer))
+}
+
+macro_rules! forward_to_inner {
+    ($($method:ident($($arg:ident: $ty:ty),*))*) => {
+        $(
+            fn $method<V>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value, D::Error>
+            where
+                V: Visitor<'de>,
+            {
+                self.deserializer.$method($($arg,)* visitor)
+            }
+        )*
+    };
+}
+
+impl<'de, D> Deserializer<'de> for KeyedDeserializer<D>
+where
+    D: Deserializer<'de>,
+{
+    type Error = D::Error;
+
+    forward_to_inner! {
+        deserialize_any()
+        deserialize_bool()
+        deserialize_i8()
+        deserialize_i16()
+        deserialize_i32()
+        deserialize_i64()
+        deserialize_i128()
+        deserialize_u8()
+        deserialize_u16()
+        deserialize_u32()
+        deserialize_u64()
+        deserialize_u128()
+        deserialize_f32()
+        deserialize_f64()
+        deserialize_char()
+        deserialize_str()
+        deserialize_string()
+        deserialize_bytes()
+        deserialize_byte_buf()
+        deserialize_option()
+        deserialize_unit()
+        deserialize_unit_struct(name: &'static str)
+        deserialize_newtype_struct(name: &'static str)
+        deserialize_seq()
+        deserialize_tuple(len: usize)
+        deserialize_tuple_struct(name: &'static str, len: usize)
+        deserialize_map()
+        deserialize_struct(name: &'static str, fields: &'static [&'static str])
+        deserialize_identifier()
+        deserialize_ignored_any()
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserializer.deserialize_struct(
+            self.type_name,
+            self.field_names,
+            KeyedEnumVisitor {
+                name,
+                key_name: self.field_names[0],
+                value_name: self.field_names[1],
+                visitor,
+            },
+        )
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.deserializer.is_human_readable()
+    }
+}
+
+struct KeyedEnumVisitor<V> {
+    name: &'static str,
+    key_name: &'static str,
+    value_name: &'static str,
+    visitor: V,
+}
+
+impl<'de, V> Visitor<'de> for KeyedEnumVisitor<V>
+where
+    V: Visitor<'de>,
+{
+    type Value = V::Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(EXPECTING)
+    }
+
+    fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        self.visitor.visit_enum(KeyedEnumAccess {
+            name: self.name,
+            keys: SeqKey(seq),
+        })
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let (key_name, value_name) = (self.key_name, self.value_name);
+        let value = match next_tag_or_content(&mut map, key_name, value_name)? {
+            Some(TagOrContentField::Tag) => self.visitor.visit_enum(KeyedEnumAccess {
+                name: self.name,
+                keys: MapKey {
+                    map: &mut map,
+                    key_name,
+                    value_name,
+                },
+            })?,
+            Some(TagOrContentField::Content) => {
+                let content = map.next_value::<Value>()?;
+                match next_tag_or_content(&mut map, key_name, value_name)? {
+                    Some(TagOrContentField::Tag) => self.visitor.visit_enum(KeyedEnumAccess {
+                        name: self.name,
+                        keys: BufferedKey {
+                            map: &mut map,
+                            content,
+                        },
+                    })?,
+                    Some(TagOrContentField::Content) => {
+                        return Err(A::Error::duplicate_field(value_name))
+                    }
+                    None => return Err(A::Error::missing_field(key_name)),
+                }
+            }
+            None => return Err(A::Error::missing_field(key_name)),
+        };
+
+        match next_tag_or_content(&mut map, key_name, value_name)? {
+            Some(TagOrContentField::Tag) => Err(A::Error::duplicate_field(key_name)),
+            Some(TagOrContentField::Content) => Err(A::Error::duplicate_field(value_name)),
+            None => Ok(value),
+        }
+    }
+}
+
+/// Reads the key of a keyed value, then gives access to its value.
+trait KeyAccess<'de> {
+    type Error: serde::de::Error;
+    type Content: ContentAccess<'de, Error = Self::Error>;
+
+    fn key<T>(self, seed: T) -> Result<(T::Value, Self::Content), Self::Error>
+    where
+        T: DeserializeSeed<'de>;
+}
+
+/// Reads the value of a keyed value.
+trait ContentAccess<'de> {
+    type Error: serde::de::Error;
+
+    fn content<T>(self, seed: T) -> Result<T::Value, Self::Error>
+    where
+        T: DeserializeSeed<'de>;
+}
+
+struct KeyedEnumAccess<K> {
+    name: &'static str,
+    keys: K,
+}
+
+impl<'de, K> EnumAccess<'de> for KeyedEnumAccess<K>
+where
+    K: KeyAccess<'de>,
+{
+    type Error = K::Error;
+    type Variant = KeyedVariantAccess<K::Content>;
+
+    fn variant_seed<T>(self, seed: T) -> Result<(T::Value, Self::Variant), K::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        let (variant, content) = self.keys.key(VariantSeed(seed))?;
+        Ok((
+            variant,
+            KeyedVariantAccess {
+                name: self.name,
+                content,
+            },
+        ))
+    }
+}
+
+/// Reads the key as a variant name, which is written as a string.
+struct VariantSeed<T>(T);
+
+impl<'de, T> DeserializeSeed<'de> for VariantSeed<T>
+where
+    T: DeserializeSeed<'de>,
+{
+    type Value = T::Value;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.0.deserialize(VariantDeserializer { deserializer })
+    }
+}
+
+/// Asks for a string where the variant visitor asks for an identifier, which
+/// formats that aren't self-describing don't support.
+struct VariantDeserializer<D> {
+    deserializer: D,
+}
+
+impl<'de, D> Deserializer<'de> for VariantDeserializer<D>
+where
+    D: Deserializer<'de>,
+{
+    type Error = D::Error;
+
+    forward_to_inner! {
+        deserialize_any()
+        deserialize_bool()
+        deserialize_i8()
+        deserialize_i16()
+        deserialize_i32()
+        deserialize_i64()
+        deserialize_i128()
+        deserialize_u8()
+        deserialize_u16()
+        deserialize_u32()
+        deserialize_u64()
+        deserialize_u128()
+        deserialize_f32()
+        deserialize_f64()
+        deserialize_char()
+        deserialize_str()
+        deserialize_string()
+        deserialize_bytes()
+        deserialize_byte_buf()
+        deserialize_option()
+        deserialize_unit()
+        deserialize_unit_struct(name: &'static str)
+        deserialize_newtype_struct(name: &'static str)
+        deserialize_seq()
+        deserialize_tuple(len: usize)
+        deserialize_tuple_struct(name: &'static str, len: usize)
+        deserialize_map()
+        deserialize_struct(name: &'static str, fields: &'static [&'static str])
+        deserialize_enum(name: &'static str, variants: &'static [&'static str])
+        deserialize_ignored_any()
+    }
+
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserializer.deserialize_str(visitor)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.deserializer.is_human_readable()
+    }
+}
+
+struct KeyedVariantAccess<C> {
+    name: &'static str,
+    content: C,
+}
+
+impl<'de, C> VariantAccess<'de> for KeyedVariantAccess<C>
+where
+    C: ContentAccess<'de>,
+{
+    type Error = C::Error;
+
+    fn unit_variant(self) -> Result<(), C::Error> {
+        self.content.content(PhantomData::<()>)
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, C::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.content.content(seed)
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value, C::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.content.content(TupleSeed { len, visitor })
+    }
+
+    fn struct_variant<V>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, C::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.content.content(StructSeed {
+            name: self.name,
+            fields,
+            visitor,
+        })
+    }
+}
+
+struct TupleSeed<V> {
+    len: usize,
+    visitor: V,
+}
+
+impl<'de, V> DeserializeSeed<'de> for TupleSeed<V>
+where
+    V: Visitor<'de>,
+{
+    type Value = V::Value;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_tuple(self.len, self.visitor)
+    }
+}
+
+struct StructSeed<V> {
+    name: &'static str,
+    fields: &'static [&'static str],
+    visitor: V,
+}
+
+impl<'de, V> DeserializeSeed<'de> for StructSeed<V>
+where
+    V: Visitor<'de>,
+{
+    type Value = V::Value;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_struct(self.name, self.fields, self.visitor)
+    }
+}
+
+/// The key and value are the elements of a sequence.
+struct SeqKey<A>(A);
+
+impl<'de, A> KeyAccess<'de> for SeqKey<A>
+where
+    A: SeqAccess<'de>,
+{
+    type Error = A::Error;
+    type Content = Self;
+
+    fn key<T>(mut self, seed: T) -> Result<(T::Value, Self), A::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.0.next_element_seed(seed)? {
+            Some(key) => Ok((key, self)),
+            None => Err(A::Error::invalid_length(0, &EXPECTING)),
+        }
+    }
+}
+
+impl<'de, A> ContentAccess<'de> for SeqKey<A>
+where
+    A: SeqAccess<'de>,
+{
+    type Error = A::Error;
+
+    fn content<T>(mut self, seed: T) -> Result<T::Value, A::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.0
+            .next_element_seed(seed)?
+            .ok_or_else(|| A::Error::invalid_length(1, &EXPECTING))
+    }
+}
+
+/// The key is the next value of the map, followed by the value field if
+/// there is one.
+struct MapKey<'a, A> {
+    map: &'a mut A,
+    key_name: &'static str,
+    value_name: &'static str,
+}
+
+impl<'de, 'a, A> KeyAccess<'de> for MapKey<'a, A>
+where
+    A: MapAccess<'de>,
+{
+    type Error = A::Error;
+    type Content = Self;
+
+    fn key<T>(self, seed: T) -> Result<(T::Value, Self), A::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        let key = self.map.next_value_seed(seed)?;
+        Ok((key, self))
+    }
+}
+
+impl<'de, 'a, A> ContentAccess<'de> for MapKey<'a, A>
+where
+    A: MapAccess<'de>,
+{
+    type Error = A::Error;
+
+    fn content<T>(self, seed: T) -> Result<T::Value, A::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match next_tag_or_content(self.map, self.key_name, self.value_name)? {
+            Some(TagOrContentField::Tag) => Err(A::Error::duplicate_field(self.key_name)),
+            Some(TagOrContentField::Content) => self.map.next_value_seed(seed),
+            None => seed.deserialize(MissingFieldDeserializer(self.value_name, PhantomData)),
+        }
+    }
+}
+
+/// The key is the next value of the map, with the value buffered before it.
+struct BufferedKey<'a, A> {
+    map: &'a mut A,
+    content: Value,
+}
+
+impl<'de, 'a, A> KeyAccess<'de> for BufferedKey<'a, A>
+where
+    A: MapAccess<'de>,
+{
+    type Error = A::Error;
+    type Content = BufferedContent<A::Error>;
+
+    fn key<T>(self, seed: T) -> Result<(T::Value, Self::Content), A::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        let key = self.map.next_value_seed(seed)?;
+        Ok((key, BufferedContent(self.content, PhantomData)))
+    }
+}
+
+struct BufferedContent<E>(Value, PhantomData<E>);
+
+impl<'de, E> ContentAccess<'de> for BufferedContent<E>
+where
+    E: serde::de::Error,
+{
+    type Error = E;
+
+    fn content<T>(self, seed: T) -> Result<T::Value, E>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        seed.deserialize(ValueDeserializer::<E>::new(self.0))
+    }
+}
+
+/// A serializer that writes enum variants as keyed values and forwards
+/// everything else.
+///
+/// See the [module documentation](self) for details.
+pub struct KeyedSerializer<S> {
+    type_name: &'static str,
+    field_names: &'static [&'static str; 2],
+    serializer: S,
+}
+
+impl<S> KeyedSerializer<S> {
+    /// Wraps the serializer, writing enum variants as structs with the given
+    /// type and field names.
+    pub fn new(
+        type_name: &'static str,
+        field_names: &'static [&'static str; 2],
+        serializer: S,
+    ) -> KeyedSerializer<S> {
+        KeyedSerializer {
+            type_name,
+            field_names,
+            serializer,
+        }
+    }
+
+    /// Gets the wrapped serializer.
+    pub fn into_inner(self) -> S {
+        self.serializer
+    }
+}
+
+/// Will serialize an enum as a keyed value, using the variant name as the
+/// key.
+pub fn serialize_enum_with_key<S, T>(
+    type_name: &'static str,
+    field_names: &'static [&'static str; 2],
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: ?Sized + Serialize,
+{
+    value.serialize(KeyedSerializer::new(type_name, field_names, serializer))
+}
+
+macro_rules! forward_to_inner_ser {
+    ($($method:ident($($arg:ident: $ty:ty),*))*) => {
+        $(
+            fn $method(self, $($arg: $ty),*) -> Result<S::Ok, S::Error> {
+                self.serializer.$method($($arg),*)
+            }
+        )*
+    };
+}
+
+impl<S> Serializer for KeyedSerializer<S>
+where
+    S: Serializer,
+{
+    type Ok = S::Ok;
+    type Error = S::Error;
+    type SerializeSeq = S::SerializeSeq;
+    type SerializeTuple = S::SerializeTuple;
+    type SerializeTupleStruct = S::SerializeTupleStruct;
+    type SerializeTupleVariant = KeyedTupleVariant<S>;
+    type SerializeMap = S::SerializeMap;
+    type SerializeStruct = S::SerializeStruct;
+    type SerializeStructVariant = KeyedStructVariant<S>;
+
+    forward_to_inner_ser! {
+        serialize_bool(v: bool)
+        serialize_i8(v: i8)
+        serialize_i16(v: i16)
+        serialize_i32(v: i32)
+        serialize_i64(v: i64)
+        serialize_i128(v: i128)
+        serialize_u8(v: u8)
+        serialize_u16(v: u16)
+        serialize_u32(v: u32)
+        serialize_u64(v: u64)
+        serialize_u128(v: u128)
+        serialize_f32(v: f32)
+        serialize_f64(v: f64)
+        serialize_char(v: char)
+        serialize_str(v: &str)
+        serialize_bytes(v: &[u8])
+        serialize_none()
+        serialize_unit()
+        serialize_unit_struct(name: &'static str)
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<S::Ok, S::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.serializer.serialize_some(value)
+    }
+
+    fn serialize_newtype_struct<T>(self, name: &'static str, value: &T) -> Result<S::Ok, S::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.serializer.serialize_newtype_struct(name, value)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<S::SerializeSeq, S::Error> {
+        self.serializer.serialize_seq(len)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<S::SerializeTuple, S::Error> {
+        self.serializer.serialize_tuple(len)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<S::SerializeTupleStruct, S::Error> {
+        self.serializer.serialize_tuple_struct(name, len)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<S::SerializeMap, S::Error> {
+        self.serializer.serialize_map(len)
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<S::SerializeStruct, S::Error> {
+        self.serializer.serialize_struct(name, len)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<S::Ok, S::Error> {
+        serialize_with_key(
+            self.type_name,
+            self.field_names,
+            variant,
+            &(),
+            self.serializer,
+        )
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        serialize_with_key(
+            self.type_name,
+            self.field_names,
+            variant,
+            value,
+            self.serializer,
+        )
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<KeyedTupleVariant<S>, S::Error> {
+        Ok(KeyedTupleVariant {
+            human_readable: self.is_human_readable(),
+            serializer: self,
+            variant,
+            fields: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<KeyedStructVariant<S>, S::Error> {
+        Ok(KeyedStructVariant {
+            human_readable: self.is_human_readable(),
+            serializer: self,
+            variant,
+            fields: Vec::with_capacity(len),
+        })
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.serializer.is_human_readable()
+    }
+}
+
+/// Buffers the fields of a tuple variant to write them as the value.
+pub struct KeyedTupleVariant<S> {
+    serializer: KeyedSerializer<S>,
+    human_readable: bool,
+    variant: &'static str,
+    fields: Vec<Content>,
+}
+
+impl<S> SerializeTupleVariant for KeyedTupleVariant<S>
+where
+    S: Serializer,
+{
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), S::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.fields.push(to_content(value, self.human_readable)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        let KeyedSerializer {
+            type_name,
+            field_names,
+            serializer,
+        } = self.serializer;
+        serialize_with_key(
+            type_name,
+            field_names,
+            self.variant,
+            &Content::Tuple(self.fields),
+            serializer,
+        )
+    }
+}
+
+/// Buffers the fields of a struct variant to write them as the value.
+pub struct KeyedStructVariant<S> {
+    serializer: KeyedSerializer<S>,
+    human_readable: bool,
+    variant: &'static str,
+    fields: Fields,
+}
+
+impl<S> SerializeStructVariant for KeyedStructVariant<S>
+where
+    S: Serializer,
+{
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), S::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        let value = to_content(value, self.human_readable)?;
+        self.fields.push((key, Some(value)));
+        Ok(())
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), S::Error> {
+        self.fields.push((key, None));
+        Ok(())
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        let KeyedSerializer {
+            type_name,
+            field_names,
+            serializer,
+        } = self.serializer;
+        serialize_with_key(
+            type_name,
+            field_names,
+            self.variant,
+            &Content::Struct(self.variant, self.fields),
+            serializer,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Shape {
+        Circle { radius: f64 },
+        Count(u32),
+        Pair(i32, i32),
+        Empty,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Drawing {
+        #[serde(deserialize_with = "shapes")]
+        shapes: Vec<Shape>,
+    }
+
+    fn shapes<'de, D>(deserializer: D) -> Result<Vec<Shape>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Keyed(#[serde(deserialize_with = "keyed_shape")] Shape);
+
+        fn keyed_shape<'de, D>(deserializer: D) -> Result<Shape, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            deserialize_enum_by_key("Shape", &["id", "data"], deserializer)
+        }
+
+        Vec::<Keyed>::deserialize(deserializer).map(|v| v.into_iter().map(|k| k.0).collect())
+    }
+
+    fn read(json: &str) -> Result<Shape, serde_json::Error> {
+        let mut deserializer = serde_json::Deserializer::from_str(json);
+        Shape::deserialize(KeyedDeserializer::new(
+            "Shape",
+            &["id", "data"],
+            &mut deserializer,
+        ))
+    }
+
+    #[test]
+    fn keyed_enums_read_in_any_field_order() {
+        for json in [
+            r#"{"id":"Circle","data":{"radius":2.0}}"#,
+            r#"{"data":{"radius":2.0},"id":"Circle"}"#,
+            r#"["Circle",{"radius":2.0}]"#,
+            r#"{"other":1,"id":"Circle","data":{"radius":2.0}}"#,
+        ] {
+            assert_eq!(read(json).unwrap(), Shape::Circle { radius: 2.0 });
+        }
+
+        for json in [
+            r#"{"id":"Pair","data":[1,-2]}"#,
+            r#"{"data":[1,-2],"id":"Pair"}"#,
+            r#"["Pair",[1,-2]]"#,
+        ] {
+            assert_eq!(read(json).unwrap(), Shape::Pair(1, -2));
+        }
+
+        for json in [
+            r#"{"id":"Empty"}"#,
+            r#"{"data":null,"id":"Empty"}"#,
+            r#"["Empty",null]"#,
+        ] {
+            assert_eq!(read(json).unwrap(), Shape::Empty);
+        }
+
+        let drawing: Drawing = serde_json::from_str(
+            r#"{"shapes":[{"id":"Count","data":3},{"data":4,"id":"Count"},["Empty",null]]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            drawing.shapes,
+            [Shape::Count(3), Shape::Count(4), Shape::Empty]
+        );
+    }
+
+    #[test]
+    fn keyed_enums_return_errors() {
+        let error = read(r#"{"id":"Square","data":1}"#).err().unwrap();
+        assert!(error.to_string().contains("unknown variant `Square`"));
+
+        let error = read(r#"{"id":"Count"}"#).err().unwrap();
+        assert!(error.to_string().contains("missing field `data`"));
+
+        let error = read(r#"{"data":3}"#).err().unwrap();
+        assert!(error.to_string().contains("missing field `id`"));
+
+        let error = read(r#"{"id":"Count","data":3,"id":"Count"}"#)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("duplicate field `id`"));
+
+        let error = read(r#"["Count"]"#).err().unwrap();
+        assert!(error.to_string().contains("invalid length 1"));
+    }
+
+    fn write(shape: &Shape) -> String {
+        let mut json = Vec::new();
+        serialize_enum_with_key(
+            "Shape",
+            &["id", "data"],
+            shape,
+            &mut serde_json::Serializer::new(&mut json),
+        )
+        .unwrap();
+        String::from_utf8(json).unwrap()
+    }
+
+    #[test]
+    fn keyed_enums_write_the_keyed_shape() {
+        for (shape, expected) in [
+            (
+                Shape::Circle { radius: 2.0 },
+                r#"{"id":"Circle","data":{"radius":2.0}}"#,
+            ),
+            (Shape::Count(3), r#"{"id":"Count","data":3}"#),
+            (Shape::Pair(1, -2), r#"{"id":"Pair","data":[1,-2]}"#),
+            (Shape::Empty, r#"{"id":"Empty","data":null}"#),
+        ] {
+            let json = write(&shape);
+            assert_eq!(json, expected);
+            assert_eq!(read(&json).unwrap(), shape);
+        }
+
+        #[derive(Serialize)]
+        struct Circle {
+            radius: f64,
+        }
+
+        let mut json = Vec::new();
+        crate::serialize_with_key(
+            "Shape",
+            &["id", "data"],
+            "Circle",
+            &Circle { radius: 2.0 },
+            &mut serde_json::Serializer::new(&mut json),
+        )
+        .unwrap();
+        assert_eq!(json, write(&Shape::Circle { radius: 2.0 }).as_bytes());
+    }
+
+    #[test]
+    fn keyed_enums_write_the_same_bytes_as_structs() {
+        use std::net::Ipv4Addr;
+
+        #[derive(Serialize)]
+        enum Node {
+            Host { ip: Ipv4Addr, port: Option<u16> },
+            Link(Ipv4Addr, Ipv4Addr),
+        }
+
+        #[derive(Serialize)]
+        struct Host {
+            ip: Ipv4Addr,
+            port: Option<u16>,
+        }
+
+        let ip = Ipv4Addr::new(10, 0, 0, 1);
+        let bincode = bincode::DefaultOptions::new();
+
+        let mut bytes = Vec::new();
+        serialize_enum_with_key(
+            "Node",
+            &["id", "data"],
+            &Node::Host { ip, port: Some(80) },
+            &mut bincode::Serializer::new(&mut bytes, bincode),
+        )
+        .unwrap();
+
+        let mut expected = Vec::new();
+        crate::serialize_with_key(
+            "Node",
+            &["id", "data"],
+            "Host",
+            &Host { ip, port: Some(80) },
+            &mut bincode::Serializer::new(&mut expected, bincode),
+        )
+        .unwrap();
+        assert_eq!(bytes, expected);
+
+        let mut bytes = Vec::new();
+        serialize_enum_with_key(
+            "Node",
+            &["id", "data"],
+            &Node::Link(ip, ip),
+            &mut bincode::Serializer::new(&mut bytes, bincode),
+        )
+        .unwrap();
+
+        let mut expected = Vec::new();
+        crate::serialize_with_key(
+            "Node",
+            &["id", "data"],
+            "Link",
+            &(ip, ip),
+            &mut bincode::Serializer::new(&mut expected, bincode),
+        )
+        .unwrap();
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn keyed_enums_round_trip_in_binary_formats() {
+        for shape in [
+            Shape::Circle { radius: 2.0 },
+            Shape::Count(3),
+            Shape::Pair(1, -2),
+            Shape::Empty,
+        ] {
+            let mut bytes = Vec::new();
+            serialize_enum_with_key(
+                "Shape",
+                &["id", "data"],
+                &shape,
+                &mut bincode::Serializer::new(&mut bytes, bincode::DefaultOptions::new()),
+            )
+            .unwrap();
+
+            let mut deserializer =
+                bincode::Deserializer::from_slice(&bytes, bincode::DefaultOptions::new());
+            let read: Shape =
+                deserialize_enum_by_key("Shape", &["id", "data"], &mut deserializer).unwrap();
+            assert_eq!(read, shape);
+
+            let bytes = postcard::to_stdvec(&KeyedShape(&shape)).unwrap();
+            let read: Shape = postcard::from_bytes::<ReadShape>(&bytes).unwrap().0;
+            assert_eq!(read, shape);
+        }
+
+        struct KeyedShape<'a>(&'a Shape);
+
+        impl Serialize for KeyedShape<'_> {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                serialize_enum_with_key("Shape", &["id", "data"], self.0, serializer)
+            }
+        }
+
+        #[derive(Deserialize)]
+        struct ReadShape(#[serde(deserialize_with = "read_shape")] Shape);
+
+        fn read_shape<'de, D>(deserializer: D) -> Result<Shape, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            deserialize_enum_by_key("Shape", &["id", "data"], deserializer)
+        }
+    }
+}
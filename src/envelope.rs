@@ -0,0 +1,505 @@
+//! Representation of keyed values as a key and length-prefixed bytes.
+//!
+//! Formats that aren't self-describing, like `bincode` or `postcard`, can't
+//! skip over a value without knowing its type. An unknown key is then fatal
+//! for the rest of the input. Instead, this writes the key followed by the
+//! value encoded separately as bytes with a [`Codec`], so the bytes can
+//! always be read regardless of the key.
+//!
+//! With [`Keyed<Enveloped<R, C>>`](crate::Keyed), values are encoded and
+//! decoded immediately. Reading an [`Envelope`] instead keeps the key and the
+//! encoded bytes, so entries with unknown keys can be skipped or written back
+//! unchanged, and known ones decoded later.
+//!
+//! ```
+//! use bincode::Options;
+//! use keyedes::envelope::{Codec, Envelope};
+//! use keyedes::Error;
+//!
+//! struct Bincode;
+//!
+//! impl Codec for Bincode {
+//!     fn encode(value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, Error> {
+//!         bincode::DefaultOptions::new()
+//!             .serialize(value)
+//!             .map_err(serde::ser::Error::custom)
+//!     }
+//!
+//!     fn decode<T, F>(bytes: &[u8], f: F) -> Result<T, Error>
+//!     where
+//!         F: FnOnce(&mut dyn erased_serde::Deserializer) -> Result<T, Error>,
+//!     {
+//!         let mut deserializer =
+//!             bincode::Deserializer::from_slice(bytes, bincode::DefaultOptions::new());
+//!         f(&mut <dyn erased_serde::Deserializer>::erase(&mut deserializer))
+//!     }
+//! }
+//!
+//! let envelope = Envelope::encode::<Bincode, _>("point".to_string(), &(1u8, 2u8)).unwrap();
+//! let point: (u8, u8) = envelope
+//!     .decode::<Bincode, _, _>(|_key, deserializer| erased_serde::deserialize(deserializer))
+//!     .unwrap();
+//! assert_eq!(point, (1, 2));
+//! ```
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{Error as _, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Error as _, SerializeStruct};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::adapters::Representation;
+use crate::private::{
+    Bytes, BytesSeed, ErasedSerdeSerializeWrapper, TagContentOtherField,
+    TagContentOtherFieldVisitor,
+};
+use crate::{Error, KeyedValue, Registry};
+
+/// A nested encoding for the bytes of an envelope.
+pub trait Codec {
+    /// Encodes the value to bytes.
+    fn encode(value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, Error>;
+
+    /// Calls `f` with a deserializer for the bytes.
+    fn decode<T, F>(bytes: &[u8], f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut dyn erased_serde::Deserializer) -> Result<T, Error>;
+}
+
+/// Marker for serializing objects of `R` in envelopes encoded with `C`.
+///
+/// See the [module documentation](self) for details.
+pub struct Enveloped<R, C>(PhantomData<(R, C)>);
+
+/// A key with its value still encoded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Envelope<K> {
+    pub key: K,
+    pub payload: Vec<u8>,
+}
+
+const TYPE_NAME: &str = "Envelope";
+const FIELD_NAMES: &[&str; 2] = &["key", "payload"];
+
+impl<K> Envelope<K> {
+    /// Encodes the value with `C` into a new envelope.
+    pub fn encode<C, V>(key: K, value: &V) -> Result<Envelope<K>, Error>
+    where
+        C: Codec,
+        V: ?Sized + erased_serde::Serialize,
+    {
+        Ok(Envelope {
+            key,
+            payload: C::encode(&ErasedSerdeSerializeWrapper(value))?,
+        })
+    }
+
+    /// Will call `f` with the key and a deserializer for the payload decoded
+    /// with `C`.
+    pub fn decode<C, T, F>(&self, f: F) -> Result<T, Error>
+    where
+        C: Codec,
+        F: FnOnce(&K, &mut dyn erased_serde::Deserializer) -> Result<T, Error>,
+    {
+        C::decode(&self.payload, |deserializer| f(&self.key, deserializer))
+    }
+
+    /// Decodes the payload with `C` into an object of the registry `R`.
+    pub fn decode_object<R, C>(&self) -> Result<Box<R::Object>, Error>
+    where
+        R: Registry<Key = K>,
+        C: Codec,
+        K: Clone,
+    {
+        self.decode::<C, _, _>(|key, deserializer| R::deserialize(key.clone(), deserializer))
+    }
+}
+
+impl<K: Serialize> Serialize for Envelope<K> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_envelope(TYPE_NAME, FIELD_NAMES, &self.key, &self.payload, serializer)
+    }
+}
+
+impl<'de, K: Deserialize<'de>> Deserialize<'de> for Envelope<K> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_envelope(TYPE_NAME, FIELD_NAMES, deserializer)
+    }
+}
+
+/// Will serialize a struct with the key and the value encoded with `C` as
+/// bytes.
+pub fn serialize_with_envelope<C, S, K, V>(
+    type_name: &'static str,
+    field_names: &'static [&'static str; 2],
+    key: &K,
+    value: &V,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    C: Codec,
+    K: ?Sized + Serialize,
+    V: ?Sized + erased_serde::Serialize,
+    S: Serializer,
+{
+    let payload = C::encode(&ErasedSerdeSerializeWrapper(value)).map_err(S::Error::custom)?;
+    serialize_envelope(type_name, field_names, key, &payload, serializer)
+}
+
+/// Will deserialize a struct with the key and the value encoded with `C` as
+/// bytes.
+///
+/// The function `f` will be called with the deserialized key and a
+/// deserializer for the decoded value. Since the bytes are read before that,
+/// the fields may be in either order.
+pub fn deserialize_from_envelope<'de, C, D, K, V, F>(
+    type_name: &'static str,
+    field_names: &'static [&'static str; 2],
+    f: F,
+    deserializer: D,
+) -> Result<V, D::Error>
+where
+    C: Codec,
+    D: Deserializer<'de>,
+    K: Deserialize<'de>,
+    F: Fn(K, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+{
+    let Envelope { key, payload } = deserialize_envelope(type_name, field_names, deserializer)?;
+    C::decode(&payload, |deserializer| f(key, deserializer)).map_err(D::Error::custom)
+}
+
+fn serialize_envelope<S, K>(
+    type_name: &'static str,
+    field_names: &'static [&'static str; 2],
+    key: &K,
+    payload: &[u8],
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    K: ?Sized + Serialize,
+    S: Serializer,
+{
+    let mut state = serializer.serialize_struct(type_name, 2)?;
+    state.serialize_field(field_names[0], key)?;
+    state.serialize_field(field_names[1], &Bytes(payload))?;
+    state.end()
+}
+
+fn deserialize_envelope<'de, D, K>(
+    type_name: &'static str,
+    field_names: &'static [&'static str; 2],
+    deserializer: D,
+) -> Result<Envelope<K>, D::Error>
+where
+    D: Deserializer<'de>,
+    K: Deserialize<'de>,
+{
+    deserializer.deserialize_struct(
+        type_name,
+        field_names,
+        EnvelopeVisitor {
+            field_names,
+            _dummy: PhantomData,
+        },
+    )
+}
+
+struct EnvelopeVisitor<K> {
+    field_names: &'static [&'static str; 2],
+    _dummy: PhantomData<fn() -> K>,
+}
+
+impl<'de, K> Visitor<'de> for EnvelopeVisitor<K>
+where
+    K: Deserialize<'de>,
+{
+    type Value = Envelope<K>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("key and encoded payload")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let key = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+        let payload = seq
+            .next_element_seed(BytesSeed)?
+            .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+        Ok(Envelope { key, payload })
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let [key_name, payload_name] = *self.field_names;
+        let mut key = None;
+        let mut payload = None;
+        while let Some(field) = map.next_key_seed(TagContentOtherFieldVisitor {
+            tag: key_name,
+            content: payload_name,
+        })? {
+            match field {
+                TagContentOtherField::Tag if key.is_some() => {
+                    return Err(A::Error::duplicate_field(key_name))
+                }
+                TagContentOtherField::Tag => key = Some(map.next_value()?),
+                TagContentOtherField::Content if payload.is_some() => {
+                    return Err(A::Error::duplicate_field(payload_name))
+                }
+                TagContentOtherField::Content => payload = Some(map.next_value_seed(BytesSeed)?),
+                TagContentOtherField::Other => {
+                    map.next_value::<serde::de::IgnoredAny>()?;
+                }
+            }
+        }
+
+        Ok(Envelope {
+            key: key.ok_or_else(|| A::Error::missing_field(key_name))?,
+            payload: payload.ok_or_else(|| A::Error::missing_field(payload_name))?,
+        })
+    }
+}
+
+impl<R, C> Representation for Enveloped<R, C>
+where
+    R: Registry,
+    C: Codec,
+{
+    type Object = R::Object;
+
+    fn serialize_object<S>(object: &R::Object, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_with_envelope::<C, _, _, _>(
+            R::TYPE_NAME,
+            R::FIELD_NAMES,
+            &R::key(object),
+            object,
+            serializer,
+        )
+    }
+
+    fn deserialize_object<'de, D>(deserializer: D) -> Result<Box<R::Object>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_from_envelope::<C, _, _, _, _>(
+            R::TYPE_NAME,
+            R::FIELD_NAMES,
+            R::deserialize,
+            deserializer,
+        )
+    }
+}
+
+/// Envelopes are read and written with the type and field names of `R`, so
+/// they can be used in place of objects of `R` without decoding them.
+impl<R, C> KeyedValue<Enveloped<R, C>> for Envelope<R::Key>
+where
+    R: Registry,
+{
+    fn serialize_keyed<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_envelope(
+            R::TYPE_NAME,
+            R::FIELD_NAMES,
+            &self.key,
+            &self.payload,
+            serializer,
+        )
+    }
+
+    fn deserialize_keyed<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_envelope(R::TYPE_NAME, R::FIELD_NAMES, deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use once_cell::sync::Lazy;
+
+    use crate::testing::Bincode;
+    use crate::{deserialize_into_boxed_trait, DesFnSync, Keyed};
+
+    struct Postcard;
+
+    impl Codec for Postcard {
+        fn encode(value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, Error> {
+            postcard::to_stdvec(value).map_err(serde::ser::Error::custom)
+        }
+
+        fn decode<T, F>(bytes: &[u8], f: F) -> Result<T, Error>
+        where
+            F: FnOnce(&mut dyn erased_serde::Deserializer) -> Result<T, Error>,
+        {
+            let mut deserializer = postcard::Deserializer::from_bytes(bytes);
+            f(&mut <dyn erased_serde::Deserializer>::erase(
+                &mut deserializer,
+            ))
+        }
+    }
+
+    trait TestTrait: erased_serde::Serialize {
+        fn key(&self) -> String;
+        fn name(&self) -> String;
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct TestStructA {
+        name: String,
+    }
+
+    impl TestTrait for TestStructA {
+        fn key(&self) -> String {
+            "A".to_string()
+        }
+
+        fn name(&self) -> String {
+            self.name.clone()
+        }
+    }
+
+    #[derive(Serialize)]
+    struct Unregistered(u32, u32);
+
+    impl TestTrait for Unregistered {
+        fn key(&self) -> String {
+            "new".to_string()
+        }
+
+        fn name(&self) -> String {
+            "unregistered".to_string()
+        }
+    }
+
+    static MAP: Lazy<HashMap<String, DesFnSync<Box<dyn TestTrait>>>> = Lazy::new(|| {
+        let mut map = HashMap::<String, DesFnSync<Box<dyn TestTrait>>>::new();
+        map.insert("A".to_string(), deserialize_into_boxed_trait!(TestStructA));
+        map
+    });
+
+    struct TestRegistry;
+
+    impl Registry for TestRegistry {
+        type Object = dyn TestTrait;
+        type Key = String;
+
+        const TYPE_NAME: &'static str = "Box<dyn TestTrait>";
+        const FIELD_NAMES: &'static [&'static str; 2] = &["id", "data"];
+
+        fn key(object: &dyn TestTrait) -> String {
+            object.key()
+        }
+
+        fn deserialize(
+            key: String,
+            deserializer: &mut dyn erased_serde::Deserializer,
+        ) -> Result<Box<dyn TestTrait>, Error> {
+            MAP.get(&key)
+                .ok_or_else(crate::unknown_key)
+                .and_then(|f| f(deserializer))
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Container<C: Codec> {
+        #[serde(with = "Keyed::<Enveloped<TestRegistry, C>>")]
+        items: Vec<Box<dyn TestTrait>>,
+        #[serde(with = "Keyed::<Enveloped<TestRegistry, C>>")]
+        shared: Arc<dyn TestTrait>,
+        #[serde(skip)]
+        _codec: PhantomData<C>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct RawContainer {
+        #[serde(with = "Keyed::<Enveloped<TestRegistry, Bincode>>")]
+        items: Vec<Envelope<String>>,
+        #[serde(with = "Keyed::<Enveloped<TestRegistry, Bincode>>")]
+        shared: Envelope<String>,
+    }
+
+    fn test_container<C: Codec>(items: Vec<Box<dyn TestTrait>>) -> Container<C> {
+        Container {
+            items,
+            shared: Arc::new(TestStructA {
+                name: "Pizza".to_string(),
+            }),
+            _codec: PhantomData,
+        }
+    }
+
+    #[test]
+    fn enveloped_values_round_trip() {
+        let container = test_container::<Postcard>(vec![Box::new(TestStructA {
+            name: "chuck norris".to_string(),
+        })]);
+        let bytes = postcard::to_stdvec(&container).unwrap();
+        let container: Container<Postcard> = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(container.items[0].name(), "chuck norris");
+        assert_eq!(container.shared.name(), "Pizza");
+
+        let json = serde_json::to_string(&test_container::<Postcard>(Vec::new())).unwrap();
+        assert_eq!(
+            json,
+            r#"{"items":[],"shared":{"id":"A","data":[5,80,105,122,122,97]}}"#
+        );
+        let json = r#"{"items":[],"shared":{"data":[5,80,105,122,122,97],"id":"A"}}"#;
+        let container: Container<Postcard> = serde_json::from_str(json).unwrap();
+        assert_eq!(container.shared.name(), "Pizza");
+    }
+
+    #[test]
+    fn unknown_envelopes_can_be_skipped_and_preserved() {
+        let container = test_container::<Bincode>(vec![
+            Box::new(TestStructA {
+                name: "chuck norris".to_string(),
+            }),
+            Box::new(Unregistered(1, 2)),
+            Box::new(TestStructA {
+                name: "Broccoli".to_string(),
+            }),
+        ]);
+        let bytes = bincode::serialize(&container).unwrap();
+
+        let error = bincode::deserialize::<Container<Bincode>>(&bytes)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("unknown deserialization key"));
+
+        let raw: RawContainer = bincode::deserialize(&bytes).unwrap();
+        let names: Vec<String> = raw
+            .items
+            .iter()
+            .filter_map(|envelope| envelope.decode_object::<TestRegistry, Bincode>().ok())
+            .map(|object| object.name())
+            .collect();
+        assert_eq!(names, ["chuck norris", "Broccoli"]);
+        assert_eq!(raw.items[1].key, "new");
+
+        assert_eq!(bincode::serialize(&raw).unwrap(), bytes);
+    }
+}
@@ -0,0 +1,411 @@
+//! Golden files of example values to guard against breaking stored data.
+//!
+//! [`GoldenFiles`] collects example values for keys of a [`Registry`], from
+//! the [`Example`] impls of their types or given directly, and writes them
+//! keyed with [`serialize_with_key`] in every format it's given, as
+//! [`Codec`]s. Files are named after the key, the index of the example and
+//! the extension of the format:
+//!
+//! ```text
+//! tests/golden/circle.0.json
+//! tests/golden/circle.0.bin
+//! ```
+//!
+//! Characters of the key other than ASCII letters, digits, `-` and `_` are
+//! percent-encoded, so `a/b` is written to `a%2Fb.0.json`. Keys whose file
+//! names would only differ by case are reported as a failure, since they
+//! would be the same file on some file systems.
+//!
+//! Running [`check`](GoldenFiles::check) writes the files that are missing
+//! and reads back the others through [`deserialize_by_key`] with the
+//! deserialization function of the registry. The objects read must have the
+//! same key and be equal to the current examples, which are compared by
+//! what they serialize to, so changes that would break reading stored data
+//! make the check fail.
+//! Golden files are never overwritten; delete them to write them anew.
+//!
+//! ```
+//! # use keyedes::envelope::Codec;
+//! # use keyedes::golden::{Example, GoldenFiles};
+//! # use keyedes::Registry;
+//! # fn check<Shapes, Json, Circle>()
+//! # where
+//! #     Shapes: Registry<Key = String>,
+//! #     Json: Codec,
+//! #     Circle: Example + 'static,
+//! # {
+//! GoldenFiles::<Shapes>::new("tests/golden")
+//!     .format::<Json>("json")
+//!     .add::<Circle>("circle".to_owned())
+//!     .check()
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::{Display, Write};
+use std::fs;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use serde::ser::Error as _;
+use serde::{Serialize, Serializer};
+
+use crate::envelope::Codec;
+use crate::private::ErasedSerdeSerializeWrapper;
+use crate::{deserialize_by_key, serialize_with_key, Error, Registry};
+
+/// Example values of a type, to be written to golden files.
+pub trait Example: erased_serde::Serialize + Sized {
+    fn examples() -> Vec<Self>;
+}
+
+/// Writes and checks golden files of the example values of keys of `R`.
+///
+/// See the [module documentation](self) for details.
+pub struct GoldenFiles<R: Registry> {
+    dir: PathBuf,
+    formats: Vec<Format<R>>,
+    keys: Vec<(R::Key, Examples)>,
+}
+
+type Examples = Vec<Box<dyn erased_serde::Serialize>>;
+type EncodeFn<K> = fn(&K, &dyn erased_serde::Serialize) -> Result<Vec<u8>, Error>;
+type DecodeFn<O> = fn(&[u8]) -> Result<Box<O>, Error>;
+
+struct Format<R: Registry> {
+    extension: &'static str,
+    encode: EncodeFn<R::Key>,
+    decode: DecodeFn<R::Object>,
+}
+
+impl<R> GoldenFiles<R>
+where
+    R: Registry,
+    R::Key: Display,
+{
+    /// Will write and read golden files in `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> GoldenFiles<R> {
+        GoldenFiles {
+            dir: dir.into(),
+            formats: Vec::new(),
+            keys: Vec::new(),
+        }
+    }
+
+    /// Adds a format to encode the files with, naming them with `extension`.
+    pub fn format<C>(&mut self, extension: &'static str) -> &mut Self
+    where
+        C: Codec,
+    {
+        self.formats.push(Format {
+            extension,
+            encode: encode::<R, C>,
+            decode: decode::<R, C>,
+        });
+        self
+    }
+
+    /// Adds the [`examples`](Example::examples) of `T` for `key`.
+    pub fn add<T>(&mut self, key: R::Key) -> &mut Self
+    where
+        T: Example + 'static,
+    {
+        self.add_examples(key, T::examples())
+    }
+
+    /// Adds the `examples` for `key`.
+    pub fn add_examples<T>(&mut self, key: R::Key, examples: Vec<T>) -> &mut Self
+    where
+        T: erased_serde::Serialize + 'static,
+    {
+        let examples = examples
+            .into_iter()
+            .map(|example| Box::new(example) as Box<dyn erased_serde::Serialize>)
+            .collect();
+        self.keys.push((key, examples));
+        self
+    }
+
+    /// Writes the golden files that are missing and checks the others.
+    ///
+    /// This will return an error listing every file that couldn't be
+    /// written, read or didn't match its example.
+    pub fn check(&self) -> Result<(), Error> {
+        fs::create_dir_all(&self.dir).map_err(Error::custom)?;
+
+        let mut failures = Vec::new();
+        let mut names = HashMap::new();
+        for (key, examples) in &self.keys {
+            for (index, example) in examples.iter().enumerate() {
+                for format in &self.formats {
+                    let name = file_name(key, index, format.extension);
+                    if let Some(other) = names.insert(name.to_lowercase(), name.clone()) {
+                        failures.push(format!(
+                            "{}: same file as {}",
+                            self.dir.join(&name).display(),
+                            other
+                        ));
+                        continue;
+                    }
+
+                    let path = self.dir.join(name);
+                    if let Err(error) = check_file(&path, key, &**example, format) {
+                        failures.push(format!("{}: {}", path.display(), error));
+                    }
+                }
+            }
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::custom(format_args!(
+                "golden files failed:\n{}",
+                failures.join("\n")
+            )))
+        }
+    }
+}
+
+fn file_name(key: &impl Display, index: usize, extension: &str) -> String {
+    let mut name = String::new();
+    for byte in key.to_string().bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' {
+            name.push(char::from(byte));
+        } else {
+            let _ = write!(name, "%{:02X}", byte);
+        }
+    }
+    format!("{}.{}.{}", name, index, extension)
+}
+
+fn check_file<R>(
+    path: &Path,
+    key: &R::Key,
+    example: &dyn erased_serde::Serialize,
+    format: &Format<R>,
+) -> Result<(), Error>
+where
+    R: Registry,
+    R::Key: Display,
+{
+    if !path.exists() {
+        let bytes = (format.encode)(key, example)?;
+        return fs::write(path, bytes).map_err(Error::custom);
+    }
+
+    let bytes = fs::read(path).map_err(Error::custom)?;
+    let object = (format.decode)(&bytes)?;
+    let read_key = R::key(&*object);
+    if serde_value::to_value(&read_key).map_err(Error::custom)?
+        != serde_value::to_value(key).map_err(Error::custom)?
+    {
+        return Err(Error::custom(format_args!(
+            "read key `{}`, expected `{}`",
+            read_key, key
+        )));
+    }
+
+    let read =
+        serde_value::to_value(ErasedSerdeSerializeWrapper(&*object)).map_err(Error::custom)?;
+    let expected =
+        serde_value::to_value(ErasedSerdeSerializeWrapper(example)).map_err(Error::custom)?;
+    if read == expected {
+        Ok(())
+    } else {
+        Err(Error::custom(format_args!(
+            "read {:?}, expected {:?}",
+            read, expected
+        )))
+    }
+}
+
+/// A value to be serialized with its key.
+struct KeyedExample<'a, R: Registry> {
+    key: &'a R::Key,
+    value: &'a dyn erased_serde::Serialize,
+    _registry: PhantomData<R>,
+}
+
+impl<'a, R> Serialize for KeyedExample<'a, R>
+where
+    R: Registry,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_with_key(
+            R::TYPE_NAME,
+            R::FIELD_NAMES,
+            self.key,
+            self.value,
+            serializer,
+        )
+    }
+}
+
+fn encode<R, C>(key: &R::Key, value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, Error>
+where
+    R: Registry,
+    C: Codec,
+{
+    C::encode(&KeyedExample::<R> {
+        key,
+        value,
+        _registry: PhantomData,
+    })
+}
+
+fn decode<R, C>(bytes: &[u8]) -> Result<Box<R::Object>, Error>
+where
+    R: Registry,
+    C: Codec,
+{
+    C::decode(bytes, |deserializer| {
+        deserialize_by_key(R::TYPE_NAME, R::FIELD_NAMES, R::deserialize, deserializer)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{Bincode, Circle, Json, Label, Shapes};
+
+    impl Example for Circle {
+        fn examples() -> Vec<Self> {
+            vec![
+                Circle {
+                    radius: 1.0,
+                    label: None,
+                },
+                Circle {
+                    radius: 2.5,
+                    label: Some("big".to_owned()),
+                },
+            ]
+        }
+    }
+
+    #[derive(Serialize)]
+    struct LabelV2(String, u32);
+
+    #[test]
+    fn writes_and_checks_golden_files() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut golden = GoldenFiles::<Shapes>::new(dir.path());
+        golden
+            .format::<Json>("json")
+            .format::<Bincode>("bin")
+            .add::<Circle>("circle".to_owned())
+            .add_examples("label".to_owned(), vec![Label("a/b".to_owned())]);
+        golden.check().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("circle.1.json")).unwrap(),
+            r#"{"kind":"circle","shape":{"radius":2.5,"label":"big"}}"#
+        );
+        let mut files: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                "circle.0.bin",
+                "circle.0.json",
+                "circle.1.bin",
+                "circle.1.json",
+                "label.0.bin",
+                "label.0.json",
+            ]
+        );
+
+        golden.check().unwrap();
+    }
+
+    #[test]
+    fn reports_golden_files_that_changed() {
+        let dir = tempfile::tempdir().unwrap();
+
+        GoldenFiles::<Shapes>::new(dir.path())
+            .format::<Json>("json")
+            .add_examples("label".to_owned(), vec![Label("a".to_owned())])
+            .check()
+            .unwrap();
+
+        let error = GoldenFiles::<Shapes>::new(dir.path())
+            .format::<Json>("json")
+            .add_examples("label".to_owned(), vec![LabelV2("a".to_owned(), 1)])
+            .check()
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("label.0.json: read"), "{}", error);
+
+        fs::write(dir.path().join("label.0.json"), r#"{"kind":"square"}"#).unwrap();
+        let error = GoldenFiles::<Shapes>::new(dir.path())
+            .format::<Json>("json")
+            .add_examples("label".to_owned(), vec![Label("a".to_owned())])
+            .check()
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("unknown deserialization key"), "{}", error);
+
+        let circle = || Circle {
+            radius: 1.0,
+            label: None,
+        };
+        fs::write(
+            dir.path().join("label.0.json"),
+            r#"{"kind":"circle","shape":{"radius":1.0,"label":null}}"#,
+        )
+        .unwrap();
+        let error = GoldenFiles::<Shapes>::new(dir.path())
+            .format::<Json>("json")
+            .add_examples("label".to_owned(), vec![circle()])
+            .check()
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            error.contains("label.0.json: read key `circle`, expected `label`"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn names_golden_files_without_collisions() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let label = || vec![Label("a".to_owned())];
+        let error = GoldenFiles::<Shapes>::new(dir.path())
+            .format::<Json>("json")
+            .add_examples("a/b".to_owned(), label())
+            .add_examples("a.b".to_owned(), label())
+            .add_examples("a_b".to_owned(), label())
+            .add_examples("A_b".to_owned(), label())
+            .check()
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            error.contains("A_b.0.json: same file as a_b.0.json"),
+            "{}",
+            error
+        );
+
+        let mut files: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["a%2Eb.0.json", "a%2Fb.0.json", "a_b.0.json"]);
+    }
+}
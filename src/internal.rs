@@ -0,0 +1,768 @@
+//! Representation of keyed values with the key next to the value's fields.
+//!
+//! Instead of a struct with key and value fields, the key is written in a
+//! tag field of a map along with the fields of the value, like serde's
+//! internally tagged enums:
+//!
+//! ```json
+//! {"type": "circle", "radius": 2.0}
+//! ```
+//!
+//! Only values that are structs or maps can be written this way, along with
+//! units, unit structs and `None`, which are written as the tag alone. Since
+//! the tag may come after the other fields, they are buffered before the key
+//! is known, so this requires a self-describing format. The buffered fields
+//! are read back in their original order, so a value still sees repeated
+//! fields and can reject them.
+//!
+//! ```
+//! # mod outer {
+//! use serde::{Deserializer, Serializer};
+//!
+//! trait Shape: erased_serde::Serialize {
+//!     fn key(&self) -> &'static str;
+//! }
+//!
+//! pub fn serialize<S>(value: &Box<dyn Shape>, serializer: S) -> Result<S::Ok, S::Error>
+//! where
+//!     S: Serializer,
+//! {
+//!     keyedes::internal::serialize_with_tag("type", value.key(), &**value, serializer)
+//! }
+//!
+//! pub fn deserialize<'de, D>(deserializer: D) -> Result<Box<dyn Shape>, D::Error>
+//! where
+//!     D: Deserializer<'de>,
+//! {
+//!     keyedes::internal::deserialize_by_tag(
+//!         "type",
+//!         |key: String, deserializer| {
+//!             // look up the key in a map
+//! #           let _ = (key, deserializer);
+//!             Err(keyedes::unknown_key())
+//!         },
+//!         deserializer,
+//!     )
+//! }
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::vec;
+
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{DeserializeSeed, Error as _, MapAccess, Visitor};
+use serde::ser::{Error as _, Impossible, Serialize, SerializeMap, SerializeStruct};
+use serde::{Deserialize, Deserializer, Serializer};
+use serde_value::{Value, ValueDeserializer};
+
+use crate::private::ErasedSerdeSerializeWrapper;
+use crate::Error;
+
+/// Will serialize the value as a map with the key in the `tag` field,
+/// followed by the fields of the value.
+///
+/// Units, unit structs and `None` are written as the tag alone. Other values
+/// that aren't structs or maps will return an error.
+pub fn serialize_with_tag<S, K, V>(
+    tag: &str,
+    key: &K,
+    value: &V,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    K: ?Sized + Serialize,
+    V: ?Sized + erased_serde::Serialize,
+    S: Serializer,
+{
+    serialize_tagged(tag, key, None, value, serializer)
+}
+
+/// Will deserialize a map with the key in the `tag` field, using the
+/// remaining fields as the value.
+///
+/// The function `f` will be called with the deserialized key and a
+/// deserializer for the buffered fields that can be used to get the final
+/// value.
+pub fn deserialize_by_tag<'de, D, K, V, F>(tag: &str, f: F, deserializer: D) -> Result<V, D::Error>
+where
+    D: Deserializer<'de>,
+    K: Deserialize<'de>,
+    F: Fn(K, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+{
+    let TaggedFields { key, fields } = deserialize_tagged(tag, deserializer)?;
+    let key = key.ok_or_else(|| D::Error::custom(format_args!("missing field `{}`", tag)))?;
+
+    f(
+        key,
+        &mut <dyn erased_serde::Deserializer>::erase(
+            FieldsDeserializer::<FieldsMap<D::Error>>::in_order(fields),
+        ),
+    )
+    .map_err(D::Error::custom)
+}
+
+/// Serializes a map with the key in the `tag` field and the value flattened
+/// next to it, or in the `content` field if it isn't a struct or map.
+pub(crate) fn serialize_tagged<S, K, V>(
+    tag: &str,
+    key: &K,
+    content: Option<&str>,
+    value: &V,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    K: ?Sized + Serialize,
+    V: ?Sized + erased_serde::Serialize,
+    S: Serializer,
+{
+    let mut state = serializer.serialize_map(None)?;
+    state.serialize_entry(tag, key)?;
+    ErasedSerdeSerializeWrapper(value).serialize(FlattenSerializer {
+        map: &mut state,
+        content,
+    })?;
+    state.end()
+}
+
+/// The key from the tag field, if there was one, and the other fields.
+pub(crate) struct TaggedFields<K, F> {
+    pub key: Option<K>,
+    pub fields: F,
+}
+
+/// Deserializes a map into the key from the `tag` field and the other fields
+/// buffered.
+///
+/// The fields are added to `F` in the order they were read, including
+/// repeated ones, so it's up to the caller how those are handled.
+pub(crate) fn deserialize_tagged<'de, D, K, F>(
+    tag: &str,
+    deserializer: D,
+) -> Result<TaggedFields<K, F>, D::Error>
+where
+    D: Deserializer<'de>,
+    K: Deserialize<'de>,
+    F: Default + Extend<(Value, Value)>,
+{
+    deserializer.deserialize_map(TaggedVisitor {
+        tag,
+        _dummy: PhantomData,
+    })
+}
+
+struct TaggedVisitor<'a, K, F> {
+    tag: &'a str,
+    _dummy: PhantomData<fn() -> (K, F)>,
+}
+
+impl<'de, 'a, K, F> Visitor<'de> for TaggedVisitor<'a, K, F>
+where
+    K: Deserialize<'de>,
+    F: Default + Extend<(Value, Value)>,
+{
+    type Value = TaggedFields<K, F>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "map with a {:?} field", self.tag)
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut key = None;
+        let mut fields = F::default();
+        while let Some(field) = map.next_key::<String>()? {
+            if field == self.tag {
+                if key.is_some() {
+                    return Err(A::Error::custom(format_args!(
+                        "duplicate field `{}`",
+                        self.tag
+                    )));
+                }
+                key = Some(map.next_value::<K>()?);
+            } else {
+                let value = map.next_value::<Value>()?;
+                fields.extend(Some((Value::String(field), value)));
+            }
+        }
+        Ok(TaggedFields { key, fields })
+    }
+}
+
+/// Deserializes the buffered fields with `D`, reading them as a unit or `None`
+/// when there are none, since that's how [`FlattenSerializer`] writes those.
+pub(crate) struct FieldsDeserializer<D> {
+    deserializer: D,
+    empty: bool,
+}
+
+impl<D> FieldsDeserializer<D> {
+    pub fn new(fields: BTreeMap<Value, Value>, f: impl FnOnce(Value) -> D) -> Self {
+        FieldsDeserializer {
+            empty: fields.is_empty(),
+            deserializer: f(Value::Map(fields)),
+        }
+    }
+}
+
+/// The deserializer returned by [`fields_map`].
+pub(crate) type FieldsMap<E> = MapAccessDeserializer<FieldsAccess<E>>;
+
+impl<E> FieldsDeserializer<FieldsMap<E>> {
+    /// Reads the fields back as a map in the order they were read, so that
+    /// a struct rejects repeated fields like it would without buffering.
+    pub fn in_order(fields: Vec<(Value, Value)>) -> Self {
+        FieldsDeserializer {
+            empty: fields.is_empty(),
+            deserializer: fields_map(fields),
+        }
+    }
+}
+
+/// Reads buffered fields as a map, in the order they were read.
+pub(crate) fn fields_map<E>(fields: Vec<(Value, Value)>) -> FieldsMap<E> {
+    MapAccessDeserializer::new(FieldsAccess {
+        fields: fields.into_iter(),
+        value: None,
+        _error: PhantomData,
+    })
+}
+
+pub(crate) struct FieldsAccess<E> {
+    fields: vec::IntoIter<(Value, Value)>,
+    value: Option<Value>,
+    _error: PhantomData<fn() -> E>,
+}
+
+impl<'de, E> MapAccess<'de> for FieldsAccess<E>
+where
+    E: serde::de::Error,
+{
+    type Error = E;
+
+    fn next_key_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, E>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.fields.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(ValueDeserializer::new(key)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<T>(&mut self, seed: T) -> Result<T::Value, E>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.value.take() {
+            Some(value) => seed.deserialize(ValueDeserializer::new(value)),
+            None => Err(E::custom("value is missing")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.fields.len())
+    }
+}
+
+macro_rules! forward_to_fields {
+    ($($method:ident($($arg:ident: $ty:ty),*))*) => {
+        $(
+            fn $method<V>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value, D::Error>
+            where
+                V: Visitor<'de>,
+            {
+                self.deserializer.$method($($arg,)* visitor)
+            }
+        )*
+    };
+}
+
+impl<'de, D> Deserializer<'de> for FieldsDeserializer<D>
+where
+    D: Deserializer<'de>,
+{
+    type Error = D::Error;
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if self.empty {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if self.empty {
+            visitor.visit_unit()
+        } else {
+            self.deserializer.deserialize_unit(visitor)
+        }
+    }
+
+    fn deserialize_unit_struct<V>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if self.empty {
+            visitor.visit_unit()
+        } else {
+            self.deserializer.deserialize_unit_struct(name, visitor)
+        }
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_fields! {
+        deserialize_any()
+        deserialize_bool()
+        deserialize_i8()
+        deserialize_i16()
+        deserialize_i32()
+        deserialize_i64()
+        deserialize_i128()
+        deserialize_u8()
+        deserialize_u16()
+        deserialize_u32()
+        deserialize_u64()
+        deserialize_u128()
+        deserialize_f32()
+        deserialize_f64()
+        deserialize_char()
+        deserialize_str()
+        deserialize_string()
+        deserialize_bytes()
+        deserialize_byte_buf()
+        deserialize_seq()
+        deserialize_tuple(len: usize)
+        deserialize_tuple_struct(name: &'static str, len: usize)
+        deserialize_map()
+        deserialize_struct(name: &'static str, fields: &'static [&'static str])
+        deserialize_enum(name: &'static str, variants: &'static [&'static str])
+        deserialize_identifier()
+        deserialize_ignored_any()
+    }
+}
+
+/// Writes the fields of a struct or map into another map.
+///
+/// Other values are written to the `content` field if there is one.
+pub(crate) struct FlattenSerializer<'a, M> {
+    pub map: &'a mut M,
+    pub content: Option<&'a str>,
+}
+
+impl<'a, M> FlattenSerializer<'a, M>
+where
+    M: SerializeMap,
+{
+    fn write_content<T>(self, kind: &str, value: &T) -> Result<(), M::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        match self.content {
+            Some(field) => self.map.serialize_entry(field, value),
+            None => Err(Self::unsupported(kind)),
+        }
+    }
+
+    fn unsupported(kind: &str) -> M::Error {
+        M::Error::custom(format_args!("{} can't be written next to a key", kind))
+    }
+}
+
+impl<'a, M> Serializer for FlattenSerializer<'a, M>
+where
+    M: SerializeMap,
+{
+    type Ok = ();
+    type Error = M::Error;
+    type SerializeSeq = Impossible<(), M::Error>;
+    type SerializeTuple = Impossible<(), M::Error>;
+    type SerializeTupleStruct = Impossible<(), M::Error>;
+    type SerializeTupleVariant = Impossible<(), M::Error>;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<(), M::Error>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), M::Error> {
+        self.write_content("a boolean", &v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), M::Error> {
+        self.write_content("an integer", &v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), M::Error> {
+        self.write_content("an integer", &v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), M::Error> {
+        self.write_content("an integer", &v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), M::Error> {
+        self.write_content("an integer", &v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), M::Error> {
+        self.write_content("an integer", &v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), M::Error> {
+        self.write_content("an integer", &v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), M::Error> {
+        self.write_content("an integer", &v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), M::Error> {
+        self.write_content("an integer", &v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), M::Error> {
+        self.write_content("a float", &v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), M::Error> {
+        self.write_content("a float", &v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), M::Error> {
+        self.write_content("a character", &v)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), M::Error> {
+        self.write_content("a string", v)
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<(), M::Error> {
+        Err(Self::unsupported("bytes"))
+    }
+
+    fn serialize_none(self) -> Result<(), M::Error> {
+        Ok(())
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<(), M::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), M::Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), M::Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<(), M::Error> {
+        Err(Self::unsupported("an enum"))
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<(), M::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), M::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        Err(Self::unsupported("an enum"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, M::Error> {
+        Err(Self::unsupported("a sequence"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, M::Error> {
+        Err(Self::unsupported("a tuple"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, M::Error> {
+        Err(Self::unsupported("a tuple struct"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, M::Error> {
+        Err(Self::unsupported("an enum"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, M::Error> {
+        Ok(self)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, M::Error> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, M::Error> {
+        Err(Self::unsupported("an enum"))
+    }
+}
+
+impl<'a, M> SerializeMap for FlattenSerializer<'a, M>
+where
+    M: SerializeMap,
+{
+    type Ok = ();
+    type Error = M::Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), M::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.map.serialize_key(key)
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), M::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.map.serialize_value(value)
+    }
+
+    fn end(self) -> Result<(), M::Error> {
+        Ok(())
+    }
+}
+
+impl<'a, M> SerializeStruct for FlattenSerializer<'a, M>
+where
+    M: SerializeMap,
+{
+    type Ok = ();
+    type Error = M::Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), M::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.map.serialize_entry(key, value)
+    }
+
+    fn end(self) -> Result<(), M::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use once_cell::sync::Lazy;
+
+    use crate::{deserialize_into_boxed_trait, DesFnSync};
+
+    trait TestTrait: erased_serde::Serialize {
+        fn key(&self) -> &'static str;
+        fn name(&self) -> String;
+    }
+
+    #[derive(serde::Serialize, Deserialize)]
+    struct TestStructA {
+        name: String,
+    }
+
+    impl TestTrait for TestStructA {
+        fn key(&self) -> &'static str {
+            "A"
+        }
+
+        fn name(&self) -> String {
+            self.name.clone()
+        }
+    }
+
+    #[derive(serde::Serialize, Deserialize)]
+    struct TestStructB(String);
+
+    impl TestTrait for TestStructB {
+        fn key(&self) -> &'static str {
+            "B"
+        }
+
+        fn name(&self) -> String {
+            self.0.clone()
+        }
+    }
+
+    #[derive(serde::Serialize, Deserialize)]
+    struct TestStructU;
+
+    impl TestTrait for TestStructU {
+        fn key(&self) -> &'static str {
+            "U"
+        }
+
+        fn name(&self) -> String {
+            "unit".to_string()
+        }
+    }
+
+    #[derive(serde::Serialize, Deserialize)]
+    struct TestStructO(Option<TestStructA>);
+
+    impl TestTrait for TestStructO {
+        fn key(&self) -> &'static str {
+            "O"
+        }
+
+        fn name(&self) -> String {
+            self.0
+                .as_ref()
+                .map_or("none".to_string(), |a| a.name.clone())
+        }
+    }
+
+    static MAP: Lazy<HashMap<String, DesFnSync<Box<dyn TestTrait>>>> = Lazy::new(|| {
+        let mut map = HashMap::<String, DesFnSync<Box<dyn TestTrait>>>::new();
+        map.insert("A".to_string(), deserialize_into_boxed_trait!(TestStructA));
+        map.insert("U".to_string(), deserialize_into_boxed_trait!(TestStructU));
+        map.insert("O".to_string(), deserialize_into_boxed_trait!(TestStructO));
+        map
+    });
+
+    fn to_json(value: &dyn TestTrait) -> Result<String, serde_json::Error> {
+        let mut json = Vec::new();
+        serialize_with_tag(
+            "type",
+            value.key(),
+            value,
+            &mut serde_json::Serializer::new(&mut json),
+        )?;
+        Ok(String::from_utf8(json).unwrap())
+    }
+
+    fn from_json(json: &str) -> Result<Box<dyn TestTrait>, serde_json::Error> {
+        deserialize_by_tag(
+            "type",
+            |key: String, deserializer| {
+                MAP.get(&key)
+                    .ok_or_else(crate::unknown_key)
+                    .and_then(|f| f(deserializer))
+            },
+            &mut serde_json::Deserializer::from_str(json),
+        )
+    }
+
+    #[test]
+    fn internally_tagged_values_round_trip() {
+        let value = TestStructA {
+            name: "chuck norris".to_string(),
+        };
+        let json = to_json(&value).unwrap();
+        assert_eq!(json, r#"{"type":"A","name":"chuck norris"}"#);
+        assert_eq!(from_json(&json).unwrap().name(), "chuck norris");
+
+        let json = r#"{"name":"chuck norris","type":"A"}"#;
+        assert_eq!(from_json(json).unwrap().name(), "chuck norris");
+    }
+
+    #[test]
+    fn internally_tagged_units_round_trip() {
+        let json = to_json(&TestStructU).unwrap();
+        assert_eq!(json, r#"{"type":"U"}"#);
+        assert_eq!(from_json(&json).unwrap().name(), "unit");
+
+        let json = to_json(&TestStructO(None)).unwrap();
+        assert_eq!(json, r#"{"type":"O"}"#);
+        assert_eq!(from_json(&json).unwrap().name(), "none");
+
+        let value = TestStructO(Some(TestStructA {
+            name: "chuck norris".to_string(),
+        }));
+        let json = to_json(&value).unwrap();
+        assert_eq!(json, r#"{"type":"O","name":"chuck norris"}"#);
+        assert_eq!(from_json(&json).unwrap().name(), "chuck norris");
+
+        let error = from_json(r#"{"type":"U","name":"chuck norris"}"#)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("expected unit struct"));
+    }
+
+    #[test]
+    fn internally_tagged_values_return_errors() {
+        let error = to_json(&TestStructB("Broccoli".to_string())).unwrap_err();
+        assert_eq!(error.to_string(), "a string can't be written next to a key");
+
+        let error = from_json(r#"{"name":"chuck norris"}"#).err().unwrap();
+        assert!(error.to_string().contains("missing field `type`"));
+
+        let error = from_json(r#"{"type":"A","name":"chuck","name":"norris"}"#)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("duplicate field `name`"));
+
+        let error = from_json(r#"{"type":"B","name":"chuck norris"}"#)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("unknown deserialization key"));
+    }
+}
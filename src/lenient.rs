@@ -0,0 +1,774 @@
+//! Lenient deserialization of collections of keyed values.
+//!
+//! Deserializing a `Vec` or `HashMap` of keyed values normally fails as a
+//! whole if any single element has an unknown key or malformed data. The
+//! functions here deserialize each element separately so that a failing
+//! element can be skipped, replaced, or recorded alongside the elements that
+//! succeeded.
+//!
+//! For a field holding objects of a [`Registry`](crate::Registry), the
+//! [`Lenient`] adapter does the same from `#[serde(with = "...")]` or
+//! `#[serde_as(as = "...")]`. Otherwise, the functions take the same
+//! parameters as [`deserialize_by_key()`].
+//!
+//! Each element is buffered before it is deserialized, so a failure doesn't leave the underlying deserializer in a broken state.
+//! Errors in the outer collection itself (for example, a syntax error in the
+//! input) are still fatal.
+//!
+//! Buffering reads each element with `deserialize_any`, so this requires a
+//! self-describing format like JSON or YAML; formats like bincode or
+//! postcard can't be read this way. Elements are buffered in full with no
+//! bound on their size or nesting, unlike the content buffered by
+//! [`deserialize_by_key_with_limits()`](crate::deserialize_by_key_with_limits()),
+//! so untrusted input should be bounded by the format or the input size.
+//!
+//! ```
+//! # mod outer {
+//! use serde::Deserialize;
+//!
+//! trait Plugin: erased_serde::Serialize {}
+//!
+//! fn deserialize_plugins<'de, D>(deserializer: D) -> Result<Vec<Box<dyn Plugin>>, D::Error>
+//! where
+//!     D: serde::Deserializer<'de>,
+//! {
+//!     keyedes::lenient::deserialize_vec(
+//!         "Box<dyn Plugin>",
+//!         &["id", "data"],
+//!         |key: String, deserializer| -> Result<Box<dyn Plugin>, keyedes::Error> {
+//!             // look up the key in a map
+//! #           let _ = (key, deserializer);
+//!             Err(keyedes::unknown_key())
+//!         },
+//!         keyedes::lenient::skip,
+//!         deserializer,
+//!     )
+//! }
+//!
+//! #[derive(Deserialize)]
+//! struct Config {
+//!     #[serde(deserialize_with = "deserialize_plugins")]
+//!     plugins: Vec<Box<dyn Plugin>>,
+//! }
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+
+use serde::de::{DeserializeOwned, MapAccess, SeqAccess, Visitor};
+use serde::{Deserializer, Serialize, Serializer};
+use serde_value::{Value, ValueDeserializer};
+
+use crate::{deserialize_by_key, Error, KeyedValue};
+
+/// An element of a collection that failed to deserialize.
+#[derive(Debug)]
+pub struct ElementError<I> {
+    /// The position of the element: its index in a sequence or its key in a
+    /// map.
+    pub index: I,
+    pub error: Error,
+}
+
+/// The result of deserializing a collection while recording failures.
+#[derive(Debug)]
+pub struct Partial<C, I> {
+    /// The elements that deserialized successfully.
+    pub values: C,
+    /// The elements that failed, in the order they were encountered.
+    pub errors: Vec<ElementError<I>>,
+}
+
+/// Recovery function that drops any element that failed to deserialize.
+pub fn skip<I, V>(_index: I, _error: Error) -> Option<V> {
+    None
+}
+
+/// Will deserialize a sequence of keyed values, calling `recover` for any
+/// element that fails.
+///
+/// The function `recover` is given the index of the failed element and its
+/// error. It may return a placeholder to put in its place or `None` to skip
+/// it. See [`deserialize_by_key()`] for the meaning of the other parameters.
+pub fn deserialize_vec<'de, D, K, V, F, R>(
+    type_name: &'static str,
+    field_names: &'static [&'static str; 2],
+    f: F,
+    mut recover: R,
+    deserializer: D,
+) -> Result<Vec<V>, D::Error>
+where
+    D: Deserializer<'de>,
+    K: DeserializeOwned,
+    F: Fn(K, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+    R: FnMut(usize, Error) -> Option<V>,
+{
+    deserializer.deserialize_seq(LenientSeqVisitor {
+        deserialize_element: |value| deserialize_buffered(type_name, field_names, &f, value),
+        on_element: |values: &mut Vec<V>, index, result| match result {
+            Ok(value) => values.push(value),
+            Err(error) => values.extend(recover(index, error)),
+        },
+        _dummy: PhantomData,
+    })
+}
+
+/// Will deserialize a sequence of keyed values, collecting any elements that
+/// fail alongside the ones that succeed.
+///
+/// See [`deserialize_by_key()`] for the meaning of the parameters.
+pub fn deserialize_vec_partial<'de, D, K, V, F>(
+    type_name: &'static str,
+    field_names: &'static [&'static str; 2],
+    f: F,
+    deserializer: D,
+) -> Result<Partial<Vec<V>, usize>, D::Error>
+where
+    D: Deserializer<'de>,
+    K: DeserializeOwned,
+    F: Fn(K, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+{
+    let mut errors = Vec::new();
+    let values = deserialize_vec(
+        type_name,
+        field_names,
+        f,
+        |index, error| {
+            errors.push(ElementError { index, error });
+            None
+        },
+        deserializer,
+    )?;
+
+    Ok(Partial { values, errors })
+}
+
+/// Will deserialize a map of keyed values, calling `recover` for any entry
+/// whose value fails.
+///
+/// The function `recover` is given the map key of the failed entry and its
+/// error. It may return a placeholder to put in its place or `None` to skip
+/// it. See [`deserialize_by_key()`] for the meaning of the other parameters.
+pub fn deserialize_map<'de, D, M, K, V, F, R>(
+    type_name: &'static str,
+    field_names: &'static [&'static str; 2],
+    f: F,
+    mut recover: R,
+    deserializer: D,
+) -> Result<HashMap<M, V>, D::Error>
+where
+    D: Deserializer<'de>,
+    M: DeserializeOwned + Eq + Hash,
+    K: DeserializeOwned,
+    F: Fn(K, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+    R: FnMut(&M, Error) -> Option<V>,
+{
+    deserializer.deserialize_map(LenientMapVisitor {
+        deserialize_element: |value| deserialize_buffered(type_name, field_names, &f, value),
+        on_entry: |values: &mut HashMap<M, V>, key, result| match result {
+            Ok(value) => {
+                values.insert(key, value);
+            }
+            Err(error) => {
+                if let Some(value) = recover(&key, error) {
+                    values.insert(key, value);
+                }
+            }
+        },
+        _dummy: PhantomData,
+        _collection: PhantomData,
+    })
+}
+
+/// Will deserialize a map of keyed values, collecting any entries that fail
+/// alongside the ones that succeed.
+///
+/// See [`deserialize_by_key()`] for the meaning of the parameters.
+pub fn deserialize_map_partial<'de, D, M, K, V, F>(
+    type_name: &'static str,
+    field_names: &'static [&'static str; 2],
+    f: F,
+    deserializer: D,
+) -> Result<Partial<HashMap<M, V>, M>, D::Error>
+where
+    D: Deserializer<'de>,
+    M: DeserializeOwned + Eq + Hash,
+    K: DeserializeOwned,
+    F: Fn(K, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+{
+    deserializer.deserialize_map(LenientMapVisitor {
+        deserialize_element: |value| deserialize_buffered(type_name, field_names, &f, value),
+        on_entry: |partial: &mut Partial<HashMap<M, V>, M>, key, result| match result {
+            Ok(value) => {
+                partial.values.insert(key, value);
+            }
+            Err(error) => partial.errors.push(ElementError { index: key, error }),
+        },
+        _dummy: PhantomData,
+        _collection: PhantomData,
+    })
+}
+
+/// Adapter for leniently deserializing a collection through the registry `R`.
+///
+/// Use it with `#[serde(with = "keyedes::Lenient::<R>")]` on a `Vec` or
+/// `HashMap` of values implementing [`KeyedValue<R>`]. Elements that fail
+/// are passed to `P`, which skips them by default; see [`Recover`] for
+/// putting a placeholder in their place. A field of type [`Partial`] of
+/// either collection records the failures instead. Collections are
+/// serialized just like [`Keyed`](crate::Keyed) would, without the errors of
+/// a `Partial`.
+pub struct Lenient<R, P = Skip>(PhantomData<(R, P)>);
+
+impl<R, P> Lenient<R, P> {
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: LenientValue<R, P>,
+        S: Serializer,
+    {
+        value.serialize_lenient(serializer)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: LenientValue<R, P>,
+        D: Deserializer<'de>,
+    {
+        T::deserialize_lenient(deserializer)
+    }
+}
+
+/// Recovery from an element of type `T` that failed to deserialize, for use
+/// with [`Lenient`].
+///
+/// ```
+/// # mod outer {
+/// # use serde::{Deserialize, Serialize};
+/// # trait Plugin: erased_serde::Serialize {}
+/// # struct Plugins;
+/// # impl keyedes::Registry for Plugins {
+/// #     type Object = dyn Plugin;
+/// #     type Key = String;
+/// #     const TYPE_NAME: &'static str = "Box<dyn Plugin>";
+/// #     const FIELD_NAMES: &'static [&'static str; 2] = &["id", "data"];
+/// #     fn key(_: &dyn Plugin) -> String { unimplemented!() }
+/// #     fn deserialize(
+/// #         _: String,
+/// #         _: &mut dyn erased_serde::Deserializer,
+/// #     ) -> Result<Box<dyn Plugin>, keyedes::Error> {
+/// #         Err(keyedes::unknown_key())
+/// #     }
+/// # }
+/// use keyedes::lenient::Recover;
+/// use keyedes::Lenient;
+///
+/// #[derive(Serialize)]
+/// struct Broken {
+///     reason: String,
+/// }
+///
+/// impl Plugin for Broken {}
+///
+/// struct ReplaceWithBroken;
+///
+/// impl Recover<Box<dyn Plugin>> for ReplaceWithBroken {
+///     fn recover(error: keyedes::Error) -> Option<Box<dyn Plugin>> {
+///         Some(Box::new(Broken {
+///             reason: error.to_string(),
+///         }))
+///     }
+/// }
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct Config {
+///     #[serde(with = "Lenient::<Plugins, ReplaceWithBroken>")]
+///     plugins: Vec<Box<dyn Plugin>>,
+/// }
+/// # }
+/// ```
+pub trait Recover<T> {
+    /// Returns a placeholder to put in place of the failed element, or
+    /// `None` to skip it.
+    fn recover(error: Error) -> Option<T>;
+}
+
+/// Recovery that drops any element that failed to deserialize.
+pub struct Skip;
+
+impl<T> Recover<T> for Skip {
+    fn recover(_error: Error) -> Option<T> {
+        None
+    }
+}
+
+/// A collection that can be deserialized through the registry `R` while
+/// recovering failed elements with `P`.
+pub trait LenientValue<R, P>: Sized {
+    fn serialize_lenient<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer;
+
+    fn deserialize_lenient<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>;
+}
+
+impl<R, P, T> LenientValue<R, P> for Vec<T>
+where
+    T: KeyedValue<R>,
+    P: Recover<T>,
+{
+    fn serialize_lenient<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        KeyedValue::<R>::serialize_keyed(self, serializer)
+    }
+
+    fn deserialize_lenient<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(LenientSeqVisitor {
+            deserialize_element: deserialize_keyed_buffered::<R, T>,
+            on_element: |values: &mut Vec<T>, _, result| match result {
+                Ok(value) => values.push(value),
+                Err(error) => values.extend(P::recover(error)),
+            },
+            _dummy: PhantomData,
+        })
+    }
+}
+
+impl<R, P, M, T, H> LenientValue<R, P> for HashMap<M, T, H>
+where
+    M: Serialize + DeserializeOwned + Eq + Hash,
+    T: KeyedValue<R>,
+    P: Recover<T>,
+    H: BuildHasher + Default,
+{
+    fn serialize_lenient<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        KeyedValue::<R>::serialize_keyed(self, serializer)
+    }
+
+    fn deserialize_lenient<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(LenientMapVisitor {
+            deserialize_element: deserialize_keyed_buffered::<R, T>,
+            on_entry: |values: &mut HashMap<M, T, H>, key, result| match result {
+                Ok(value) => {
+                    values.insert(key, value);
+                }
+                Err(error) => {
+                    if let Some(value) = P::recover(error) {
+                        values.insert(key, value);
+                    }
+                }
+            },
+            _dummy: PhantomData,
+            _collection: PhantomData,
+        })
+    }
+}
+
+impl<R, P, T> LenientValue<R, P> for Partial<Vec<T>, usize>
+where
+    T: KeyedValue<R>,
+{
+    fn serialize_lenient<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.values.serialize_keyed(serializer)
+    }
+
+    fn deserialize_lenient<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut errors = Vec::new();
+        let values = deserializer.deserialize_seq(LenientSeqVisitor {
+            deserialize_element: deserialize_keyed_buffered::<R, T>,
+            on_element: |values: &mut Vec<T>, index, result| match result {
+                Ok(value) => values.push(value),
+                Err(error) => errors.push(ElementError { index, error }),
+            },
+            _dummy: PhantomData,
+        })?;
+
+        Ok(Partial { values, errors })
+    }
+}
+
+impl<R, P, M, T, H> LenientValue<R, P> for Partial<HashMap<M, T, H>, M>
+where
+    M: Serialize + DeserializeOwned + Eq + Hash,
+    T: KeyedValue<R>,
+    H: BuildHasher + Default,
+{
+    fn serialize_lenient<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.values.serialize_keyed(serializer)
+    }
+
+    fn deserialize_lenient<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(LenientMapVisitor {
+            deserialize_element: deserialize_keyed_buffered::<R, T>,
+            on_entry: |partial: &mut Self, key, result| match result {
+                Ok(value) => {
+                    partial.values.insert(key, value);
+                }
+                Err(error) => partial.errors.push(ElementError { index: key, error }),
+            },
+            _dummy: PhantomData,
+            _collection: PhantomData,
+        })
+    }
+}
+
+#[cfg(feature = "serde_with")]
+impl<R, P, T: LenientValue<R, P>> serde_with::SerializeAs<T> for Lenient<R, P> {
+    fn serialize_as<S>(source: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        source.serialize_lenient(serializer)
+    }
+}
+
+#[cfg(feature = "serde_with")]
+impl<'de, R, P, T: LenientValue<R, P>> serde_with::DeserializeAs<'de, T> for Lenient<R, P> {
+    fn deserialize_as<D>(deserializer: D) -> Result<T, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        T::deserialize_lenient(deserializer)
+    }
+}
+
+fn deserialize_keyed_buffered<R, T: KeyedValue<R>>(value: Value) -> Result<T, Error> {
+    T::deserialize_keyed(ValueDeserializer::<Error>::new(value))
+}
+
+fn deserialize_buffered<K, V, F>(
+    type_name: &'static str,
+    field_names: &'static [&'static str; 2],
+    f: &F,
+    value: Value,
+) -> Result<V, Error>
+where
+    K: DeserializeOwned,
+    F: Fn(K, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+{
+    deserialize_by_key(
+        type_name,
+        field_names,
+        f,
+        ValueDeserializer::<Error>::new(value),
+    )
+}
+
+struct LenientSeqVisitor<V, F, E> {
+    deserialize_element: F,
+    on_element: E,
+    _dummy: PhantomData<fn() -> V>,
+}
+
+impl<'de, V, F, E> Visitor<'de> for LenientSeqVisitor<V, F, E>
+where
+    F: Fn(Value) -> Result<V, Error>,
+    E: FnMut(&mut Vec<V>, usize, Result<V, Error>),
+{
+    type Value = Vec<V>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a sequence")
+    }
+
+    fn visit_seq<A>(mut self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        let mut index = 0;
+        while let Some(value) = seq.next_element::<Value>()? {
+            let result = (self.deserialize_element)(value);
+            (self.on_element)(&mut values, index, result);
+            index += 1;
+        }
+
+        Ok(values)
+    }
+}
+
+struct LenientMapVisitor<M, V, F, E, C> {
+    deserialize_element: F,
+    on_entry: E,
+    _dummy: PhantomData<fn(M) -> V>,
+    _collection: PhantomData<fn() -> C>,
+}
+
+impl<'de, M, V, F, E, C> Visitor<'de> for LenientMapVisitor<M, V, F, E, C>
+where
+    M: DeserializeOwned,
+    F: Fn(Value) -> Result<V, Error>,
+    E: FnMut(&mut C, M, Result<V, Error>),
+    C: Default,
+{
+    type Value = C;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map")
+    }
+
+    fn visit_map<A>(mut self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut values = C::default();
+        while let Some((key, value)) = map.next_entry::<M, Value>()? {
+            let result = (self.deserialize_element)(value);
+            (self.on_entry)(&mut values, key, result);
+        }
+
+        Ok(values)
+    }
+}
+
+impl<C: Default, I> Default for Partial<C, I> {
+    fn default() -> Self {
+        Partial {
+            values: C::default(),
+            errors: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::Deserialize;
+
+    use crate::testing::{Label, Shape, Shapes};
+    use crate::{deserialize_into_boxed_trait, DesFn};
+
+    trait TestTrait {
+        fn name(&self) -> String;
+    }
+
+    #[derive(Deserialize)]
+    struct TestStructA {
+        name: String,
+    }
+
+    impl TestTrait for TestStructA {
+        fn name(&self) -> String {
+            self.name.clone()
+        }
+    }
+
+    struct Placeholder;
+
+    impl TestTrait for Placeholder {
+        fn name(&self) -> String {
+            "placeholder".to_string()
+        }
+    }
+
+    fn test_map() -> HashMap<String, DesFn<Box<dyn TestTrait>>> {
+        let mut map = HashMap::<String, DesFn<Box<dyn TestTrait>>>::new();
+        map.insert("A".to_string(), deserialize_into_boxed_trait!(TestStructA));
+        map
+    }
+
+    const JSON_SEQ: &str = r#"[
+        {"id":"A","data":{"name":"first"}},
+        {"id":"Z","data":{"name":"unknown"}},
+        {"id":"A","data":{"nom":"malformed"}},
+        {"data":{"name":"last"},"id":"A"}
+    ]"#;
+
+    #[test]
+    fn deserialize_vec_skips_or_replaces_failed_elements() {
+        let map = test_map();
+        let lookup = |key: String, deserializer: &mut dyn erased_serde::Deserializer| {
+            map.get(&key)
+                .ok_or_else(crate::unknown_key)
+                .and_then(|f| f(deserializer))
+        };
+
+        let mut deserializer = serde_json::Deserializer::from_str(JSON_SEQ);
+        let result = deserialize_vec(
+            "Box<dyn TestTrait>",
+            &["id", "data"],
+            lookup,
+            skip,
+            &mut deserializer,
+        )
+        .unwrap();
+        let names: Vec<_> = result.iter().map(|v| v.name()).collect();
+        assert_eq!(names, ["first", "last"]);
+
+        let mut deserializer = serde_json::Deserializer::from_str(JSON_SEQ);
+        let result = deserialize_vec(
+            "Box<dyn TestTrait>",
+            &["id", "data"],
+            lookup,
+            |_, _| Some(Box::new(Placeholder) as Box<dyn TestTrait>),
+            &mut deserializer,
+        )
+        .unwrap();
+        let names: Vec<_> = result.iter().map(|v| v.name()).collect();
+        assert_eq!(names, ["first", "placeholder", "placeholder", "last"]);
+    }
+
+    #[test]
+    fn deserialize_partial_collects_failed_elements() {
+        let map = test_map();
+        let lookup = |key: String, deserializer: &mut dyn erased_serde::Deserializer| {
+            map.get(&key)
+                .ok_or_else(crate::unknown_key)
+                .and_then(|f| f(deserializer))
+        };
+
+        let mut deserializer = serde_json::Deserializer::from_str(JSON_SEQ);
+        let result = deserialize_vec_partial(
+            "Box<dyn TestTrait>",
+            &["id", "data"],
+            lookup,
+            &mut deserializer,
+        )
+        .unwrap();
+        assert_eq!(result.values.len(), 2);
+        let indices: Vec<_> = result.errors.iter().map(|e| e.index).collect();
+        assert_eq!(indices, [1, 2]);
+
+        let json = r#"{
+            "good":{"id":"A","data":{"name":"good"}},
+            "bad":{"id":"A","data":[]},
+            "ugly":{"id":"Z","data":null}
+        }"#;
+
+        let mut deserializer = serde_json::Deserializer::from_str(json);
+        let mut result = deserialize_map_partial::<_, String, _, _, _>(
+            "Box<dyn TestTrait>",
+            &["id", "data"],
+            lookup,
+            &mut deserializer,
+        )
+        .unwrap();
+        assert_eq!(result.values.len(), 1);
+        assert_eq!(result.values["good"].name(), "good");
+        result.errors.sort_by(|a, b| a.index.cmp(&b.index));
+        let indices: Vec<_> = result.errors.iter().map(|e| e.index.as_str()).collect();
+        assert_eq!(indices, ["bad", "ugly"]);
+    }
+
+    struct AsLabel;
+
+    impl Recover<Box<dyn Shape>> for AsLabel {
+        fn recover(_error: Error) -> Option<Box<dyn Shape>> {
+            Some(Box::new(Label("broken".to_string())))
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Drawing {
+        #[serde(with = "Lenient::<Shapes>")]
+        skipped: Vec<Box<dyn Shape>>,
+        #[serde(with = "Lenient::<Shapes, AsLabel>")]
+        replaced: HashMap<String, Box<dyn Shape>>,
+        #[serde(with = "Lenient::<Shapes>")]
+        collected: Partial<Vec<Box<dyn Shape>>, usize>,
+    }
+
+    #[test]
+    fn lenient_adapter_skips_replaces_or_collects_failed_elements() {
+        let json = r#"{
+            "skipped":[
+                {"kind":"label","shape":"first"},
+                {"kind":"star","shape":null},
+                {"kind":"label","shape":"last"}
+            ],
+            "replaced":{"bad":{"kind":"circle","shape":{}}},
+            "collected":[{"kind":"circle","shape":"round"},{"kind":"path","shape":"Closed"}]
+        }"#;
+        let drawing: Drawing = serde_json::from_str(json).unwrap();
+        let names: Vec<_> = drawing.skipped.iter().map(|v| v.name()).collect();
+        assert_eq!(names, ["label", "label"]);
+        assert_eq!(drawing.replaced["bad"].name(), "label");
+        assert_eq!(drawing.collected.values.len(), 1);
+        assert_eq!(drawing.collected.values[0].name(), "path");
+        let indices: Vec<_> = drawing.collected.errors.iter().map(|e| e.index).collect();
+        assert_eq!(indices, [0]);
+
+        assert_eq!(
+            serde_json::to_string(&drawing).unwrap(),
+            concat!(
+                r#"{"skipped":[{"kind":"label","shape":"first"},{"kind":"label","shape":"last"}],"#,
+                r#""replaced":{"bad":{"kind":"label","shape":"broken"}},"#,
+                r#""collected":[{"kind":"path","shape":"Closed"}]}"#,
+            )
+        );
+    }
+
+    #[test]
+    fn lenient_adapter_fails_on_malformed_collection() {
+        let result =
+            serde_json::from_str::<Drawing>(r#"{"skipped":{},"replaced":{},"collected":[]}"#);
+        assert!(result.is_err());
+    }
+
+    #[cfg(feature = "serde_with")]
+    #[test]
+    fn lenient_implements_serde_with_traits() {
+        #[serde_with::serde_as]
+        #[derive(Serialize, Deserialize)]
+        struct Wrapper {
+            #[serde_as(as = "Option<Lenient<Shapes>>")]
+            shapes: Option<Vec<Box<dyn Shape>>>,
+        }
+
+        let json = r#"{"shapes":[{"kind":"label","shape":"x"},{"kind":"star","shape":null}]}"#;
+        let wrapper: Wrapper = serde_json::from_str(json).unwrap();
+        assert_eq!(wrapper.shapes.as_ref().unwrap().len(), 1);
+        assert_eq!(
+            serde_json::to_string(&wrapper).unwrap(),
+            r#"{"shapes":[{"kind":"label","shape":"x"}]}"#
+        );
+    }
+
+    #[test]
+    fn deserialize_fails_on_malformed_collection() {
+        let map = test_map();
+        let lookup = |key: String, deserializer: &mut dyn erased_serde::Deserializer| {
+            map.get(&key)
+                .ok_or_else(crate::unknown_key)
+                .and_then(|f| f(deserializer))
+        };
+
+        let mut deserializer = serde_json::Deserializer::from_str(r#"[{"id":"A","#);
+        let result = deserialize_vec(
+            "Box<dyn TestTrait>",
+            &["id", "data"],
+            lookup,
+            skip,
+            &mut deserializer,
+        );
+        assert!(result.is_err());
+    }
+}
@@ -74,17 +74,83 @@
 
 use std::marker::PhantomData;
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+use crate::limits::Limits;
 use crate::private::{ErasedSerdeSerializeWrapper, KeyValueVisitor};
 
+pub use crate::adapters::{Keyed, KeyedValue};
+pub use crate::lenient::Lenient;
+
+pub mod adapters;
+pub mod any;
+pub mod cbor;
+pub mod compact;
+pub mod compat;
+mod content;
+pub mod embedded;
+pub mod enums;
+pub mod envelope;
+pub mod golden;
+pub mod internal;
+pub mod lenient;
+pub mod limits;
+#[cfg(feature = "rmp-serde")]
+pub mod msgpack;
+pub mod multi;
+pub mod overrides;
 mod private;
+#[cfg(feature = "serde-reflection")]
+pub mod reflection;
+#[cfg(feature = "proptest")]
+pub mod roundtrip;
+#[cfg(feature = "json-schema")]
+pub mod schema;
+pub mod shared;
+#[cfg(feature = "serde-reflection")]
+pub mod snapshot;
+#[cfg(feature = "csv")]
+pub mod tabular;
+#[cfg(test)]
+mod testing;
+mod text;
+#[cfg(feature = "serde-reflection")]
+pub mod typescript;
+pub mod variant;
+pub mod xml;
 
 pub type Error = erased_serde::Error;
 pub type DesFn<T> = Box<dyn Fn(&mut dyn erased_serde::Deserializer) -> Result<T, Error>>;
 pub type DesFnSync<T> =
     Box<dyn Fn(&mut dyn erased_serde::Deserializer) -> Result<T, Error> + Send + Sync>;
 
+/// Describes how a family of objects is serialized with a key and how to get
+/// them back.
+///
+/// This is typically implemented on a unit struct for a `dyn Trait` so that
+/// the [`Keyed`] adapters can be used. The constants are what would be passed
+/// to [`serialize_with_key()`] and [`deserialize_by_key()`].
+pub trait Registry {
+    /// The type of object stored, usually `dyn Trait`.
+    type Object: ?Sized + erased_serde::Serialize;
+
+    /// The type of key stored alongside the object.
+    type Key: Serialize + DeserializeOwned;
+
+    const TYPE_NAME: &'static str;
+    const FIELD_NAMES: &'static [&'static str; 2];
+
+    /// Gets the key for the given object.
+    fn key(object: &Self::Object) -> Self::Key;
+
+    /// Deserializes the object associated with the key.
+    fn deserialize(
+        key: Self::Key,
+        deserializer: &mut dyn erased_serde::Deserializer,
+    ) -> Result<Box<Self::Object>, Error>;
+}
+
 /// Will serialize a struct with the given field names and values.
 pub fn serialize_with_key<S, K, V>(
     type_name: &'static str,
@@ -128,6 +194,40 @@ where
             deserialization_fn: f,
             key_name: field_names[0],
             value_name: field_names[1],
+            text_values: false,
+            limits: Limits::NONE,
+            _dummy: PhantomData,
+        },
+    )
+}
+
+/// Will deserialize a struct with the given field names and values, like
+/// [`deserialize_by_key()`], but fail once the key or the content buffered
+/// before it cross the given [`Limits`].
+///
+/// This should be used instead for untrusted input. See the
+/// [`limits`] module for details.
+pub fn deserialize_by_key_with_limits<'de, D, K, V, F>(
+    type_name: &'static str,
+    field_names: &'static [&'static str; 2],
+    limits: Limits,
+    f: F,
+    deserializer: D,
+) -> Result<V, D::Error>
+where
+    D: Deserializer<'de>,
+    K: Deserialize<'de>,
+    F: Fn(K, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+{
+    deserializer.deserialize_struct(
+        type_name,
+        field_names,
+        KeyValueVisitor {
+            deserialization_fn: f,
+            key_name: field_names[0],
+            value_name: field_names[1],
+            text_values: false,
+            limits,
             _dummy: PhantomData,
         },
     )
@@ -0,0 +1,710 @@
+//! Limits on what is buffered while deserializing keyed values.
+//!
+//! When the content field comes before the key, [`deserialize_by_key()`]
+//! has to buffer the whole content before it can look up the key, which an
+//! untrusted input can make arbitrarily large or deeply nested.
+//! [`deserialize_by_key_with_limits()`] bounds the buffered content and the
+//! key with [`Limits`], and fails with a [`LimitExceeded`] error as soon as
+//! one of them is crossed:
+//!
+//! ```
+//! # use keyedes::limits::{LimitExceeded, Limits};
+//! let limits = Limits {
+//!     max_content_size: 1024,
+//!     max_depth: 2,
+//!     max_key_length: 16,
+//! };
+//!
+//! let error = keyedes::deserialize_by_key_with_limits(
+//!     "Box<dyn Shape>",
+//!     &["kind", "shape"],
+//!     limits,
+//!     |_key: String, _deserializer| Ok(()),
+//!     &mut serde_json::Deserializer::from_str(r#"{"shape": [[[1]]], "kind": "path"}"#),
+//! )
+//! .unwrap_err();
+//! assert_eq!(
+//!     LimitExceeded::from_error(&error),
+//!     Some(LimitExceeded::Depth { limit: 2 })
+//! );
+//! ```
+//!
+//! Content that comes after the key isn't buffered, it is deserialized
+//! directly by the function given for the key, so its limits are those of
+//! the format and the type it's read into.
+//!
+//! The fuzz targets in `fuzz/` check these limits against hostile input and
+//! can be run with `cargo fuzz run visit_map` from the repository.
+//!
+//! [`deserialize_by_key()`]: crate::deserialize_by_key()
+//! [`deserialize_by_key_with_limits()`]: crate::deserialize_by_key_with_limits()
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{DeserializeSeed, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use serde_value::Value;
+
+/// Limits on the key and buffered content of a keyed value.
+///
+/// The [default](Limits::NONE) is no limits at all, like
+/// [`deserialize_by_key()`](crate::deserialize_by_key()).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// The size of the buffered content, counting the bytes of strings and
+    /// byte arrays and one for every other value, including the keys of
+    /// maps.
+    pub max_content_size: usize,
+    /// How many sequences, maps, options and newtypes can be nested in the
+    /// buffered content, so that `0` only allows primitives.
+    pub max_depth: usize,
+    /// The length in bytes of a string or byte array key.
+    pub max_key_length: usize,
+}
+
+impl Limits {
+    /// No limits.
+    pub const NONE: Limits = Limits {
+        max_content_size: usize::MAX,
+        max_depth: usize::MAX,
+        max_key_length: usize::MAX,
+    };
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits::NONE
+    }
+}
+
+/// The limit that was crossed, given as the message of deserialization
+/// errors.
+///
+/// Formats only keep the message of such errors, so use
+/// [`LimitExceeded::from_error()`] to get it back from them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LimitExceeded {
+    /// The buffered content is larger than [`Limits::max_content_size`].
+    ContentSize { limit: usize },
+    /// The buffered content is nested deeper than [`Limits::max_depth`].
+    Depth { limit: usize },
+    /// The key is longer than [`Limits::max_key_length`].
+    KeyLength { limit: usize },
+}
+
+impl LimitExceeded {
+    /// Returns the limit that was crossed if `error` was produced by one.
+    ///
+    /// This recognizes the message of the limit at the start of the error,
+    /// before anything added by the format, like the position in its input.
+    pub fn from_error(error: &dyn fmt::Display) -> Option<LimitExceeded> {
+        let message = error.to_string();
+        let digits = &message[message.find(|c: char| c.is_ascii_digit())?..];
+        let end = digits
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(digits.len());
+        let limit = digits[..end].parse().ok()?;
+
+        [
+            LimitExceeded::ContentSize { limit },
+            LimitExceeded::Depth { limit },
+            LimitExceeded::KeyLength { limit },
+        ]
+        .iter()
+        .copied()
+        .find(|exceeded| message.starts_with(&exceeded.to_string()))
+    }
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LimitExceeded::ContentSize { limit } => {
+                write!(f, "buffered content is larger than {} bytes", limit)
+            }
+            LimitExceeded::Depth { limit } => {
+                write!(f, "buffered content is nested deeper than {} levels", limit)
+            }
+            LimitExceeded::KeyLength { limit } => {
+                write!(f, "key is longer than {} bytes", limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// Deserializes a key, failing if a string or byte array is longer than the
+/// limit.
+pub(crate) struct KeySeed<K> {
+    max_length: usize,
+    _key: PhantomData<fn() -> K>,
+}
+
+impl<K> KeySeed<K> {
+    pub(crate) fn new(limits: &Limits) -> KeySeed<K> {
+        KeySeed {
+            max_length: limits.max_key_length,
+            _key: PhantomData,
+        }
+    }
+}
+
+impl<'de, K> DeserializeSeed<'de> for KeySeed<K>
+where
+    K: Deserialize<'de>,
+{
+    type Value = K;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<K, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if self.max_length == usize::MAX {
+            K::deserialize(deserializer)
+        } else {
+            K::deserialize(KeyDeserializer {
+                deserializer,
+                max_length: self.max_length,
+            })
+        }
+    }
+}
+
+struct KeyDeserializer<D> {
+    deserializer: D,
+    max_length: usize,
+}
+
+macro_rules! forward_key_deserializer {
+    ($($method:ident($($arg:ident: $ty:ty),*))*) => {
+        $(
+            fn $method<V>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value, D::Error>
+            where
+                V: Visitor<'de>,
+            {
+                self.deserializer.$method($($arg,)* KeyVisitor {
+                    visitor,
+                    max_length: self.max_length,
+                })
+            }
+        )*
+    };
+}
+
+impl<'de, D> Deserializer<'de> for KeyDeserializer<D>
+where
+    D: Deserializer<'de>,
+{
+    type Error = D::Error;
+
+    forward_key_deserializer! {
+        deserialize_any() deserialize_bool() deserialize_i8() deserialize_i16()
+        deserialize_i32() deserialize_i64() deserialize_i128() deserialize_u8()
+        deserialize_u16() deserialize_u32() deserialize_u64() deserialize_u128()
+        deserialize_f32() deserialize_f64() deserialize_char() deserialize_str()
+        deserialize_string() deserialize_bytes() deserialize_byte_buf()
+        deserialize_option() deserialize_unit()
+        deserialize_unit_struct(name: &'static str)
+        deserialize_newtype_struct(name: &'static str)
+        deserialize_seq() deserialize_tuple(len: usize)
+        deserialize_tuple_struct(name: &'static str, len: usize)
+        deserialize_map()
+        deserialize_struct(name: &'static str, fields: &'static [&'static str])
+        deserialize_enum(name: &'static str, variants: &'static [&'static str])
+        deserialize_identifier() deserialize_ignored_any()
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.deserializer.is_human_readable()
+    }
+}
+
+struct KeyVisitor<V> {
+    visitor: V,
+    max_length: usize,
+}
+
+impl<V> KeyVisitor<V> {
+    fn check<E>(&self, length: usize) -> Result<(), E>
+    where
+        E: serde::de::Error,
+    {
+        if length > self.max_length {
+            Err(E::custom(LimitExceeded::KeyLength {
+                limit: self.max_length,
+            }))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+macro_rules! forward_key_visitor {
+    ($($method:ident($ty:ty))*) => {
+        $(
+            fn $method<E>(self, v: $ty) -> Result<V::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                self.visitor.$method(v)
+            }
+        )*
+    };
+}
+
+impl<'de, V> Visitor<'de> for KeyVisitor<V>
+where
+    V: Visitor<'de>,
+{
+    type Value = V::Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        self.visitor.expecting(formatter)
+    }
+
+    forward_key_visitor! {
+        visit_bool(bool) visit_i8(i8) visit_i16(i16) visit_i32(i32) visit_i64(i64)
+        visit_i128(i128) visit_u8(u8) visit_u16(u16) visit_u32(u32) visit_u64(u64)
+        visit_u128(u128) visit_f32(f32) visit_f64(f64) visit_char(char)
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<V::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.check(v.len())?;
+        self.visitor.visit_str(v)
+    }
+
+    fn visit_borrowed_str<E>(self, v: &'de str) -> Result<V::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.check(v.len())?;
+        self.visitor.visit_borrowed_str(v)
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<V::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.check(v.len())?;
+        self.visitor.visit_string(v)
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<V::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.check(v.len())?;
+        self.visitor.visit_bytes(v)
+    }
+
+    fn visit_borrowed_bytes<E>(self, v: &'de [u8]) -> Result<V::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.check(v.len())?;
+        self.visitor.visit_borrowed_bytes(v)
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<V::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.check(v.len())?;
+        self.visitor.visit_byte_buf(v)
+    }
+
+    fn visit_none<E>(self) -> Result<V::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.visitor.visit_none()
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<V::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.visitor.visit_some(deserializer)
+    }
+
+    fn visit_unit<E>(self) -> Result<V::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.visitor.visit_unit()
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<V::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.visitor.visit_newtype_struct(deserializer)
+    }
+
+    fn visit_seq<A>(self, seq: A) -> Result<V::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        self.visitor.visit_seq(seq)
+    }
+
+    fn visit_map<A>(self, map: A) -> Result<V::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        self.visitor.visit_map(map)
+    }
+
+    fn visit_enum<A>(self, data: A) -> Result<V::Value, A::Error>
+    where
+        A: serde::de::EnumAccess<'de>,
+    {
+        self.visitor.visit_enum(data)
+    }
+}
+
+/// Buffers content into a [`Value`], failing as soon as it crosses the size
+/// or depth limit.
+pub(crate) struct ContentSeed<'a> {
+    limits: &'a Limits,
+    size: &'a mut usize,
+    depth: usize,
+}
+
+impl<'a> ContentSeed<'a> {
+    pub(crate) fn new(limits: &'a Limits, size: &'a mut usize) -> ContentSeed<'a> {
+        ContentSeed {
+            limits,
+            size,
+            depth: 0,
+        }
+    }
+
+    fn add<E>(&mut self, size: usize) -> Result<(), E>
+    where
+        E: serde::de::Error,
+    {
+        match self.size.checked_add(size) {
+            Some(total) if total <= self.limits.max_content_size => {
+                *self.size = total;
+                Ok(())
+            }
+            _ => Err(E::custom(LimitExceeded::ContentSize {
+                limit: self.limits.max_content_size,
+            })),
+        }
+    }
+
+    /// Accounts for a value containing others and returns the seed for them.
+    fn nested<E>(mut self) -> Result<ContentSeed<'a>, E>
+    where
+        E: serde::de::Error,
+    {
+        if self.depth >= self.limits.max_depth {
+            return Err(E::custom(LimitExceeded::Depth {
+                limit: self.limits.max_depth,
+            }));
+        }
+        self.add(1)?;
+        Ok(ContentSeed {
+            depth: self.depth + 1,
+            ..self
+        })
+    }
+
+    fn reborrow(&mut self) -> ContentSeed<'_> {
+        ContentSeed {
+            limits: self.limits,
+            size: self.size,
+            depth: self.depth,
+        }
+    }
+
+    fn primitive<E>(mut self, value: Value) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.add(1)?;
+        Ok(value)
+    }
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for ContentSeed<'a> {
+    type Value = Value;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for ContentSeed<'a> {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.primitive(Value::Bool(v))
+    }
+
+    fn visit_i8<E>(self, v: i8) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.primitive(Value::I8(v))
+    }
+
+    fn visit_i16<E>(self, v: i16) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.primitive(Value::I16(v))
+    }
+
+    fn visit_i32<E>(self, v: i32) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.primitive(Value::I32(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.primitive(Value::I64(v))
+    }
+
+    fn visit_u8<E>(self, v: u8) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.primitive(Value::U8(v))
+    }
+
+    fn visit_u16<E>(self, v: u16) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.primitive(Value::U16(v))
+    }
+
+    fn visit_u32<E>(self, v: u32) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.primitive(Value::U32(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.primitive(Value::U64(v))
+    }
+
+    fn visit_f32<E>(self, v: f32) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.primitive(Value::F32(v))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.primitive(Value::F64(v))
+    }
+
+    fn visit_char<E>(self, v: char) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.primitive(Value::Char(v))
+    }
+
+    fn visit_str<E>(mut self, v: &str) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.add(v.len())?;
+        Ok(Value::String(v.to_owned()))
+    }
+
+    fn visit_string<E>(mut self, v: String) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.add(v.len())?;
+        Ok(Value::String(v))
+    }
+
+    fn visit_bytes<E>(mut self, v: &[u8]) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.add(v.len())?;
+        Ok(Value::Bytes(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(mut self, v: Vec<u8>) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.add(v.len())?;
+        Ok(Value::Bytes(v))
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.primitive(Value::Unit)
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.primitive(Value::Option(None))
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let seed = self.nested()?;
+        Ok(Value::Option(Some(Box::new(
+            seed.deserialize(deserializer)?,
+        ))))
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let seed = self.nested()?;
+        Ok(Value::Newtype(Box::new(seed.deserialize(deserializer)?)))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut seed = self.nested()?;
+        let mut values = Vec::new();
+        while let Some(value) = seq.next_element_seed(seed.reborrow())? {
+            values.push(value);
+        }
+        Ok(Value::Seq(values))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut seed = self.nested()?;
+        let mut values = std::collections::BTreeMap::new();
+        while let Some(key) = map.next_key_seed(seed.reborrow())? {
+            let value = map.next_value_seed(seed.reborrow())?;
+            values.insert(key, value);
+        }
+        Ok(Value::Map(values))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::de::Error as _;
+
+    use crate::{deserialize_by_key_with_limits, Error};
+
+    const LIMITS: Limits = Limits {
+        max_content_size: 16,
+        max_depth: 2,
+        max_key_length: 8,
+    };
+
+    fn read(json: &str) -> Result<Value, Error> {
+        deserialize_by_key_with_limits(
+            "Box<dyn Shape>",
+            &["kind", "shape"],
+            LIMITS,
+            |key: String, deserializer| match key.as_str() {
+                "points" => erased_serde::deserialize(deserializer),
+                _ => Err(crate::unknown_key()),
+            },
+            &mut serde_json::Deserializer::from_str(json),
+        )
+        .map_err(Error::custom)
+    }
+
+    fn exceeded(json: &str) -> Option<LimitExceeded> {
+        LimitExceeded::from_error(&read(json).unwrap_err())
+    }
+
+    #[test]
+    fn buffers_content_within_limits() {
+        let points = Value::Seq(vec![
+            Value::Seq(vec![Value::U64(1), Value::U64(2)]),
+            Value::Seq(vec![]),
+        ]);
+        assert_eq!(
+            read(r#"{"shape": [[1, 2], []], "kind": "points"}"#).unwrap(),
+            points
+        );
+        assert_eq!(
+            read(r#"{"kind": "points", "shape": [[[[[1]]]]]}"#).unwrap(),
+            Value::Seq(vec![Value::Seq(vec![Value::Seq(vec![Value::Seq(vec![
+                Value::Seq(vec![Value::U64(1)])
+            ])])])])
+        );
+        assert_eq!(read(r#"["points", [[1, 2], []]]"#).unwrap(), points);
+    }
+
+    #[test]
+    fn fails_past_limits() {
+        let size = Some(LimitExceeded::ContentSize { limit: 16 });
+        let depth = Some(LimitExceeded::Depth { limit: 2 });
+        let key = Some(LimitExceeded::KeyLength { limit: 8 });
+
+        assert_eq!(
+            exceeded(r#"{"shape": "abcdefghijklmnopq", "kind": "points"}"#),
+            size
+        );
+        assert_eq!(
+            exceeded(r#"{"shape": [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]}"#),
+            size
+        );
+        assert_eq!(exceeded(r#"{"shape": [[[1]]], "kind": "points"}"#), depth);
+        assert_eq!(exceeded(r#"{"shape": {"a": {"b": {}}}}"#), depth);
+        assert_eq!(exceeded(r#"{"kind": "pointsssss", "shape": []}"#), key);
+        assert_eq!(exceeded(r#"{"shape": [], "kind": "pointsssss"}"#), key);
+        assert_eq!(exceeded(r#"["pointsssss", []]"#), key);
+    }
+
+    #[test]
+    fn from_error_ignores_other_errors() {
+        assert_eq!(exceeded(r#"{"kind": "circle", "shape": []}"#), None);
+        assert_eq!(exceeded(r#"{"shape": [], "kind": 12}"#), None);
+        assert_eq!(
+            LimitExceeded::from_error(&"key is longer than 8 bits"),
+            None
+        );
+    }
+}
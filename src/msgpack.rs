@@ -0,0 +1,329 @@
+//! Representation of keyed values as MessagePack extension types.
+//!
+//! Requires the `rmp-serde` feature.
+//!
+//! Instead of a struct with key and value fields, an `i8` key is written as
+//! the type id of a MessagePack `ext` and the value is encoded with
+//! `rmp_serde` into its data. Structs in the value are encoded as maps, but
+//! either maps or arrays are accepted when decoding.
+//!
+//! This uses the same convention as `rmp_serde` for extension types: a
+//! newtype struct named `"_ExtStruct"` around a tuple of the type id and the
+//! data. Other formats will write that struct as-is, so this representation
+//! is only really useful for MessagePack.
+//!
+//! A [`Registry`] with `i8` keys can use this representation with
+//! [`Keyed<Ext<R>>`](crate::Keyed):
+//!
+//! ```
+//! # mod outer {
+//! use keyedes::msgpack::Ext;
+//! use keyedes::Keyed;
+//! use serde::{Deserialize, Serialize};
+//!
+//! trait Reading: erased_serde::Serialize {}
+//! # struct Readings;
+//! # impl keyedes::Registry for Readings {
+//! #     type Object = dyn Reading;
+//! #     type Key = i8;
+//! #     const TYPE_NAME: &'static str = "Box<dyn Reading>";
+//! #     const FIELD_NAMES: &'static [&'static str; 2] = &["id", "data"];
+//! #     fn key(_: &dyn Reading) -> i8 { 0 }
+//! #     fn deserialize(
+//! #         _: i8,
+//! #         _: &mut dyn erased_serde::Deserializer,
+//! #     ) -> Result<Box<dyn Reading>, keyedes::Error> {
+//! #         Err(keyedes::unknown_key())
+//! #     }
+//! # }
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Report {
+//!     #[serde(with = "Keyed::<Ext<Readings>>")]
+//!     readings: Vec<Box<dyn Reading>>,
+//! }
+//! # }
+//! ```
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{Error as _, SeqAccess, Visitor};
+use serde::ser::{Error as _, SerializeTuple};
+use serde::{Deserializer, Serialize, Serializer};
+
+use crate::adapters::Representation;
+use crate::private::{Bytes, BytesSeed, ErasedSerdeSerializeWrapper};
+use crate::{Error, Registry};
+
+/// Marker for serializing objects of `R` as MessagePack extension types,
+/// using their keys as the type ids.
+///
+/// See the [module documentation](self) for details.
+pub struct Ext<R>(PhantomData<R>);
+
+/// Will serialize the value as a MessagePack extension type with the key as
+/// its type id.
+///
+/// The value is encoded separately, so any error from doing so is returned
+/// as a custom error of the serializer.
+pub fn serialize_as_ext<S, V>(type_id: i8, value: &V, serializer: S) -> Result<S::Ok, S::Error>
+where
+    V: ?Sized + erased_serde::Serialize,
+    S: Serializer,
+{
+    let data =
+        rmp_serde::to_vec_named(&ErasedSerdeSerializeWrapper(value)).map_err(S::Error::custom)?;
+    serializer.serialize_newtype_struct(
+        rmp_serde::MSGPACK_EXT_STRUCT_NAME,
+        &ExtData { type_id, data },
+    )
+}
+
+/// Will deserialize a MessagePack extension type, using its type id as the
+/// key.
+///
+/// The function `f` will be called with the key and a deserializer for the
+/// decoded data that can be used to get the final value.
+pub fn deserialize_from_ext<'de, D, V, F>(f: F, deserializer: D) -> Result<V, D::Error>
+where
+    D: Deserializer<'de>,
+    F: Fn(i8, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+{
+    deserializer.deserialize_newtype_struct(
+        rmp_serde::MSGPACK_EXT_STRUCT_NAME,
+        ExtVisitor {
+            deserialization_fn: f,
+            _dummy: PhantomData,
+        },
+    )
+}
+
+struct ExtData {
+    type_id: i8,
+    data: Vec<u8>,
+}
+
+impl Serialize for ExtData {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_tuple(2)?;
+        state.serialize_element(&self.type_id)?;
+        state.serialize_element(&Bytes(&self.data))?;
+        state.end()
+    }
+}
+
+struct ExtVisitor<F, V> {
+    deserialization_fn: F,
+    _dummy: PhantomData<fn() -> V>,
+}
+
+impl<'de, F, V> Visitor<'de> for ExtVisitor<F, V>
+where
+    F: Fn(i8, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+{
+    type Value = V;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("extension type")
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_tuple(2, self)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let type_id: i8 = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+        let data = seq
+            .next_element_seed(BytesSeed)?
+            .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+
+        let mut deserializer = rmp_serde::Deserializer::new(&data[..]);
+        (self.deserialization_fn)(
+            type_id,
+            &mut <dyn erased_serde::Deserializer>::erase(&mut deserializer),
+        )
+        .map_err(A::Error::custom)
+    }
+}
+
+impl<R> Representation for Ext<R>
+where
+    R: Registry<Key = i8>,
+{
+    type Object = R::Object;
+
+    fn serialize_object<S>(object: &R::Object, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_as_ext(R::key(object), object, serializer)
+    }
+
+    fn deserialize_object<'de, D>(deserializer: D) -> Result<Box<R::Object>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_from_ext(R::deserialize, deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    use once_cell::sync::Lazy;
+    use serde::Deserialize;
+
+    use crate::{deserialize_into_boxed_trait, DesFnSync, Keyed};
+
+    trait TestTrait: erased_serde::Serialize {
+        fn key(&self) -> i8;
+        fn name(&self) -> String;
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct TestStructA {
+        name: String,
+    }
+
+    impl TestTrait for TestStructA {
+        fn key(&self) -> i8 {
+            1
+        }
+
+        fn name(&self) -> String {
+            self.name.clone()
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct TestStructB(String);
+
+    impl TestTrait for TestStructB {
+        fn key(&self) -> i8 {
+            -5
+        }
+
+        fn name(&self) -> String {
+            self.0.clone()
+        }
+    }
+
+    static MAP: Lazy<HashMap<i8, DesFnSync<Box<dyn TestTrait>>>> = Lazy::new(|| {
+        let mut map = HashMap::<i8, DesFnSync<Box<dyn TestTrait>>>::new();
+        map.insert(1, deserialize_into_boxed_trait!(TestStructA));
+        map.insert(-5, deserialize_into_boxed_trait!(TestStructB));
+        map
+    });
+
+    struct TestRegistry;
+
+    impl Registry for TestRegistry {
+        type Object = dyn TestTrait;
+        type Key = i8;
+
+        const TYPE_NAME: &'static str = "Box<dyn TestTrait>";
+        const FIELD_NAMES: &'static [&'static str; 2] = &["id", "data"];
+
+        fn key(object: &dyn TestTrait) -> i8 {
+            object.key()
+        }
+
+        fn deserialize(
+            key: i8,
+            deserializer: &mut dyn erased_serde::Deserializer,
+        ) -> Result<Box<dyn TestTrait>, Error> {
+            MAP.get(&key)
+                .ok_or_else(crate::unknown_key)
+                .and_then(|f| f(deserializer))
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Container {
+        #[serde(with = "Keyed::<Ext<TestRegistry>>")]
+        items: Vec<Box<dyn TestTrait>>,
+        #[serde(with = "Keyed::<Ext<TestRegistry>>")]
+        shared: Rc<dyn TestTrait>,
+    }
+
+    fn test_container() -> Container {
+        Container {
+            items: vec![
+                Box::new(TestStructA {
+                    name: "chuck norris".to_string(),
+                }),
+                Box::new(TestStructB("Broccoli".to_string())),
+            ],
+            shared: Rc::new(TestStructB("Pizza".to_string())),
+        }
+    }
+
+    #[test]
+    fn ext_values_round_trip_through_msgpack() {
+        for bytes in [
+            rmp_serde::to_vec(&test_container()).unwrap(),
+            rmp_serde::to_vec_named(&test_container()).unwrap(),
+        ] {
+            let container: Container = rmp_serde::from_slice(&bytes).unwrap();
+            assert_eq!(container.items[0].name(), "chuck norris");
+            assert_eq!(container.items[1].name(), "Broccoli");
+            assert_eq!(container.shared.name(), "Pizza");
+        }
+    }
+
+    #[test]
+    fn ext_values_are_written_as_ext_types() {
+        let bytes = rmp_serde::to_vec(&test_container()).unwrap();
+
+        let mut reader = &bytes[..];
+        assert_eq!(rmp::decode::read_array_len(&mut reader).unwrap(), 2);
+        assert_eq!(rmp::decode::read_array_len(&mut reader).unwrap(), 2);
+
+        let meta = rmp::decode::read_ext_meta(&mut reader).unwrap();
+        assert_eq!(meta.typeid, 1);
+        let (data, rest) = reader.split_at(meta.size as usize);
+        let value: TestStructA = rmp_serde::from_slice(data).unwrap();
+        assert_eq!(value.name, "chuck norris");
+
+        let mut reader = rest;
+        let meta = rmp::decode::read_ext_meta(&mut reader).unwrap();
+        assert_eq!(meta.typeid, -5);
+    }
+
+    #[test]
+    fn ext_values_return_error_for_unknown_type_ids() {
+        #[derive(Deserialize)]
+        struct Single(#[serde(with = "Keyed::<Ext<TestRegistry>>")] Box<dyn TestTrait>);
+
+        let data = rmp_serde::to_vec(&TestStructB("Pizza".to_string())).unwrap();
+        let mut bytes = Vec::new();
+        rmp::encode::write_ext_meta(&mut bytes, data.len() as u32, -5).unwrap();
+        bytes.extend_from_slice(&data);
+
+        let single = rmp_serde::from_slice::<Single>(&bytes).unwrap();
+        assert_eq!(single.0.name(), "Pizza");
+
+        let mut bytes = Vec::new();
+        rmp::encode::write_ext_meta(&mut bytes, 1, 3).unwrap();
+        bytes.push(0xc0);
+
+        let error = rmp_serde::from_slice::<Single>(&bytes).err().unwrap();
+        assert!(error.to_string().contains("unknown deserialization key"));
+    }
+}
@@ -0,0 +1,263 @@
+//! A registry where one registration can produce several trait objects.
+//!
+//! A concrete type is registered once with [`MultiRegistry::register()`],
+//! along with a cast function for each `dyn Trait` it should be available
+//! as. Any of those trait objects can then be deserialized by key.
+//!
+//! ```
+//! use keyedes::multi::MultiRegistry;
+//! use serde::Deserialize;
+//!
+//! trait Render {}
+//! trait Tick {}
+//!
+//! #[derive(Deserialize)]
+//! struct Sprite;
+//!
+//! impl Render for Sprite {}
+//! impl Tick for Sprite {}
+//!
+//! let mut registry = MultiRegistry::new();
+//! registry
+//!     .register::<Sprite>("sprite")
+//!     .cast::<dyn Render>(|v| v)
+//!     .cast::<dyn Tick>(|v| v);
+//!
+//! let mut deserializer = serde_json::Deserializer::from_str("null");
+//! let render = registry.deserialize::<dyn Render>(
+//!     "sprite",
+//!     &mut <dyn erased_serde::Deserializer>::erase(&mut deserializer),
+//! );
+//! assert!(render.is_ok());
+//! ```
+
+use std::any::{type_name, Any, TypeId};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use serde::de::{DeserializeOwned, Error as _};
+
+use crate::Error;
+
+type AnyDesFn =
+    Box<dyn Fn(&mut dyn erased_serde::Deserializer) -> Result<Box<dyn Any>, Error> + Send + Sync>;
+type CastFn<U> = Box<dyn Fn(Box<dyn Any>) -> Box<U> + Send + Sync>;
+
+struct Entry {
+    type_name: &'static str,
+    deserialization_fn: AnyDesFn,
+    casts: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+/// A registry of concrete types that can each be deserialized as any of the
+/// trait objects they were registered with.
+#[derive(Default)]
+pub struct MultiRegistry {
+    entries: HashMap<String, Entry>,
+}
+
+impl MultiRegistry {
+    #[must_use]
+    pub fn new() -> MultiRegistry {
+        MultiRegistry::default()
+    }
+
+    /// Registers the type `T` under the given key. Cast functions should be
+    /// added to the returned [`Registration`] for each trait object the type
+    /// can be deserialized as.
+    ///
+    /// Registering a key that already exists will replace it.
+    pub fn register<T>(&mut self, key: impl Into<String>) -> Registration<'_, T>
+    where
+        T: DeserializeOwned + 'static,
+    {
+        let entry = Entry {
+            type_name: type_name::<T>(),
+            deserialization_fn: Box::new(|deserializer| {
+                erased_serde::deserialize::<T>(deserializer).map(|v| Box::new(v) as Box<dyn Any>)
+            }),
+            casts: HashMap::new(),
+        };
+
+        let key = key.into();
+        self.entries.insert(key.clone(), entry);
+        Registration {
+            entry: self.entries.get_mut(&key).unwrap(),
+            _dummy: PhantomData,
+        }
+    }
+
+    /// Returns whether the type registered for the key can be deserialized as
+    /// a `Box<U>`.
+    #[must_use]
+    pub fn implements<U>(&self, key: &str) -> bool
+    where
+        U: ?Sized + 'static,
+    {
+        self.entries
+            .get(key)
+            .is_some_and(|entry| entry.casts.contains_key(&TypeId::of::<U>()))
+    }
+
+    /// Returns an iterator over the registered keys.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// Deserializes the type registered for the key and casts it to a
+    /// `Box<U>`.
+    ///
+    /// This will return an error if the key is unknown or if the type wasn't
+    /// registered with a cast to `U`. Any [overrides](crate::overrides) for a
+    /// `Box<U>` are considered first.
+    pub fn deserialize<U>(
+        &self,
+        key: &str,
+        deserializer: &mut dyn erased_serde::Deserializer,
+    ) -> Result<Box<U>, Error>
+    where
+        U: ?Sized + 'static,
+    {
+        if let Some(result) = crate::overrides::get(key, deserializer) {
+            return result;
+        }
+
+        let entry = self.entries.get(key).ok_or_else(crate::unknown_key)?;
+        let cast = entry
+            .casts
+            .get(&TypeId::of::<U>())
+            .and_then(|cast| cast.downcast_ref::<CastFn<U>>())
+            .ok_or_else(|| {
+                Error::custom(format_args!(
+                    "type `{}` for key {:?} does not implement `{}`",
+                    entry.type_name,
+                    key,
+                    type_name::<U>()
+                ))
+            })?;
+
+        (entry.deserialization_fn)(deserializer).map(cast)
+    }
+}
+
+/// A type registered in a [`MultiRegistry`], used to add cast functions.
+pub struct Registration<'a, T> {
+    entry: &'a mut Entry,
+    _dummy: PhantomData<fn(T)>,
+}
+
+impl<'a, T: 'static> Registration<'a, T> {
+    /// Adds a function to cast the type to a `Box<U>`. This is usually just
+    /// `|v| v` to let the compiler do the coercion.
+    pub fn cast<U>(self, f: fn(Box<T>) -> Box<U>) -> Self
+    where
+        U: ?Sized + 'static,
+    {
+        let cast: CastFn<U> = Box::new(move |value| f(value.downcast::<T>().unwrap()));
+        self.entry.casts.insert(TypeId::of::<U>(), Box::new(cast));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::Deserialize;
+
+    use crate::deserialize_by_key;
+
+    trait Render {
+        fn render(&self) -> String;
+    }
+
+    trait Tick {
+        fn tick(&mut self) -> u32;
+    }
+
+    #[derive(Deserialize)]
+    struct Counter {
+        count: u32,
+    }
+
+    impl Render for Counter {
+        fn render(&self) -> String {
+            format!("count: {}", self.count)
+        }
+    }
+
+    impl Tick for Counter {
+        fn tick(&mut self) -> u32 {
+            self.count += 1;
+            self.count
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct Label(String);
+
+    impl Render for Label {
+        fn render(&self) -> String {
+            self.0.clone()
+        }
+    }
+
+    fn test_registry() -> MultiRegistry {
+        let mut registry = MultiRegistry::new();
+        registry
+            .register::<Counter>("counter")
+            .cast::<dyn Render>(|v| v)
+            .cast::<dyn Tick>(|v| v);
+        registry
+            .register::<Label>("label")
+            .cast::<dyn Render>(|v| v);
+        registry
+    }
+
+    fn deserialize_json<U: ?Sized + 'static>(
+        registry: &MultiRegistry,
+        json: &str,
+    ) -> Result<Box<U>, serde_json::Error> {
+        let mut deserializer = serde_json::Deserializer::from_str(json);
+        deserialize_by_key(
+            "Box<dyn Trait>",
+            &["id", "data"],
+            |key: String, deserializer| registry.deserialize::<U>(&key, deserializer),
+            &mut deserializer,
+        )
+    }
+
+    #[test]
+    fn multi_registry_produces_each_registered_trait() {
+        let registry = test_registry();
+        let json = r#"{"id":"counter","data":{"count":4}}"#;
+
+        let render = deserialize_json::<dyn Render>(&registry, json).unwrap();
+        assert_eq!(render.render(), "count: 4");
+
+        let mut tick = deserialize_json::<dyn Tick>(&registry, json).unwrap();
+        assert_eq!(tick.tick(), 5);
+
+        let render =
+            deserialize_json::<dyn Render>(&registry, r#"{"id":"label","data":"hi"}"#).unwrap();
+        assert_eq!(render.render(), "hi");
+
+        assert!(registry.implements::<dyn Tick>("counter"));
+        assert!(!registry.implements::<dyn Tick>("label"));
+    }
+
+    #[test]
+    fn multi_registry_returns_error_if_trait_is_not_implemented() {
+        let registry = test_registry();
+
+        let error = deserialize_json::<dyn Tick>(&registry, r#"{"id":"label","data":"hi"}"#)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("does not implement `dyn "));
+
+        let error = deserialize_json::<dyn Tick>(&registry, r#"{"id":"other","data":null}"#)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("unknown deserialization key"));
+    }
+}
@@ -0,0 +1,307 @@
+//! Temporary, thread-local overrides of deserialization functions.
+//!
+//! This is mainly intended for tests that need to swap in mock
+//! implementations for some keys without touching a global registry that
+//! other threads are using. Entries are added with [`with_overrides()`] and
+//! are only visible on the current thread until it returns.
+//!
+//! Lookups have to go through [`deserialize()`] (or [`get()`]) for the
+//! overrides to be considered:
+//!
+//! ```
+//! # mod outer {
+//! use std::collections::HashMap;
+//!
+//! use keyedes::DesFnSync;
+//! use once_cell::sync::Lazy;
+//! use serde::Deserializer;
+//!
+//! trait TestTrait {}
+//!
+//! static MAP: Lazy<HashMap<String, DesFnSync<Box<dyn TestTrait>>>> =
+//!     Lazy::new(|| {
+//!         let mut map = HashMap::<String, DesFnSync<Box<dyn TestTrait>>>::new();
+//!         // fill out the map
+//!         map
+//!     });
+//!
+//! pub fn deserialize<'de, D>(deserializer: D) -> Result<Box<dyn TestTrait>, D::Error>
+//! where
+//!     D: Deserializer<'de>,
+//! {
+//!     keyedes::deserialize_by_key(
+//!         "Box<dyn TestTrait>",
+//!         &["id", "data"],
+//!         |key: String, deserializer| {
+//!             keyedes::overrides::deserialize(&*MAP, &key, deserializer)
+//!         },
+//!         deserializer,
+//!     )
+//! }
+//! # }
+//! ```
+//!
+//! The same goes for the [`Keyed`](crate::Keyed) adapters, which call
+//! [`Registry::deserialize`](crate::Registry::deserialize) directly, so a
+//! registry has to do the lookup itself for its keys to be overridden:
+//!
+//! ```
+//! # use std::collections::HashMap;
+//! # use keyedes::DesFnSync;
+//! # use once_cell::sync::Lazy;
+//! # trait TestTrait: erased_serde::Serialize {
+//! #     fn key(&self) -> String;
+//! # }
+//! # static MAP: Lazy<HashMap<String, DesFnSync<Box<dyn TestTrait>>>> =
+//! #     Lazy::new(HashMap::new);
+//! struct TestTraits;
+//!
+//! impl keyedes::Registry for TestTraits {
+//!     type Object = dyn TestTrait;
+//!     type Key = String;
+//!
+//!     const TYPE_NAME: &'static str = "Box<dyn TestTrait>";
+//!     const FIELD_NAMES: &'static [&'static str; 2] = &["id", "data"];
+//!
+//!     fn key(object: &dyn TestTrait) -> String {
+//!         object.key()
+//!     }
+//!
+//!     fn deserialize(
+//!         key: String,
+//!         deserializer: &mut dyn erased_serde::Deserializer,
+//!     ) -> Result<Box<dyn TestTrait>, keyedes::Error> {
+//!         keyedes::overrides::deserialize(&*MAP, &key, deserializer)
+//!     }
+//! }
+//! ```
+//!
+//! While no thread has overrides active, lookups return right away without
+//! touching the thread-local state.
+
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{DesFn, Error};
+
+thread_local! {
+    static LAYERS: RefCell<Vec<Overrides>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The number of layers active on all threads.
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// A set of deserialization functions to layer over a registry.
+///
+/// Entries are keyed by both the key and the type they produce, so a single
+/// set can override keys for several registries.
+#[derive(Default)]
+pub struct Overrides {
+    entries: HashMap<TypeId, HashMap<String, Rc<dyn Any>>>,
+}
+
+impl Overrides {
+    #[must_use]
+    pub fn new() -> Overrides {
+        Overrides::default()
+    }
+
+    /// Adds a deserialization function for the key, replacing any previous
+    /// one for the same key and type.
+    #[must_use]
+    pub fn with<T: 'static>(mut self, key: impl Into<String>, f: DesFn<T>) -> Overrides {
+        self.entries
+            .entry(TypeId::of::<T>())
+            .or_default()
+            .insert(key.into(), Rc::new(f));
+        self
+    }
+}
+
+/// Will run `f` with the given overrides active for the current thread.
+///
+/// Overrides can be nested, in which case the innermost entry for a key takes
+/// precedence. They are removed when `f` returns or panics.
+pub fn with_overrides<R, F>(overrides: Overrides, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    struct Guard;
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            LAYERS.with(|layers| layers.borrow_mut().pop());
+            ACTIVE.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    LAYERS.with(|layers| layers.borrow_mut().push(overrides));
+    ACTIVE.fetch_add(1, Ordering::Relaxed);
+    let _guard = Guard;
+    f()
+}
+
+/// Will deserialize with the override for the key if there is one.
+///
+/// Returns `None` if the key isn't overridden for the type `T` on the current
+/// thread.
+pub fn get<T: 'static>(
+    key: &str,
+    deserializer: &mut dyn erased_serde::Deserializer,
+) -> Option<Result<T, Error>> {
+    if ACTIVE.load(Ordering::Relaxed) == 0 {
+        return None;
+    }
+
+    let type_id = TypeId::of::<T>();
+    let f = LAYERS.with(|layers| {
+        layers
+            .borrow()
+            .iter()
+            .rev()
+            .find_map(|layer| layer.entries.get(&type_id)?.get(key).cloned())
+    })?;
+
+    let f = f.downcast::<DesFn<T>>().ok()?;
+    Some(f(deserializer))
+}
+
+/// Will deserialize with the override for the key if there is one, or with
+/// the function from `map` otherwise.
+///
+/// This will return an error if the key isn't found in either.
+pub fn deserialize<T, V, S>(
+    map: &HashMap<String, V, S>,
+    key: &str,
+    deserializer: &mut dyn erased_serde::Deserializer,
+) -> Result<T, Error>
+where
+    T: 'static,
+    V: Fn(&mut dyn erased_serde::Deserializer) -> Result<T, Error>,
+    S: BuildHasher,
+{
+    match get(key, deserializer) {
+        Some(result) => result,
+        None => map
+            .get(key)
+            .ok_or_else(crate::unknown_key)
+            .and_then(|f| f(deserializer)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::Deserialize;
+
+    use crate::{deserialize_by_key, deserialize_into_boxed_trait, DesFnSync};
+
+    trait TestTrait {
+        fn name(&self) -> String;
+    }
+
+    #[derive(Deserialize)]
+    struct TestStructA {
+        name: String,
+    }
+
+    impl TestTrait for TestStructA {
+        fn name(&self) -> String {
+            self.name.clone()
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct MockA {}
+
+    impl TestTrait for MockA {
+        fn name(&self) -> String {
+            "mock".to_string()
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct OtherMockA {}
+
+    impl TestTrait for OtherMockA {
+        fn name(&self) -> String {
+            "other mock".to_string()
+        }
+    }
+
+    fn deserialize_name(map: &HashMap<String, DesFnSync<Box<dyn TestTrait>>>) -> String {
+        let json = r#"{"id":"A","data":{"name":"real"}}"#;
+        let mut deserializer = serde_json::Deserializer::from_str(json);
+        let result: Box<dyn TestTrait> = deserialize_by_key(
+            "Box<dyn TestTrait>",
+            &["id", "data"],
+            |key: String, deserializer| deserialize(map, &key, deserializer),
+            &mut deserializer,
+        )
+        .unwrap();
+        result.name()
+    }
+
+    fn test_map() -> HashMap<String, DesFnSync<Box<dyn TestTrait>>> {
+        let mut map = HashMap::<String, DesFnSync<Box<dyn TestTrait>>>::new();
+        map.insert("A".to_string(), deserialize_into_boxed_trait!(TestStructA));
+        map
+    }
+
+    #[test]
+    fn overrides_are_layered_and_removed() {
+        let map = test_map();
+        assert_eq!(deserialize_name(&map), "real");
+
+        let overrides =
+            Overrides::new().with::<Box<dyn TestTrait>>("A", deserialize_into_boxed_trait!(MockA));
+        with_overrides(overrides, || {
+            assert_eq!(deserialize_name(&map), "mock");
+
+            let overrides = Overrides::new()
+                .with::<Box<dyn TestTrait>>("A", deserialize_into_boxed_trait!(OtherMockA));
+            with_overrides(overrides, || {
+                assert_eq!(deserialize_name(&map), "other mock");
+            });
+
+            assert_eq!(deserialize_name(&map), "mock");
+
+            let name = std::thread::spawn(|| deserialize_name(&test_map()))
+                .join()
+                .unwrap();
+            assert_eq!(name, "real");
+        });
+
+        assert_eq!(deserialize_name(&map), "real");
+    }
+
+    #[test]
+    fn overrides_are_removed_on_panic() {
+        let map = test_map();
+
+        let result = std::panic::catch_unwind(|| {
+            let overrides = Overrides::new()
+                .with::<Box<dyn TestTrait>>("A", deserialize_into_boxed_trait!(MockA));
+            with_overrides(overrides, || panic!("oops"))
+        });
+        assert!(result.is_err());
+
+        assert_eq!(deserialize_name(&map), "real");
+    }
+
+    #[test]
+    fn overrides_only_apply_to_their_type() {
+        let map = test_map();
+
+        let overrides =
+            Overrides::new().with::<String>("A", Box::new(|_| Ok("wrong type".to_string())));
+        with_overrides(overrides, || {
+            assert_eq!(deserialize_name(&map), "real");
+        });
+    }
+}
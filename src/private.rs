@@ -2,7 +2,10 @@ use std::marker::PhantomData;
 
 use serde::de::{DeserializeSeed, Error, IgnoredAny, MapAccess, SeqAccess, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use serde_value::{Value, ValueDeserializer};
+use serde_value::ValueDeserializer;
+
+use crate::limits::{ContentSeed, KeySeed, Limits};
+use crate::text::TextValueDeserializer;
 
 pub struct ErasedSerdeSerializeWrapper<'a, V: ?Sized>(pub &'a V);
 impl<'a, V: ?Sized> Serialize for ErasedSerdeSerializeWrapper<'a, V>
@@ -17,7 +20,64 @@ where
     }
 }
 
-struct MissingFieldDeserializer<E>(&'static str, PhantomData<E>);
+pub struct Bytes<'a>(pub &'a [u8]);
+
+impl<'a> Serialize for Bytes<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+pub struct BytesSeed;
+
+impl<'de> DeserializeSeed<'de> for BytesSeed {
+    type Value = Vec<u8>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_byte_buf(self)
+    }
+}
+
+impl<'de> Visitor<'de> for BytesSeed {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("bytes")
+    }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        Ok(value.to_vec())
+    }
+
+    fn visit_byte_buf<E>(self, value: Vec<u8>) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        Ok(value)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
+
+pub struct MissingFieldDeserializer<E>(pub &'static str, pub PhantomData<E>);
 
 impl<'de, E> Deserializer<'de> for MissingFieldDeserializer<E>
 where
@@ -111,13 +171,35 @@ impl<'de> Visitor<'de> for TagContentOtherFieldVisitor {
     }
 }
 
-struct ValueDeserializeSeed<'a, F, K, T>
+/// Advances the map to the next tag or content field, skipping any others.
+pub fn next_tag_or_content<'de, A>(
+    map: &mut A,
+    tag: &'static str,
+    content: &'static str,
+) -> Result<Option<TagOrContentField>, A::Error>
+where
+    A: MapAccess<'de>,
+{
+    while let Some(__k) = map.next_key_seed(TagContentOtherFieldVisitor { tag, content })? {
+        match __k {
+            TagContentOtherField::Other => {
+                map.next_value::<IgnoredAny>()?;
+                continue;
+            }
+            TagContentOtherField::Tag => return Ok(Some(TagOrContentField::Tag)),
+            TagContentOtherField::Content => return Ok(Some(TagOrContentField::Content)),
+        }
+    }
+    Ok(None)
+}
+
+pub struct ValueDeserializeSeed<'a, F, K, T>
 where
     F: Fn(K, &mut dyn erased_serde::Deserializer) -> Result<T, erased_serde::Error>,
 {
-    field: K,
-    deserialization_fn: &'a F,
-    _dummy: PhantomData<fn(K) -> T>,
+    pub field: K,
+    pub deserialization_fn: &'a F,
+    pub _dummy: PhantomData<fn(K) -> T>,
 }
 
 impl<'de, 'a, F, K, T> DeserializeSeed<'de> for ValueDeserializeSeed<'a, F, K, T>
@@ -145,6 +227,10 @@ where
     pub deserialization_fn: F,
     pub key_name: &'static str,
     pub value_name: &'static str,
+    /// Whether a buffered value should be parsed from strings when needed,
+    /// like for formats where everything is text.
+    pub text_values: bool,
+    pub limits: Limits,
     pub _dummy: PhantomData<fn(K) -> T>,
 }
 
@@ -162,54 +248,10 @@ where
     where
         A: MapAccess<'de>,
     {
-        match {
-            let mut __rk: Option<TagOrContentField> = None;
-            while let Some(__k) = map.next_key_seed(TagContentOtherFieldVisitor {
-                tag: self.key_name,
-                content: self.value_name,
-            })? {
-                match __k {
-                    TagContentOtherField::Other => {
-                        map.next_value::<IgnoredAny>()?;
-                        continue;
-                    }
-                    TagContentOtherField::Tag => {
-                        __rk = Some(TagOrContentField::Tag);
-                        break;
-                    }
-                    TagContentOtherField::Content => {
-                        __rk = Some(TagOrContentField::Content);
-                        break;
-                    }
-                }
-            }
-            __rk
-        } {
+        match next_tag_or_content(&mut map, self.key_name, self.value_name)? {
             Some(TagOrContentField::Tag) => {
-                let __field = map.next_value()?;
-                match {
-                    let mut __rk: Option<TagOrContentField> = None;
-                    while let Some(__k) = map.next_key_seed(TagContentOtherFieldVisitor {
-                        tag: self.key_name,
-                        content: self.value_name,
-                    })? {
-                        match __k {
-                            TagContentOtherField::Other => {
-                                map.next_value::<IgnoredAny>()?;
-                                continue;
-                            }
-                            TagContentOtherField::Tag => {
-                                __rk = Some(TagOrContentField::Tag);
-                                break;
-                            }
-                            TagContentOtherField::Content => {
-                                __rk = Some(TagOrContentField::Content);
-                                break;
-                            }
-                        }
-                    }
-                    __rk
-                } {
+                let __field = map.next_value_seed(KeySeed::new(&self.limits))?;
+                match next_tag_or_content(&mut map, self.key_name, self.value_name)? {
                     Some(TagOrContentField::Tag) => Err(
                         <A::Error as serde::de::Error>::duplicate_field(self.key_name),
                     ),
@@ -219,31 +261,7 @@ where
                             deserialization_fn: &self.deserialization_fn,
                             _dummy: PhantomData,
                         })?;
-                        match {
-                            let mut __rk: Option<TagOrContentField> = None;
-                            while let Some(__k) =
-                                map.next_key_seed(TagContentOtherFieldVisitor {
-                                    tag: self.key_name,
-                                    content: self.value_name,
-                                })?
-                            {
-                                match __k {
-                                    TagContentOtherField::Other => {
-                                        map.next_value::<IgnoredAny>()?;
-                                        continue;
-                                    }
-                                    TagContentOtherField::Tag => {
-                                        __rk = Some(TagOrContentField::Tag);
-                                        break;
-                                    }
-                                    TagContentOtherField::Content => {
-                                        __rk = Some(TagOrContentField::Content);
-                                        break;
-                                    }
-                                }
-                            }
-                            __rk
-                        } {
+                        match next_tag_or_content(&mut map, self.key_name, self.value_name)? {
                             Some(TagOrContentField::Tag) => Err(
                                 <A::Error as serde::de::Error>::duplicate_field(self.key_name),
                             ),
@@ -266,65 +284,28 @@ where
                 }
             }
             Some(TagOrContentField::Content) => {
-                let __content = map.next_value::<Value>()?;
-                match {
-                    let mut __rk: Option<TagOrContentField> = None;
-                    while let Some(__k) = map.next_key_seed(TagContentOtherFieldVisitor {
-                        tag: self.key_name,
-                        content: self.value_name,
-                    })? {
-                        match __k {
-                            TagContentOtherField::Other => {
-                                map.next_value::<IgnoredAny>()?;
-                                continue;
-                            }
-                            TagContentOtherField::Tag => {
-                                __rk = Some(TagOrContentField::Tag);
-                                break;
-                            }
-                            TagContentOtherField::Content => {
-                                __rk = Some(TagOrContentField::Content);
-                                break;
-                            }
-                        }
-                    }
-                    __rk
-                } {
+                let mut __size = 0;
+                let __content = map.next_value_seed(ContentSeed::new(&self.limits, &mut __size))?;
+                match next_tag_or_content(&mut map, self.key_name, self.value_name)? {
                     Some(TagOrContentField::Tag) => {
-                        let __deserializer = ValueDeserializer::<A::Error>::new(__content);
-                        let __val = map.next_value()?;
+                        let __val = map.next_value_seed(KeySeed::new(&self.limits))?;
 
-                        let __ret = (self.deserialization_fn)(
-                            __val,
-                            &mut <dyn erased_serde::Deserializer>::erase(__deserializer),
-                        )
+                        let __ret = if self.text_values {
+                            let __deserializer = TextValueDeserializer::<A::Error>::new(__content);
+                            (self.deserialization_fn)(
+                                __val,
+                                &mut <dyn erased_serde::Deserializer>::erase(__deserializer),
+                            )
+                        } else {
+                            let __deserializer = ValueDeserializer::<A::Error>::new(__content);
+                            (self.deserialization_fn)(
+                                __val,
+                                &mut <dyn erased_serde::Deserializer>::erase(__deserializer),
+                            )
+                        }
                         .map_err(A::Error::custom)?;
 
-                        match {
-                            let mut __rk: Option<TagOrContentField> = None;
-                            while let Some(__k) =
-                                map.next_key_seed(TagContentOtherFieldVisitor {
-                                    tag: self.key_name,
-                                    content: self.value_name,
-                                })?
-                            {
-                                match __k {
-                                    TagContentOtherField::Other => {
-                                        map.next_value::<IgnoredAny>()?;
-                                        continue;
-                                    }
-                                    TagContentOtherField::Tag => {
-                                        __rk = Some(TagOrContentField::Tag);
-                                        break;
-                                    }
-                                    TagContentOtherField::Content => {
-                                        __rk = Some(TagOrContentField::Content);
-                                        break;
-                                    }
-                                }
-                            }
-                            __rk
-                        } {
+                        match next_tag_or_content(&mut map, self.key_name, self.value_name)? {
                             Some(TagOrContentField::Tag) => Err(
                                 <A::Error as serde::de::Error>::duplicate_field(self.key_name),
                             ),
@@ -348,7 +329,7 @@ where
     where
         A: SeqAccess<'de>,
     {
-        match seq.next_element()? {
+        match seq.next_element_seed(KeySeed::new(&self.limits))? {
             Some(__field) => {
                 match seq.next_element_seed(ValueDeserializeSeed {
                     field: __field,
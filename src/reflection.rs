@@ -0,0 +1,277 @@
+//! Tracing the payload formats of registered types into a catalog.
+//!
+//! Requires the `serde-reflection` feature.
+//!
+//! A [`CatalogTracer`] runs the `Serialize` and `Deserialize` impls of the
+//! objects of a [`Registry`] through the tracer of
+//! [`serde-reflection`](https://crates.io/crates/serde-reflection), which
+//! records the format of every payload: its struct fields, enum variants,
+//! sequences, options and so on. The resulting [`Catalog`] describes all the
+//! keyed payloads and can itself be serialized, for example to keep it under
+//! version control.
+//!
+//! The payload of a key is traced by deserializing a made-up value of the
+//! type registered for it with [`trace_type`](CatalogTracer::trace_type),
+//! which goes through every variant when the payload is an enum, or by
+//! serializing an existing object with
+//! [`trace_object`](CatalogTracer::trace_object). Values recorded when
+//! serializing are reused when deserializing, which helps with payloads that
+//! validate what they read. As with `serde-reflection` itself, enums nested
+//! in payloads have to be traced separately through
+//! [`tracer`](CatalogTracer::tracer) to have all their variants recorded.
+//!
+//! The keys of the catalog are the [`Display`] text of the keys of the
+//! registry, and [`numeric_keys`](Catalog::numeric_keys) records whether
+//! they are written as numbers rather than strings.
+//!
+//! Deserializing goes through the concrete type rather than
+//! [`Registry::deserialize`], since the tracer tells enum variants apart by
+//! the type they're read into, which is lost once the deserializer is erased.
+
+use std::collections::btree_map::{BTreeMap, Entry};
+use std::fmt::Display;
+use std::marker::PhantomData;
+
+use serde::de::{DeserializeOwned, Error as _};
+use serde::{Deserialize, Serialize};
+use serde_reflection::{ContainerFormat, Format, FormatHolder, Samples, Tracer, TracerConfig};
+
+use crate::private::ErasedSerdeSerializeWrapper;
+use crate::{Error, Registry};
+
+/// The traced payload formats of a [`Registry`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Catalog {
+    /// The [`TYPE_NAME`](Registry::TYPE_NAME) of the registry.
+    pub type_name: String,
+    /// The [`FIELD_NAMES`](Registry::FIELD_NAMES) of the registry.
+    pub field_names: [String; 2],
+    /// Whether the keys are written as numbers rather than strings, in which
+    /// case the keys of `payloads` are their text.
+    #[serde(default)]
+    pub numeric_keys: bool,
+    /// The format of the payload of each key.
+    pub payloads: BTreeMap<String, Format>,
+    /// The formats of the structs and enums named in the payloads.
+    pub containers: BTreeMap<String, ContainerFormat>,
+}
+
+/// Traces the payload formats of the keys of `R` into a [`Catalog`].
+///
+/// See the [module documentation](self) for details.
+pub struct CatalogTracer<R> {
+    tracer: Tracer,
+    samples: Samples,
+    numeric_keys: bool,
+    payloads: BTreeMap<String, Format>,
+    _registry: PhantomData<R>,
+}
+
+impl<R> CatalogTracer<R>
+where
+    R: Registry,
+    R::Key: Display,
+{
+    #[must_use]
+    pub fn new(config: TracerConfig) -> CatalogTracer<R> {
+        CatalogTracer {
+            tracer: Tracer::new(config),
+            samples: Samples::new(),
+            numeric_keys: false,
+            payloads: BTreeMap::new(),
+            _registry: PhantomData,
+        }
+    }
+
+    /// Gives access to the underlying tracer and the values it recorded so
+    /// far, to trace the types nested in payloads.
+    pub fn tracer(&mut self) -> (&mut Tracer, &mut Samples) {
+        (&mut self.tracer, &mut self.samples)
+    }
+
+    /// Records the format of the payload of `key` by deserializing the type
+    /// `T` registered for it.
+    pub fn trace_type<T>(&mut self, key: R::Key) -> Result<&Format, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let (format, _) = self
+            .tracer
+            .trace_type::<T>(&self.samples)
+            .map_err(Error::custom)?;
+        self.record(&key, format)
+    }
+
+    /// Records the format of the payload of `object` by serializing it.
+    pub fn trace_object(&mut self, object: &R::Object) -> Result<&Format, Error> {
+        let (format, _) = self
+            .tracer
+            .trace_value(&mut self.samples, &ErasedSerdeSerializeWrapper(object))
+            .map_err(Error::custom)?;
+        self.record(&R::key(object), format)
+    }
+
+    fn record(&mut self, key: &R::Key, format: Format) -> Result<&Format, Error> {
+        self.numeric_keys = is_number(&serde_value::to_value(key).map_err(Error::custom)?);
+        match self.payloads.entry(key.to_string()) {
+            Entry::Vacant(entry) => Ok(entry.insert(format)),
+            Entry::Occupied(entry) => {
+                let payload = entry.into_mut();
+                payload.unify(format).map_err(Error::custom)?;
+                Ok(payload)
+            }
+        }
+    }
+
+    /// Finishes tracing.
+    ///
+    /// This will return an error if a payload or a container it names wasn't
+    /// completely traced, such as an enum with variants left over.
+    pub fn catalog(self) -> Result<Catalog, Error> {
+        let mut payloads = self.payloads;
+        for (key, format) in &mut payloads {
+            format.normalize().map_err(|_| {
+                Error::custom(format_args!(
+                    "incomplete tracing of the payload of `{}`",
+                    key
+                ))
+            })?;
+        }
+
+        Ok(Catalog {
+            type_name: R::TYPE_NAME.to_owned(),
+            field_names: [R::FIELD_NAMES[0].to_owned(), R::FIELD_NAMES[1].to_owned()],
+            numeric_keys: self.numeric_keys,
+            payloads,
+            containers: self.tracer.registry().map_err(Error::custom)?,
+        })
+    }
+}
+
+fn is_number(value: &serde_value::Value) -> bool {
+    use serde_value::Value;
+
+    match value {
+        Value::Newtype(value) => is_number(value),
+        Value::U8(_)
+        | Value::U16(_)
+        | Value::U32(_)
+        | Value::U64(_)
+        | Value::I8(_)
+        | Value::I16(_)
+        | Value::I32(_)
+        | Value::I64(_)
+        | Value::F32(_)
+        | Value::F64(_) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_reflection::{Named, VariantFormat};
+
+    use crate::testing::{Circle, Path, Shape, Shapes};
+
+    #[test]
+    fn traces_registered_payloads() {
+        let mut tracer = CatalogTracer::<Shapes>::new(TracerConfig::default());
+        tracer.trace_type::<Circle>("circle".to_owned()).unwrap();
+        tracer.trace_type::<Path>("path".to_owned()).unwrap();
+        let catalog = tracer.catalog().unwrap();
+
+        assert_eq!(catalog.type_name, "Box<dyn Shape>");
+        assert_eq!(catalog.field_names, ["kind", "shape"]);
+        assert!(!catalog.numeric_keys);
+        assert_eq!(
+            catalog.payloads.into_iter().collect::<Vec<_>>(),
+            [
+                ("circle".to_owned(), Format::TypeName("Circle".to_owned())),
+                ("path".to_owned(), Format::TypeName("Path".to_owned())),
+            ]
+        );
+        assert_eq!(
+            catalog.containers["Circle"],
+            ContainerFormat::Struct(vec![
+                Named {
+                    name: "radius".to_owned(),
+                    value: Format::F64,
+                },
+                Named {
+                    name: "label".to_owned(),
+                    value: Format::Option(Box::new(Format::Str)),
+                },
+            ])
+        );
+        let ContainerFormat::Enum(variants) = &catalog.containers["Path"] else {
+            panic!("expected an enum");
+        };
+        assert_eq!(variants[&0].value, VariantFormat::Unit);
+        assert_eq!(
+            variants[&1].value,
+            VariantFormat::NewType(Box::new(Format::Seq(Box::new(Format::TupleArray {
+                content: Box::new(Format::I32),
+                size: 2,
+            }))))
+        );
+    }
+
+    #[test]
+    fn traces_objects_and_reports_incomplete_tracing() {
+        let mut tracer = CatalogTracer::<Shapes>::new(TracerConfig::default());
+        let format = tracer
+            .trace_object(&Circle {
+                radius: 1.0,
+                label: None,
+            })
+            .unwrap();
+        assert_eq!(format, &Format::TypeName("Circle".to_owned()));
+        let error = tracer.catalog().err().unwrap();
+        assert!(error.to_string().contains("Circle"));
+
+        let mut tracer = CatalogTracer::<Shapes>::new(TracerConfig::default());
+        tracer.trace_type::<Circle>("circle".to_owned()).unwrap();
+        let error = tracer
+            .trace_type::<Path>("circle".to_owned())
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("Incompatible formats"));
+    }
+
+    #[test]
+    fn records_numeric_keys() {
+        struct Numbered;
+
+        impl Registry for Numbered {
+            type Object = dyn Shape;
+            type Key = u32;
+
+            const TYPE_NAME: &'static str = "Box<dyn Shape>";
+            const FIELD_NAMES: &'static [&'static str; 2] = &["kind", "shape"];
+
+            fn key(_: &dyn Shape) -> u32 {
+                1
+            }
+
+            fn deserialize(
+                _: u32,
+                _: &mut dyn erased_serde::Deserializer,
+            ) -> Result<Box<dyn Shape>, Error> {
+                Err(crate::unknown_key())
+            }
+        }
+
+        let mut tracer = CatalogTracer::<Numbered>::new(TracerConfig::default());
+        tracer.trace_type::<Circle>(1).unwrap();
+        tracer.trace_type::<Path>(20).unwrap();
+        let catalog = tracer.catalog().unwrap();
+
+        assert!(catalog.numeric_keys);
+        assert_eq!(
+            catalog.payloads.keys().collect::<Vec<_>>(),
+            [&"1".to_owned(), &"20".to_owned()]
+        );
+    }
+}
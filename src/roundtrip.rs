@@ -0,0 +1,355 @@
+//! Property-based round trips of keyed values.
+//!
+//! Requires the `proptest` feature.
+//!
+//! [`RoundTrip`] combines [`proptest`](https://crates.io/crates/proptest)
+//! strategies given for the keys of a [`Registry`] into random objects,
+//! writes each of them keyed in every format it's given, as [`Codec`]s, and
+//! reads them back with [`deserialize_by_key`]. The object read must have
+//! the same key and serialize to the same value as the one written, or the
+//! smallest object that fails is returned.
+//!
+//! Values are written in each of the [field orders](FieldOrder) given for
+//! the format, which exercises the different ways [`deserialize_by_key`]
+//! reads them. Formats that aren't self-describing like bincode only read
+//! the fields of a struct in order, so they can only be given
+//! [`FieldOrder::KeyFirst`] and [`FieldOrder::Seq`].
+//!
+//! ```
+//! # use std::fmt::Debug;
+//! # use keyedes::envelope::Codec;
+//! # use keyedes::roundtrip::{FieldOrder, RoundTrip};
+//! # use keyedes::Registry;
+//! # use proptest::prelude::*;
+//! # trait Shape: erased_serde::Serialize + Debug {}
+//! # #[derive(Debug, serde::Serialize)]
+//! # struct Circle { radius: f64 }
+//! # impl Shape for Circle {}
+//! # fn check<Shapes, Json>()
+//! # where
+//! #     Shapes: Registry<Object = dyn Shape>,
+//! #     Json: Codec,
+//! # {
+//! RoundTrip::<Shapes>::new()
+//!     .format::<Json>("json", FieldOrder::ALL)
+//!     .add(any::<f64>().prop_map(|radius| Box::new(Circle { radius }) as Box<dyn Shape>))
+//!     .run(ProptestConfig::default())
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::fmt::Debug;
+
+use proptest::strategy::{BoxedStrategy, Strategy, Union};
+use proptest::test_runner::{Config, TestCaseError, TestError, TestRunner};
+use serde::ser::{Error as _, SerializeStruct, SerializeTuple};
+use serde::{Serialize, Serializer};
+
+use crate::envelope::Codec;
+use crate::private::ErasedSerdeSerializeWrapper;
+use crate::{deserialize_by_key, serialize_with_key, Error, Registry};
+
+/// The order the key and the value of a keyed value are written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldOrder {
+    /// A struct with the key field first, as [`serialize_with_key`] writes.
+    KeyFirst,
+    /// A struct with the content field first.
+    ContentFirst,
+    /// A sequence of the key and the value.
+    Seq,
+}
+
+impl FieldOrder {
+    /// All the field orders.
+    pub const ALL: &'static [FieldOrder] = &[
+        FieldOrder::KeyFirst,
+        FieldOrder::ContentFirst,
+        FieldOrder::Seq,
+    ];
+}
+
+/// Checks round trips of random objects of `R`.
+///
+/// See the [module documentation](self) for details.
+pub struct RoundTrip<R: Registry> {
+    formats: Vec<Format<R>>,
+    strategies: Vec<BoxedStrategy<Box<R::Object>>>,
+}
+
+type EncodeFn<R> = fn(&<R as Registry>::Object, FieldOrder) -> Result<Vec<u8>, Error>;
+type DecodeFn<R> = fn(&[u8]) -> Result<Box<<R as Registry>::Object>, Error>;
+
+struct Format<R: Registry> {
+    name: &'static str,
+    orders: &'static [FieldOrder],
+    encode: EncodeFn<R>,
+    decode: DecodeFn<R>,
+}
+
+impl<R> RoundTrip<R>
+where
+    R: Registry,
+    R::Object: Debug,
+{
+    #[must_use]
+    pub fn new() -> RoundTrip<R> {
+        RoundTrip {
+            formats: Vec::new(),
+            strategies: Vec::new(),
+        }
+    }
+
+    /// Adds a format to write values with in each of the `orders`.
+    pub fn format<C>(&mut self, name: &'static str, orders: &'static [FieldOrder]) -> &mut Self
+    where
+        C: Codec,
+    {
+        self.formats.push(Format {
+            name,
+            orders,
+            encode: encode::<R, C>,
+            decode: decode::<R, C>,
+        });
+        self
+    }
+
+    /// Adds a strategy generating objects, usually of a single key.
+    pub fn add<S>(&mut self, strategy: S) -> &mut Self
+    where
+        S: Strategy<Value = Box<R::Object>> + 'static,
+    {
+        self.strategies.push(strategy.boxed());
+        self
+    }
+
+    /// Returns a strategy generating objects from any of the strategies
+    /// added so far.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if no strategies were added.
+    pub fn strategy(&self) -> impl Strategy<Value = Box<R::Object>> {
+        Union::new(self.strategies.clone())
+    }
+
+    /// Round trips objects generated with `config`, returning the smallest
+    /// one that fails if any.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if no strategies were added.
+    pub fn run(&self, config: Config) -> Result<(), TestError<Box<R::Object>>> {
+        TestRunner::new(config).run(&self.strategy(), |object| {
+            for format in &self.formats {
+                for &order in format.orders {
+                    round_trip(format, order, &*object).map_err(|error| {
+                        TestCaseError::fail(format!("{} {:?}: {}", format.name, order, error))
+                    })?;
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+impl<R> Default for RoundTrip<R>
+where
+    R: Registry,
+    R::Object: Debug,
+{
+    fn default() -> Self {
+        RoundTrip::new()
+    }
+}
+
+fn round_trip<R>(format: &Format<R>, order: FieldOrder, object: &R::Object) -> Result<(), Error>
+where
+    R: Registry,
+{
+    let bytes = (format.encode)(object, order)?;
+    let read = (format.decode)(&bytes)?;
+
+    let read_key = serde_value::to_value(R::key(&*read)).map_err(Error::custom)?;
+    let written_key = serde_value::to_value(R::key(object)).map_err(Error::custom)?;
+    if read_key != written_key {
+        return Err(Error::custom(format_args!(
+            "read key {:?}, wrote key {:?}",
+            read_key, written_key
+        )));
+    }
+
+    let read = serde_value::to_value(ErasedSerdeSerializeWrapper(&*read)).map_err(Error::custom)?;
+    let written =
+        serde_value::to_value(ErasedSerdeSerializeWrapper(object)).map_err(Error::custom)?;
+    if read == written {
+        Ok(())
+    } else {
+        Err(Error::custom(format_args!(
+            "read {:?}, wrote {:?}",
+            read, written
+        )))
+    }
+}
+
+/// An object to be serialized with its key in a given order.
+struct Ordered<'a, R: Registry> {
+    object: &'a R::Object,
+    order: FieldOrder,
+}
+
+impl<'a, R> Serialize for Ordered<'a, R>
+where
+    R: Registry,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let key = R::key(self.object);
+        let value = ErasedSerdeSerializeWrapper(self.object);
+        match self.order {
+            FieldOrder::KeyFirst => {
+                serialize_with_key(R::TYPE_NAME, R::FIELD_NAMES, &key, self.object, serializer)
+            }
+            FieldOrder::ContentFirst => {
+                let mut state = serializer.serialize_struct(R::TYPE_NAME, 2)?;
+                state.serialize_field(R::FIELD_NAMES[1], &value)?;
+                state.serialize_field(R::FIELD_NAMES[0], &key)?;
+                state.end()
+            }
+            FieldOrder::Seq => {
+                let mut state = serializer.serialize_tuple(2)?;
+                state.serialize_element(&key)?;
+                state.serialize_element(&value)?;
+                state.end()
+            }
+        }
+    }
+}
+
+fn encode<R, C>(object: &R::Object, order: FieldOrder) -> Result<Vec<u8>, Error>
+where
+    R: Registry,
+    C: Codec,
+{
+    C::encode(&Ordered::<R> { object, order })
+}
+
+fn decode<R, C>(bytes: &[u8]) -> Result<Box<R::Object>, Error>
+where
+    R: Registry,
+    C: Codec,
+{
+    C::decode(bytes, |deserializer| {
+        deserialize_by_key(R::TYPE_NAME, R::FIELD_NAMES, R::deserialize, deserializer)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    use crate::testing::{Bincode, Circle, Counter, Json, Path, Shape, Shapes};
+
+    fn config() -> ProptestConfig {
+        ProptestConfig {
+            cases: 64,
+            failure_persistence: None,
+            ..ProptestConfig::default()
+        }
+    }
+
+    fn round_trip() -> RoundTrip<Shapes> {
+        let mut round_trip = RoundTrip::<Shapes>::new();
+        round_trip
+            .format::<Json>("json", FieldOrder::ALL)
+            .format::<Bincode>("bincode", &[FieldOrder::KeyFirst, FieldOrder::Seq])
+            .add(
+                (-1_000_000..1_000_000, proptest::option::of("[a-z]{0,8}")).prop_map(
+                    |(radius, label)| {
+                        // serde_json doesn't read back every float exactly
+                        let radius = f64::from(radius) / 4.0;
+                        Box::new(Circle { radius, label }) as Box<dyn Shape>
+                    },
+                ),
+            )
+            .add(
+                prop_oneof![
+                    Just(Path::Closed),
+                    proptest::collection::vec(any::<(i32, i32)>(), 0..8).prop_map(Path::Points),
+                ]
+                .prop_map(|path| Box::new(path) as Box<dyn Shape>),
+            );
+        round_trip
+    }
+
+    #[test]
+    fn round_trips_in_every_order_and_format() {
+        round_trip().run(config()).unwrap();
+    }
+
+    #[test]
+    fn shrinks_objects_that_fail() {
+        let mut round_trip = round_trip();
+        round_trip
+            .add((0..1000_u32).prop_map(|count| Box::new(Counter { count }) as Box<dyn Shape>));
+        match round_trip.run(config()) {
+            Err(TestError::Fail(reason, object)) => {
+                assert!(reason.message().starts_with("json KeyFirst: read"));
+                assert_eq!(format!("{:?}", object), "Counter { count: 1 }");
+            }
+            result => panic!("expected a failure, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn reports_objects_read_with_another_key() {
+        /// Reads paths back as routes, which serialize the same.
+        #[derive(Debug, Serialize, serde::Deserialize)]
+        #[serde(transparent)]
+        struct Route(Path);
+
+        impl Shape for Route {
+            fn name(&self) -> &'static str {
+                "route"
+            }
+        }
+
+        struct Routes;
+
+        impl Registry for Routes {
+            type Object = dyn Shape;
+            type Key = String;
+
+            const TYPE_NAME: &'static str = "Box<dyn Shape>";
+            const FIELD_NAMES: &'static [&'static str; 2] = &["kind", "shape"];
+
+            fn key(object: &dyn Shape) -> String {
+                object.name().to_owned()
+            }
+
+            fn deserialize(
+                _: String,
+                deserializer: &mut dyn erased_serde::Deserializer,
+            ) -> Result<Box<dyn Shape>, Error> {
+                erased_serde::deserialize::<Route>(deserializer)
+                    .map(|route| Box::new(route) as Box<dyn Shape>)
+            }
+        }
+
+        let result = RoundTrip::<Routes>::new()
+            .format::<Json>("json", &[FieldOrder::KeyFirst])
+            .add(Just(Path::Closed).prop_map(|path| Box::new(path) as Box<dyn Shape>))
+            .run(config());
+        match result {
+            Err(TestError::Fail(reason, _)) => assert_eq!(
+                reason.message(),
+                r#"json KeyFirst: read key String("route"), wrote key String("path")"#
+            ),
+            result => panic!("expected a failure, got {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! JSON Schema of the keyed values of a registry.
+//!
+//! Requires the `json-schema` feature.
+//!
+//! A [`SchemaBuilder`] collects the [`JsonSchema`] of the payload of each key
+//! of a [`Registry`] and builds a schema that accepts any of them, with the
+//! key as a `const` in the tag field:
+//!
+//! ```json
+//! {
+//!   "title": "Box<dyn Shape>",
+//!   "oneOf": [{"$ref": "#/$defs/kind=circle"}],
+//!   "discriminator": {
+//!     "propertyName": "kind",
+//!     "mapping": {"circle": "#/$defs/kind=circle"}
+//!   },
+//!   "$defs": {
+//!     "kind=circle": {
+//!       "type": "object",
+//!       "properties": {
+//!         "kind": {"const": "circle"},
+//!         "shape": {"$ref": "#/$defs/Circle"}
+//!       },
+//!       "required": ["kind", "shape"]
+//!     },
+//!     "Circle": {"...": "..."}
+//!   }
+//! }
+//! ```
+//!
+//! The tag and content fields are the [`FIELD_NAMES`](Registry::FIELD_NAMES)
+//! of the registry, as passed to [`serialize_with_key`](crate::serialize_with_key).
+//! With [`Tagging::Internal`], the payload schema is merged with the tag
+//! field instead, as written by
+//! [`serialize_with_tag`](crate::internal::serialize_with_tag). Payloads
+//! whose schema only allows `null`, like units, are written as the tag alone,
+//! and other payloads whose schema doesn't allow an object can't be written
+//! there at all. The `discriminator` is the one of OpenAPI, which JSON Schema
+//! validators ignore.
+//!
+//! The schema of each key is added to the definitions of the
+//! [`SchemaGenerator`] as `{tag}={key}`, so that the `mapping` can refer to
+//! it, and the settings of the generator decide where the definitions go.
+
+use std::marker::PhantomData;
+
+use schemars::{JsonSchema, Schema, SchemaGenerator};
+use serde::ser::Error as _;
+use serde_json::{json, Map, Value};
+
+use crate::{Error, Registry};
+
+/// How keyed values are represented.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tagging {
+    /// The key and payload are in two fields, like [`serialize_with_key`](crate::serialize_with_key).
+    Adjacent,
+    /// The key is next to the fields of the payload, like
+    /// [`serialize_with_tag`](crate::internal::serialize_with_tag).
+    Internal,
+}
+
+/// Builds the JSON Schema of the keyed values of `R`.
+///
+/// See the [module documentation](self) for details.
+pub struct SchemaBuilder<R> {
+    generator: SchemaGenerator,
+    tagging: Tagging,
+    keys: Vec<(String, Value)>,
+    _registry: PhantomData<R>,
+}
+
+impl<R> SchemaBuilder<R>
+where
+    R: Registry,
+{
+    #[must_use]
+    pub fn new(generator: SchemaGenerator, tagging: Tagging) -> SchemaBuilder<R> {
+        SchemaBuilder {
+            generator,
+            tagging,
+            keys: Vec::new(),
+            _registry: PhantomData,
+        }
+    }
+
+    /// Adds `key` with a payload of type `T`.
+    ///
+    /// This will return an error if the key can't be written as JSON or was
+    /// already added, or with [`Tagging::Internal`] if the payload can't be
+    /// written next to the tag.
+    pub fn add<T>(&mut self, key: &R::Key) -> Result<&mut Self, Error>
+    where
+        T: ?Sized + JsonSchema,
+    {
+        let [tag, content] = *R::FIELD_NAMES;
+        let key = serde_json::to_value(key).map_err(Error::custom)?;
+        let name = match &key {
+            Value::String(key) => key.clone(),
+            key => key.to_string(),
+        };
+        if self.keys.iter().any(|(added, _)| *added == name) {
+            return Err(Error::custom(format_args!(
+                "key `{}` was already added",
+                name
+            )));
+        }
+
+        let payload = self.generator.subschema_for::<T>();
+        let schema = match self.tagging {
+            Tagging::Adjacent => json!({
+                "type": "object",
+                "properties": {
+                    tag: { "const": key },
+                    content: payload,
+                },
+                "required": [tag, content],
+            }),
+            Tagging::Internal => {
+                let tagged = json!({
+                    "type": "object",
+                    "properties": { tag: { "const": key } },
+                    "required": [tag],
+                });
+                match self.types(&payload) {
+                    Some(types) if types == ["null"] => tagged,
+                    Some(types) if !types.contains(&"object") => {
+                        return Err(Error::custom(format_args!(
+                            "payload of `{}` can't be written next to the tag",
+                            name
+                        )))
+                    }
+                    _ => json!({ "allOf": [tagged, payload] }),
+                }
+            }
+        };
+
+        self.keys.push((name, schema));
+        Ok(self)
+    }
+
+    /// The types the schema allows, following references to definitions, or
+    /// `None` if it doesn't say.
+    fn types<'a>(&'a self, schema: &'a Schema) -> Option<Vec<&'a str>> {
+        let mut schema = schema.as_value();
+        while let Some(Value::String(reference)) = schema.get("$ref") {
+            let prefix = format!("#{}/", definitions_path(&self.generator));
+            let name = unescape(reference.strip_prefix(&prefix)?);
+            schema = self.generator.definitions().get(&name)?;
+        }
+
+        match schema.get("type")? {
+            Value::String(ty) => Some(vec![ty.as_str()]),
+            Value::Array(types) => types.iter().map(Value::as_str).collect(),
+            _ => None,
+        }
+    }
+
+    /// Builds the schema accepting any of the keys added so far.
+    #[must_use]
+    pub fn build(mut self) -> Schema {
+        let tag = R::FIELD_NAMES[0];
+        let path = definitions_path(&self.generator);
+
+        let mut one_of = Vec::new();
+        let mut mapping = Map::new();
+        for (name, schema) in self.keys {
+            let definition = format!("{}={}", tag, name);
+            let reference = format!("#{}/{}", path, escape(&definition));
+            self.generator.definitions_mut().insert(definition, schema);
+            one_of.push(json!({ "$ref": reference }));
+            mapping.insert(name, Value::String(reference));
+        }
+
+        let mut root = Map::new();
+        if let Some(meta_schema) = &self.generator.settings().meta_schema {
+            root.insert("$schema".to_owned(), json!(meta_schema));
+        }
+        root.insert("title".to_owned(), json!(R::TYPE_NAME));
+        root.insert("oneOf".to_owned(), Value::Array(one_of));
+        root.insert(
+            "discriminator".to_owned(),
+            json!({ "propertyName": tag, "mapping": mapping }),
+        );
+
+        let definitions = self.generator.take_definitions(true);
+        let mut target = &mut root;
+        for segment in path.split('/').skip(1) {
+            target = target
+                .entry(unescape(segment))
+                .or_insert_with(|| Value::Object(Map::new()))
+                .as_object_mut()
+                .expect("definitions path goes through objects");
+        }
+        target.extend(definitions);
+
+        let mut schema = Schema::from(root);
+        for transform in self.generator.transforms_mut() {
+            transform.transform(&mut schema);
+        }
+        schema
+    }
+}
+
+/// Returns the definitions path of the generator as a JSON pointer.
+fn definitions_path(generator: &SchemaGenerator) -> String {
+    let path = &generator.settings().definitions_path;
+    let path = path.strip_prefix('#').unwrap_or(path);
+    path.strip_suffix('/').unwrap_or(path).to_owned()
+}
+
+fn escape(segment: &str) -> String {
+    segment.replace('~', "~0").replace('/', "~1")
+}
+
+fn unescape(segment: &str) -> String {
+    segment.replace("~1", "/").replace("~0", "~")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use schemars::generate::SchemaSettings;
+
+    #[derive(JsonSchema)]
+    #[allow(dead_code)]
+    struct Circle {
+        radius: f64,
+    }
+
+    use crate::testing::Shapes;
+
+    fn build<T>(settings: SchemaSettings, tagging: Tagging) -> Value
+    where
+        T: ?Sized + JsonSchema,
+    {
+        let mut builder = SchemaBuilder::<Shapes>::new(settings.into_generator(), tagging);
+        builder
+            .add::<Circle>(&"circle".to_owned())
+            .unwrap()
+            .add::<T>(&"a/b".to_owned())
+            .unwrap();
+        builder.build().to_value()
+    }
+
+    #[test]
+    fn builds_adjacently_tagged_schemas() {
+        assert_eq!(
+            build::<Option<u32>>(SchemaSettings::draft2020_12(), Tagging::Adjacent),
+            json!({
+                "$schema": "https://json-schema.org/draft/2020-12/schema",
+                "title": "Box<dyn Shape>",
+                "oneOf": [
+                    { "$ref": "#/$defs/kind=circle" },
+                    { "$ref": "#/$defs/kind=a~1b" },
+                ],
+                "discriminator": {
+                    "propertyName": "kind",
+                    "mapping": {
+                        "circle": "#/$defs/kind=circle",
+                        "a/b": "#/$defs/kind=a~1b",
+                    },
+                },
+                "$defs": {
+                    "Circle": {
+                        "type": "object",
+                        "properties": {
+                            "radius": { "type": "number", "format": "double" },
+                        },
+                        "required": ["radius"],
+                    },
+                    "kind=circle": {
+                        "type": "object",
+                        "properties": {
+                            "kind": { "const": "circle" },
+                            "shape": { "$ref": "#/$defs/Circle" },
+                        },
+                        "required": ["kind", "shape"],
+                    },
+                    "kind=a/b": {
+                        "type": "object",
+                        "properties": {
+                            "kind": { "const": "a/b" },
+                            "shape": {
+                                "type": ["integer", "null"],
+                                "format": "uint32",
+                                "minimum": 0,
+                            },
+                        },
+                        "required": ["kind", "shape"],
+                    },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn builds_internally_tagged_schemas() {
+        let schema = build::<()>(SchemaSettings::openapi3(), Tagging::Internal);
+        assert_eq!(
+            schema["discriminator"]["mapping"]["circle"],
+            "#/components/schemas/kind=circle"
+        );
+        assert_eq!(
+            schema["components"]["schemas"]["kind=circle"],
+            json!({
+                "allOf": [
+                    {
+                        "type": "object",
+                        "properties": { "kind": { "enum": ["circle"] } },
+                        "required": ["kind"],
+                    },
+                    { "$ref": "#/components/schemas/Circle" },
+                ],
+            })
+        );
+        assert_eq!(
+            schema["components"]["schemas"]["kind=a/b"],
+            json!({
+                "type": "object",
+                "properties": { "kind": { "enum": ["a/b"] } },
+                "required": ["kind"],
+            })
+        );
+
+        let mut builder = SchemaBuilder::<Shapes>::new(
+            SchemaSettings::openapi3().into_generator(),
+            Tagging::Internal,
+        );
+        let error = builder.add::<Option<u32>>(&"a/b".to_owned()).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "payload of `a/b` can't be written next to the tag"
+        );
+    }
+
+    #[test]
+    fn rejects_keys_added_twice() {
+        let mut builder = SchemaBuilder::<Shapes>::new(
+            SchemaSettings::draft2020_12().into_generator(),
+            Tagging::Adjacent,
+        );
+        builder.add::<Circle>(&"circle".to_owned()).unwrap();
+        let error = builder.add::<()>(&"circle".to_owned()).err().unwrap();
+        assert_eq!(error.to_string(), "key `circle` was already added");
+
+        let schema = builder.build().to_value();
+        assert_eq!(schema["oneOf"], json!([{ "$ref": "#/$defs/kind=circle" }]));
+    }
+}
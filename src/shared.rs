@@ -0,0 +1,609 @@
+//! Serialization of shared pointers that preserves their sharing.
+//!
+//! Using [`Keyed<Shared<R>>`](crate::Keyed) instead of `Keyed<R>` on `Arc`
+//! and `Rc` fields will give each pointer an id the first time it is
+//! serialized and write only a reference to that id the next times. When
+//! deserializing, references resolve to the same pointer again.
+//!
+//! The ids are only meaningful within a single [`scope()`], so serialization
+//! and deserialization must happen within one and will return an error
+//! otherwise. A definition is written as `{"def":[id,{...}]}` and a reference
+//! as `{"ref":id}` in JSON. Pointers that reference themselves, directly or
+//! indirectly, can't be rebuilt and will return an error.
+//!
+//! ```
+//! # mod outer {
+//! use std::sync::Arc;
+//!
+//! use keyedes::shared::Shared;
+//! use keyedes::Keyed;
+//! use serde::{Deserialize, Serialize};
+//!
+//! trait Node: erased_serde::Serialize {}
+//! # struct Nodes;
+//! # impl keyedes::Registry for Nodes {
+//! #     type Object = dyn Node;
+//! #     type Key = String;
+//! #     const TYPE_NAME: &'static str = "Arc<dyn Node>";
+//! #     const FIELD_NAMES: &'static [&'static str; 2] = &["id", "data"];
+//! #     fn key(_: &dyn Node) -> String { "node".to_owned() }
+//! #     fn deserialize(
+//! #         _: String,
+//! #         _: &mut dyn erased_serde::Deserializer,
+//! #     ) -> Result<Box<dyn Node>, keyedes::Error> {
+//! #         Err(keyedes::unknown_key())
+//! #     }
+//! # }
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Graph {
+//!     #[serde(with = "Keyed::<Shared<Nodes>>")]
+//!     nodes: Vec<Arc<dyn Node>>,
+//! }
+//!
+//! fn save(graph: &Graph) -> String {
+//!     keyedes::shared::scope(|| serde_json::to_string(graph).unwrap())
+//! }
+//! # }
+//! ```
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use serde::de::{DeserializeSeed, EnumAccess, Error as _, SeqAccess, VariantAccess, Visitor};
+use serde::ser::{Error as _, SerializeTupleVariant};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{deserialize_by_key, serialize_with_key, KeyedValue, Registry};
+
+/// Marker for serializing pointers to objects of `R` with sharing preserved.
+///
+/// See the [module documentation](self) for details.
+pub struct Shared<R>(PhantomData<R>);
+
+const VARIANTS: &[&str] = &["def", "ref"];
+
+#[derive(Default)]
+struct State {
+    depth: usize,
+    serialized: HashMap<usize, u64>,
+    serializing: HashSet<usize>,
+    /// Clones of the pointers serialized so far, so that their addresses
+    /// can't be reused by other pointers while the scope lasts.
+    kept: Vec<Box<dyn Any>>,
+    deserialized: HashMap<u64, Box<dyn Any>>,
+    deserializing: HashSet<u64>,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+/// Will run `f` with a fresh table of shared pointers for the current thread.
+///
+/// All [`Shared`] pointers serialized or deserialized within `f` use the same
+/// table, and the table is cleared when `f` returns. Nested calls reuse the
+/// outer table.
+///
+/// Serializing or deserializing a [`Shared`] pointer outside of this function
+/// will return an error.
+pub fn scope<T, F>(f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    struct Guard;
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            STATE.with(|state| {
+                let mut state = state.borrow_mut();
+                state.depth -= 1;
+                if state.depth == 0 {
+                    *state = State::default();
+                }
+            });
+        }
+    }
+
+    STATE.with(|state| state.borrow_mut().depth += 1);
+    let _guard = Guard;
+    f()
+}
+
+/// Runs `f` with the table of the current scope, or returns an error made
+/// with `custom` if there is none.
+fn with_state<T, E, F>(custom: fn(&'static str) -> E, f: F) -> Result<T, E>
+where
+    F: FnOnce(&mut State) -> Result<T, E>,
+{
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.depth == 0 {
+            return Err(custom(
+                "shared pointers must be used within `keyedes::shared::scope`",
+            ));
+        }
+        f(&mut state)
+    })
+}
+
+/// Common handling for the pointer types supported by [`Shared`].
+trait SharedPointer: Clone + 'static {
+    type Object: ?Sized;
+
+    fn from_box(value: Box<Self::Object>) -> Self;
+    fn address(&self) -> usize;
+}
+
+impl<T: ?Sized + 'static> SharedPointer for Arc<T> {
+    type Object = T;
+
+    fn from_box(value: Box<T>) -> Self {
+        Arc::from(value)
+    }
+
+    fn address(&self) -> usize {
+        Arc::as_ptr(self) as *const () as usize
+    }
+}
+
+impl<T: ?Sized + 'static> SharedPointer for Rc<T> {
+    type Object = T;
+
+    fn from_box(value: Box<T>) -> Self {
+        Rc::from(value)
+    }
+
+    fn address(&self) -> usize {
+        Rc::as_ptr(self) as *const () as usize
+    }
+}
+
+fn serialize_shared<R, P, S>(pointer: &P, serializer: S) -> Result<S::Ok, S::Error>
+where
+    R: Registry,
+    P: SharedPointer<Object = R::Object> + std::ops::Deref<Target = R::Object>,
+    S: Serializer,
+{
+    let address = pointer.address();
+    let (id, is_new) = with_state(S::Error::custom, |state| {
+        match state.serialized.get(&address) {
+            Some(_) if state.serializing.contains(&address) => Err(S::Error::custom(
+                "shared pointer references itself and can't be serialized",
+            )),
+            Some(id) => Ok((*id, false)),
+            None => {
+                let id = state.serialized.len() as u64;
+                state.serialized.insert(address, id);
+                state.serializing.insert(address);
+                state.kept.push(Box::new(pointer.clone()));
+                Ok((id, true))
+            }
+        }
+    })?;
+
+    if !is_new {
+        return serializer.serialize_newtype_variant(R::TYPE_NAME, 1, "ref", &id);
+    }
+
+    let object: &R::Object = pointer;
+    let result = (|| {
+        let mut state = serializer.serialize_tuple_variant(R::TYPE_NAME, 0, "def", 2)?;
+        state.serialize_field(&id)?;
+        state.serialize_field(&KeyedObject::<R>(object))?;
+        state.end()
+    })();
+
+    with_state(S::Error::custom, |state| {
+        Ok(state.serializing.remove(&address))
+    })?;
+    result
+}
+
+fn deserialize_shared<'de, R, P, D>(deserializer: D) -> Result<P, D::Error>
+where
+    R: Registry,
+    P: SharedPointer<Object = R::Object>,
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_enum(R::TYPE_NAME, VARIANTS, SharedVisitor::<R, P>(PhantomData))
+}
+
+struct KeyedObject<'a, R: Registry>(&'a R::Object);
+
+impl<'a, R: Registry> Serialize for KeyedObject<'a, R> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_with_key(
+            R::TYPE_NAME,
+            R::FIELD_NAMES,
+            &R::key(self.0),
+            self.0,
+            serializer,
+        )
+    }
+}
+
+enum Variant {
+    Def,
+    Ref,
+}
+
+impl<'de> Deserialize<'de> for Variant {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct VariantVisitor;
+
+        impl<'de> Visitor<'de> for VariantVisitor {
+            type Value = Variant;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("variant identifier")
+            }
+
+            fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                match value {
+                    0 => Ok(Variant::Def),
+                    1 => Ok(Variant::Ref),
+                    _ => Err(E::invalid_value(
+                        serde::de::Unexpected::Unsigned(value),
+                        &"variant index 0 <= i < 2",
+                    )),
+                }
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                match value {
+                    "def" => Ok(Variant::Def),
+                    "ref" => Ok(Variant::Ref),
+                    _ => Err(E::unknown_variant(value, VARIANTS)),
+                }
+            }
+        }
+
+        deserializer.deserialize_identifier(VariantVisitor)
+    }
+}
+
+struct SharedVisitor<R, P>(PhantomData<fn() -> (R, P)>);
+
+impl<'de, R, P> Visitor<'de> for SharedVisitor<R, P>
+where
+    R: Registry,
+    P: SharedPointer<Object = R::Object>,
+{
+    type Value = P;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("shared pointer definition or reference")
+    }
+
+    fn visit_enum<A>(self, data: A) -> Result<Self::Value, A::Error>
+    where
+        A: EnumAccess<'de>,
+    {
+        match data.variant()? {
+            (Variant::Def, variant) => {
+                variant.tuple_variant(2, DefinitionVisitor::<R, P>(PhantomData))
+            }
+            (Variant::Ref, variant) => {
+                let id: u64 = variant.newtype_variant()?;
+                with_state(A::Error::custom, |state| {
+                    if state.deserializing.contains(&id) {
+                        return Err(A::Error::custom(format_args!(
+                            "shared pointer {} references itself",
+                            id
+                        )));
+                    }
+                    match state.deserialized.get(&id) {
+                        Some(value) => value.downcast_ref::<P>().cloned().ok_or_else(|| {
+                            A::Error::custom(format_args!(
+                                "shared pointer {} has a different type",
+                                id
+                            ))
+                        }),
+                        None => Err(A::Error::custom(format_args!(
+                            "unknown shared pointer {}",
+                            id
+                        ))),
+                    }
+                })
+            }
+        }
+    }
+}
+
+struct DefinitionVisitor<R, P>(PhantomData<fn() -> (R, P)>);
+
+impl<'de, R, P> Visitor<'de> for DefinitionVisitor<R, P>
+where
+    R: Registry,
+    P: SharedPointer<Object = R::Object>,
+{
+    type Value = P;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("shared pointer definition")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let id: u64 = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+
+        with_state(A::Error::custom, |state| {
+            if state.deserialized.contains_key(&id) || !state.deserializing.insert(id) {
+                return Err(A::Error::custom(format_args!(
+                    "shared pointer {} is defined more than once",
+                    id
+                )));
+            }
+            Ok(())
+        })?;
+
+        let result = seq.next_element_seed(ObjectSeed::<R>(PhantomData));
+        with_state(A::Error::custom, |state| {
+            Ok(state.deserializing.remove(&id))
+        })?;
+
+        let pointer = P::from_box(result?.ok_or_else(|| A::Error::invalid_length(1, &self))?);
+        with_state(A::Error::custom, |state| {
+            state.deserialized.insert(id, Box::new(pointer.clone()));
+            Ok(())
+        })?;
+
+        Ok(pointer)
+    }
+}
+
+struct ObjectSeed<R>(PhantomData<R>);
+
+impl<'de, R: Registry> DeserializeSeed<'de> for ObjectSeed<R> {
+    type Value = Box<R::Object>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_by_key(R::TYPE_NAME, R::FIELD_NAMES, R::deserialize, deserializer)
+    }
+}
+
+impl<R, P> KeyedValue<Shared<R>> for P
+where
+    R: Registry,
+    P: SharedPointer<Object = R::Object> + std::ops::Deref<Target = R::Object>,
+{
+    fn serialize_keyed<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_shared::<R, _, _>(self, serializer)
+    }
+
+    fn deserialize_keyed<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_shared::<R, _, _>(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    use crate::{Error, Keyed};
+
+    trait Node: erased_serde::Serialize + Send + Sync {
+        fn name(&self) -> &str;
+        fn children(&self) -> Vec<Arc<dyn Node>>;
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Branch {
+        name: String,
+        #[serde(with = "Keyed::<Shared<Nodes>>")]
+        children: Mutex<Vec<Arc<dyn Node>>>,
+    }
+
+    impl Node for Branch {
+        fn name(&self) -> &str {
+            &self.name
+        }
+        fn children(&self) -> Vec<Arc<dyn Node>> {
+            self.children.lock().unwrap().clone()
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Leaf {
+        name: String,
+    }
+
+    impl Node for Leaf {
+        fn name(&self) -> &str {
+            &self.name
+        }
+        fn children(&self) -> Vec<Arc<dyn Node>> {
+            Vec::new()
+        }
+    }
+
+    struct Nodes;
+
+    impl Registry for Nodes {
+        type Object = dyn Node;
+        type Key = String;
+
+        const TYPE_NAME: &'static str = "Arc<dyn Node>";
+        const FIELD_NAMES: &'static [&'static str; 2] = &["id", "data"];
+
+        fn key(object: &dyn Node) -> String {
+            if object.children().is_empty() {
+                "leaf".to_string()
+            } else {
+                "branch".to_string()
+            }
+        }
+
+        fn deserialize(
+            key: String,
+            deserializer: &mut dyn erased_serde::Deserializer,
+        ) -> Result<Box<dyn Node>, Error> {
+            match key.as_str() {
+                "leaf" => erased_serde::deserialize::<Leaf>(deserializer)
+                    .map(|v| Box::new(v) as Box<dyn Node>),
+                "branch" => erased_serde::deserialize::<Branch>(deserializer)
+                    .map(|v| Box::new(v) as Box<dyn Node>),
+                _ => Err(crate::unknown_key()),
+            }
+        }
+    }
+
+    impl<R> KeyedValue<R> for Mutex<Vec<Arc<dyn Node>>>
+    where
+        Vec<Arc<dyn Node>>: KeyedValue<R>,
+    {
+        fn serialize_keyed<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            KeyedValue::<R>::serialize_keyed(&*self.lock().unwrap(), serializer)
+        }
+
+        fn deserialize_keyed<'de, D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            Vec::deserialize_keyed(deserializer).map(Mutex::new)
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Graph {
+        #[serde(with = "Keyed::<Shared<Nodes>>")]
+        roots: Vec<Arc<dyn Node>>,
+    }
+
+    #[test]
+    fn shared_pointers_are_deduplicated() {
+        let leaf = Arc::new(Leaf {
+            name: "leaf".to_string(),
+        }) as Arc<dyn Node>;
+        let branch = Arc::new(Branch {
+            name: "branch".to_string(),
+            children: Mutex::new(vec![leaf.clone(), leaf.clone()]),
+        }) as Arc<dyn Node>;
+        let graph = Graph {
+            roots: vec![branch, leaf],
+        };
+
+        let json = scope(|| serde_json::to_string(&graph)).unwrap();
+        assert_eq!(
+            json,
+            concat!(
+                r#"{"roots":[{"def":[0,{"id":"branch","data":{"name":"branch","children":["#,
+                r#"{"def":[1,{"id":"leaf","data":{"name":"leaf"}}]},{"ref":1}]}}]},"#,
+                r#"{"ref":1}]}"#,
+            )
+        );
+
+        let graph: Graph = scope(|| serde_json::from_str(&json)).unwrap();
+        let children = graph.roots[0].children();
+        assert_eq!(graph.roots[0].name(), "branch");
+        assert_eq!(children[0].name(), "leaf");
+        assert!(Arc::ptr_eq(&children[0], &children[1]));
+        assert!(Arc::ptr_eq(&children[0], &graph.roots[1]));
+    }
+
+    #[test]
+    fn temporary_pointers_are_not_confused() {
+        let json: Vec<String> = scope(|| {
+            (0..3)
+                .map(|i| {
+                    let graph = Graph {
+                        roots: vec![Arc::new(Leaf {
+                            name: i.to_string(),
+                        })],
+                    };
+                    serde_json::to_string(&graph).unwrap()
+                })
+                .collect()
+        });
+        assert_eq!(
+            json,
+            [
+                r#"{"roots":[{"def":[0,{"id":"leaf","data":{"name":"0"}}]}]}"#,
+                r#"{"roots":[{"def":[1,{"id":"leaf","data":{"name":"1"}}]}]}"#,
+                r#"{"roots":[{"def":[2,{"id":"leaf","data":{"name":"2"}}]}]}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn shared_pointers_return_error_on_cycle() {
+        let branch = Arc::new(Branch {
+            name: "branch".to_string(),
+            children: Mutex::new(vec![Arc::new(Leaf {
+                name: "leaf".to_string(),
+            })]),
+        });
+        branch.children.lock().unwrap().push(branch.clone());
+        let graph = Graph {
+            roots: vec![branch.clone()],
+        };
+
+        let error = scope(|| serde_json::to_string(&graph)).err().unwrap();
+        assert!(error.to_string().contains("references itself"));
+        branch.children.lock().unwrap().clear();
+
+        let json =
+            r#"{"roots":[{"def":[0,{"id":"branch","data":{"name":"b","children":[{"ref":0}]}}]}]}"#;
+        let error = scope(|| serde_json::from_str::<Graph>(json)).err().unwrap();
+        assert!(error.to_string().contains("references itself"));
+
+        let json = r#"{"roots":[{"ref":3}]}"#;
+        let error = scope(|| serde_json::from_str::<Graph>(json)).err().unwrap();
+        assert!(error.to_string().contains("unknown shared pointer"));
+    }
+
+    #[test]
+    fn shared_pointers_return_error_outside_scope() {
+        let graph = Graph {
+            roots: vec![Arc::new(Leaf {
+                name: "leaf".to_string(),
+            })],
+        };
+        let error = serde_json::to_string(&graph).err().unwrap();
+        assert!(error
+            .to_string()
+            .contains("within `keyedes::shared::scope`"));
+
+        let json = r#"{"roots":[{"def":[0,{"id":"leaf","data":{"name":"leaf"}}]}]}"#;
+        let error = serde_json::from_str::<Graph>(json).err().unwrap();
+        assert!(error
+            .to_string()
+            .contains("within `keyedes::shared::scope`"));
+    }
+}
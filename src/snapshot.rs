@@ -0,0 +1,402 @@
+//! Comparing catalogs of a registry from one release to the next.
+//!
+//! Requires the `serde-reflection` feature.
+//!
+//! A [`Catalog`] can be written to a file with any serde format to keep a
+//! snapshot of the keys of a registry and the formats of their payloads.
+//! [`diff`] compares such a snapshot with the current catalog and returns
+//! the [changes](Change) between them, each either breaking or compatible:
+//! a change is compatible if data written with the old catalog can still be
+//! read with the new one.
+//!
+//! ```
+//! # use keyedes::reflection::Catalog;
+//! # use keyedes::snapshot::diff;
+//! fn check(snapshot: &str, current: &Catalog) {
+//!     let snapshot: Catalog = serde_json::from_str(snapshot).unwrap();
+//!     let breaking: Vec<_> = diff(&snapshot, current)
+//!         .into_iter()
+//!         .filter(|change| change.is_breaking())
+//!         .map(|change| change.to_string())
+//!         .collect();
+//!     assert!(breaking.is_empty(), "breaking changes: {:#?}", breaking);
+//! }
+//! ```
+//!
+//! Struct fields and enum variants are compared by name, as they are read by
+//! self-describing formats like JSON. Compact formats like bincode that
+//! write them by position can't read old data after fields are added or
+//! variants are reordered.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use serde_reflection::{ContainerFormat, Format, Named, VariantFormat};
+
+use crate::reflection::Catalog;
+
+/// A change between two catalogs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// The tag and content fields changed.
+    FieldNamesChanged { old: [String; 2], new: [String; 2] },
+    /// The keys changed from strings to numbers, or the other way around.
+    KeysChanged { numeric: bool },
+    /// A key was added.
+    KeyAdded { key: String },
+    /// A key was removed, so its values can't be read anymore.
+    KeyRemoved { key: String },
+    /// A key has a payload of a different format, such as a different type
+    /// reusing the key.
+    PayloadChanged {
+        key: String,
+        old: Format,
+        new: Format,
+    },
+    /// A container changed from one kind to another, such as a struct to an
+    /// enum.
+    ContainerChanged {
+        container: String,
+        old: ContainerFormat,
+        new: ContainerFormat,
+    },
+    /// A struct has a new field, which is compatible only if it's an option.
+    FieldAdded {
+        container: String,
+        field: String,
+        format: Format,
+    },
+    /// A struct lost a field.
+    FieldRemoved { container: String, field: String },
+    /// A field of a struct has a different format, which is compatible only
+    /// if it became an option of its old format.
+    FieldChanged {
+        container: String,
+        field: String,
+        old: Format,
+        new: Format,
+    },
+    /// An enum has a new variant.
+    VariantAdded { container: String, variant: String },
+    /// An enum lost a variant.
+    VariantRemoved { container: String, variant: String },
+    /// A variant of an enum has a different format.
+    VariantChanged {
+        container: String,
+        variant: String,
+        old: VariantFormat,
+        new: VariantFormat,
+    },
+}
+
+impl Change {
+    /// Returns whether data written before the change can't be read after it.
+    #[must_use]
+    pub fn is_breaking(&self) -> bool {
+        match self {
+            Change::KeyAdded { .. } | Change::VariantAdded { .. } => false,
+            Change::FieldAdded { format, .. } => !matches!(format, Format::Option(_)),
+            Change::FieldChanged { old, new, .. } => !is_optional(old, new),
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::FieldNamesChanged { old, new } => write!(
+                f,
+                "field names changed from `{}`, `{}` to `{}`, `{}`",
+                old[0], old[1], new[0], new[1]
+            ),
+            Change::KeysChanged { numeric } => write!(
+                f,
+                "keys changed to {}",
+                if *numeric { "numbers" } else { "strings" }
+            ),
+            Change::KeyAdded { key } => write!(f, "key `{}` added", key),
+            Change::KeyRemoved { key } => write!(f, "key `{}` removed", key),
+            Change::PayloadChanged { key, old, new } => {
+                write!(
+                    f,
+                    "payload of key `{}` changed from {:?} to {:?}",
+                    key, old, new
+                )
+            }
+            Change::ContainerChanged { container, .. } => {
+                write!(f, "`{}` changed to another kind of container", container)
+            }
+            Change::FieldAdded {
+                container, field, ..
+            } => write!(f, "field `{}.{}` added", container, field),
+            Change::FieldRemoved { container, field } => {
+                write!(f, "field `{}.{}` removed", container, field)
+            }
+            Change::FieldChanged {
+                container,
+                field,
+                old,
+                new,
+            } => write!(
+                f,
+                "field `{}.{}` changed from {:?} to {:?}",
+                container, field, old, new
+            ),
+            Change::VariantAdded { container, variant } => {
+                write!(f, "variant `{}::{}` added", container, variant)
+            }
+            Change::VariantRemoved { container, variant } => {
+                write!(f, "variant `{}::{}` removed", container, variant)
+            }
+            Change::VariantChanged {
+                container, variant, ..
+            } => write!(f, "variant `{}::{}` changed", container, variant),
+        }
+    }
+}
+
+fn is_optional(old: &Format, new: &Format) -> bool {
+    matches!(new, Format::Option(format) if **format == *old)
+}
+
+/// Returns the changes from the `old` catalog to the `new` one.
+///
+/// Containers are compared by name wherever they appear in both, and those
+/// only in one of them aren't reported, since the payloads or fields naming
+/// them are.
+#[must_use]
+pub fn diff(old: &Catalog, new: &Catalog) -> Vec<Change> {
+    let mut changes = Vec::new();
+
+    if old.field_names != new.field_names {
+        changes.push(Change::FieldNamesChanged {
+            old: old.field_names.clone(),
+            new: new.field_names.clone(),
+        });
+    }
+
+    if old.numeric_keys != new.numeric_keys {
+        changes.push(Change::KeysChanged {
+            numeric: new.numeric_keys,
+        });
+    }
+
+    for key in union(&old.payloads, &new.payloads) {
+        let key = key.clone();
+        match (old.payloads.get(&key), new.payloads.get(&key)) {
+            (Some(_), None) => changes.push(Change::KeyRemoved { key }),
+            (None, Some(_)) => changes.push(Change::KeyAdded { key }),
+            (Some(old), Some(new)) if old != new => changes.push(Change::PayloadChanged {
+                key,
+                old: old.clone(),
+                new: new.clone(),
+            }),
+            _ => {}
+        }
+    }
+
+    for (container, old) in &old.containers {
+        if let Some(new) = new.containers.get(container) {
+            diff_containers(container, old, new, &mut changes);
+        }
+    }
+
+    changes
+}
+
+fn union<'a, V>(a: &'a BTreeMap<String, V>, b: &'a BTreeMap<String, V>) -> BTreeSet<&'a String> {
+    a.keys().chain(b.keys()).collect()
+}
+
+fn diff_containers(
+    container: &str,
+    old: &ContainerFormat,
+    new: &ContainerFormat,
+    changes: &mut Vec<Change>,
+) {
+    match (old, new) {
+        (ContainerFormat::Struct(old), ContainerFormat::Struct(new)) => {
+            let old = fields(old);
+            let new = fields(new);
+            for field in union(&old, &new) {
+                let container = container.to_owned();
+                let field = field.clone();
+                match (old.get(&field), new.get(&field)) {
+                    (Some(_), None) => changes.push(Change::FieldRemoved { container, field }),
+                    (None, Some(format)) => changes.push(Change::FieldAdded {
+                        container,
+                        field,
+                        format: (*format).clone(),
+                    }),
+                    (Some(old), Some(new)) if old != new => changes.push(Change::FieldChanged {
+                        container,
+                        field,
+                        old: (*old).clone(),
+                        new: (*new).clone(),
+                    }),
+                    _ => {}
+                }
+            }
+        }
+        (ContainerFormat::Enum(old), ContainerFormat::Enum(new)) => {
+            let old = variants(old);
+            let new = variants(new);
+            for variant in union(&old, &new) {
+                let container = container.to_owned();
+                let variant = variant.clone();
+                match (old.get(&variant), new.get(&variant)) {
+                    (Some(_), None) => changes.push(Change::VariantRemoved { container, variant }),
+                    (None, Some(_)) => changes.push(Change::VariantAdded { container, variant }),
+                    (Some(old), Some(new)) if old != new => changes.push(Change::VariantChanged {
+                        container,
+                        variant,
+                        old: (*old).clone(),
+                        new: (*new).clone(),
+                    }),
+                    _ => {}
+                }
+            }
+        }
+        (old, new) if old != new => changes.push(Change::ContainerChanged {
+            container: container.to_owned(),
+            old: old.clone(),
+            new: new.clone(),
+        }),
+        _ => {}
+    }
+}
+
+fn fields(fields: &[Named<Format>]) -> BTreeMap<String, &Format> {
+    fields
+        .iter()
+        .map(|field| (field.name.clone(), &field.value))
+        .collect()
+}
+
+fn variants(variants: &BTreeMap<u32, Named<VariantFormat>>) -> BTreeMap<String, &VariantFormat> {
+    variants
+        .values()
+        .map(|variant| (variant.name.clone(), &variant.value))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::Deserialize;
+    use serde_reflection::TracerConfig;
+
+    use crate::reflection::CatalogTracer;
+    use crate::testing::Shapes;
+
+    #[allow(dead_code)]
+    mod v1 {
+        use super::*;
+
+        #[derive(Deserialize)]
+        pub struct Circle {
+            pub radius: f32,
+            pub center: (f32, f32),
+        }
+
+        #[derive(Deserialize)]
+        pub enum Fill {
+            Solid,
+            Pattern(String),
+        }
+
+        #[derive(Deserialize)]
+        pub struct Square {
+            pub side: f32,
+        }
+    }
+
+    #[allow(dead_code)]
+    mod v2 {
+        use super::*;
+
+        #[derive(Deserialize)]
+        pub struct Circle {
+            pub radius: Option<f32>,
+            pub label: Option<String>,
+            pub color: u32,
+        }
+
+        #[derive(Deserialize)]
+        pub enum Fill {
+            Solid,
+            Gradient(String, String),
+            Pattern(String),
+        }
+
+        #[derive(Deserialize)]
+        pub struct Triangle {
+            pub side: f32,
+        }
+    }
+
+    #[test]
+    fn classifies_changes_between_snapshots() {
+        let mut tracer = CatalogTracer::<Shapes>::new(TracerConfig::default());
+        tracer
+            .trace_type::<v1::Circle>("circle".to_owned())
+            .unwrap();
+        tracer.trace_type::<v1::Fill>("fill".to_owned()).unwrap();
+        tracer
+            .trace_type::<v1::Square>("square".to_owned())
+            .unwrap();
+        tracer.trace_type::<v1::Square>("rect".to_owned()).unwrap();
+        let old = tracer.catalog().unwrap();
+        let old: Catalog = serde_json::from_str(&serde_json::to_string(&old).unwrap()).unwrap();
+
+        let mut tracer = CatalogTracer::<Shapes>::new(TracerConfig::default());
+        tracer
+            .trace_type::<v2::Circle>("circle".to_owned())
+            .unwrap();
+        tracer.trace_type::<v2::Fill>("fill".to_owned()).unwrap();
+        tracer
+            .trace_type::<v2::Triangle>("square".to_owned())
+            .unwrap();
+        tracer
+            .trace_type::<v1::Square>("polygon".to_owned())
+            .unwrap();
+        let new = tracer.catalog().unwrap();
+
+        let changes: Vec<_> = diff(&old, &new)
+            .iter()
+            .map(|change| (change.to_string(), change.is_breaking()))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                ("key `polygon` added".to_owned(), false),
+                ("key `rect` removed".to_owned(), true),
+                (
+                    "payload of key `square` changed from TypeName(\"Square\") to TypeName(\"Triangle\")"
+                        .to_owned(),
+                    true
+                ),
+                ("field `Circle.center` removed".to_owned(), true),
+                ("field `Circle.color` added".to_owned(), true),
+                ("field `Circle.label` added".to_owned(), false),
+                (
+                    "field `Circle.radius` changed from F32 to Option(F32)".to_owned(),
+                    false
+                ),
+                ("variant `Fill::Gradient` added".to_owned(), false),
+            ]
+        );
+        assert!(diff(&new, &new).is_empty());
+
+        let numbered = Catalog {
+            numeric_keys: true,
+            ..new.clone()
+        };
+        let changes = diff(&new, &numbered);
+        assert_eq!(changes, [Change::KeysChanged { numeric: true }]);
+        assert_eq!(changes[0].to_string(), "keys changed to numbers");
+        assert!(changes[0].is_breaking());
+    }
+}
@@ -0,0 +1,625 @@
+//! Tabular representation of keyed values as CSV rows.
+//!
+//! Requires the `csv` feature.
+//!
+//! Each row holds the key in its first column followed by the fields of the
+//! value, with nested structs and maps flattened into columns joined by `.`:
+//!
+//! ```text
+//! kind,center.x,center.y,radius
+//! circle,1,2,0.5
+//! ```
+//!
+//! Values that aren't structs or maps are written to a single column named
+//! after the value field of the [`Registry`]. Sequences and bytes can't be
+//! written to a column and will return an error.
+//!
+//! The header row is recognized by the name of the key field in its first
+//! column, so a key can't have that same name. With [`Columns::PerKey`], a
+//! new header is written whenever the key changes from the previous row, so
+//! each run of rows only has the columns it needs. With [`Columns::Union`],
+//! a single header has all the columns of all the rows, leaving cells empty
+//! where a row doesn't have them.
+//!
+//! Cells are read as text and parsed into the type requested when the value
+//! is deserialized. Empty cells are read as `None` for options, and so are
+//! the columns of an optional struct when all of them are empty. An option
+//! written as `None` has an empty column of its own, which is ignored when
+//! it's next to the columns of the same option written as `Some`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use serde::de::{Error as _, Visitor};
+use serde::{Deserialize, Deserializer};
+use serde_value::Value;
+
+use crate::private::ErasedSerdeSerializeWrapper;
+use crate::text::{is_blank, TextValueDeserializer};
+use crate::{Error, Registry};
+
+/// How the columns are chosen when writing rows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Columns {
+    /// Writes a header with the columns of each run of rows with the same key.
+    PerKey,
+    /// Writes a single header with the columns of all the rows.
+    Union,
+}
+
+struct Row {
+    key: String,
+    cells: BTreeMap<String, String>,
+}
+
+/// Will write the objects as CSV rows with their keys in the first column.
+pub fn write_records<R, P, W>(writer: W, records: &[P], columns: Columns) -> Result<(), Error>
+where
+    R: Registry,
+    P: AsRef<R::Object>,
+    W: io::Write,
+{
+    let rows = records
+        .iter()
+        .map(|record| {
+            let object = record.as_ref();
+            let key = serde_value::to_value(R::key(object)).map_err(Error::custom)?;
+            let value = serde_value::to_value(ErasedSerdeSerializeWrapper(object))
+                .map_err(Error::custom)?;
+
+            let mut cells = BTreeMap::new();
+            match unwrap_newtypes(value) {
+                Value::Map(entries) => flatten(None, entries, &mut cells)?,
+                value => write_cell(R::FIELD_NAMES[1].to_owned(), value, &mut cells)?,
+            }
+
+            Ok(Row {
+                key: cell_text(unwrap_newtypes(key))
+                    .ok_or_else(|| Error::custom("key must be written to a single column"))?,
+                cells,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let mut writer = WriterBuilder::new()
+        .flexible(true)
+        .has_headers(false)
+        .from_writer(writer);
+
+    let mut start = 0;
+    while start < rows.len() {
+        let end = match columns {
+            Columns::PerKey => rows[start..]
+                .iter()
+                .position(|row| row.key != rows[start].key)
+                .map_or(rows.len(), |len| start + len),
+            Columns::Union => rows.len(),
+        };
+
+        let names: BTreeSet<&str> = rows[start..end]
+            .iter()
+            .flat_map(|row| row.cells.keys().map(String::as_str))
+            .collect();
+
+        writer
+            .write_record(std::iter::once(R::FIELD_NAMES[0]).chain(names.iter().copied()))
+            .map_err(Error::custom)?;
+        for row in &rows[start..end] {
+            let cells = names
+                .iter()
+                .map(|name| row.cells.get(*name).map_or("", String::as_str));
+            writer
+                .write_record(std::iter::once(row.key.as_str()).chain(cells))
+                .map_err(Error::custom)?;
+        }
+
+        start = end;
+    }
+
+    writer.flush().map_err(Error::custom)
+}
+
+/// Will read CSV rows written by [`write_records()`], using the registry to
+/// deserialize the objects.
+pub fn read_records<R, Rd>(reader: Rd) -> Result<Vec<Box<R::Object>>, Error>
+where
+    R: Registry,
+    Rd: io::Read,
+{
+    let mut reader = ReaderBuilder::new()
+        .flexible(true)
+        .has_headers(false)
+        .from_reader(reader);
+
+    let mut header: Option<StringRecord> = None;
+    let mut records = Vec::new();
+
+    for row in reader.records() {
+        let row = row.map_err(Error::custom)?;
+        if row.get(0) == Some(R::FIELD_NAMES[0]) {
+            header = Some(row);
+            continue;
+        }
+
+        let line = row.position().map_or(0, |position| position.line());
+        let at_line = |error: Error| Error::custom(format_args!("line {}: {}", line, error));
+
+        let header = header.as_ref().ok_or_else(|| {
+            at_line(Error::custom(format_args!(
+                "missing header with a `{}` column",
+                R::FIELD_NAMES[0]
+            )))
+        })?;
+        if row.len() > header.len() {
+            return Err(at_line(Error::custom("row has more cells than its header")));
+        }
+
+        let key = R::Key::deserialize(TextValueDeserializer::<Error>::new(Value::String(
+            row[0].to_owned(),
+        )))
+        .map_err(at_line)?;
+
+        let mut fields = BTreeMap::new();
+        for (name, cell) in header.iter().zip(row.iter()).skip(1) {
+            insert_cell(&mut fields, name, cell).map_err(at_line)?;
+        }
+
+        let object = R::deserialize(
+            key,
+            &mut <dyn erased_serde::Deserializer>::erase(RowDeserializer {
+                fields,
+                content: R::FIELD_NAMES[1],
+            }),
+        )
+        .map_err(at_line)?;
+        records.push(object);
+    }
+
+    Ok(records)
+}
+
+fn unwrap_newtypes(value: Value) -> Value {
+    match value {
+        Value::Newtype(value) | Value::Option(Some(value)) => unwrap_newtypes(*value),
+        value => value,
+    }
+}
+
+fn flatten(
+    prefix: Option<&str>,
+    entries: BTreeMap<Value, Value>,
+    cells: &mut BTreeMap<String, String>,
+) -> Result<(), Error> {
+    for (key, value) in entries {
+        let key = cell_text(unwrap_newtypes(key))
+            .ok_or_else(|| Error::custom("map keys must be written as text"))?;
+        let name = match prefix {
+            Some(prefix) => format!("{}.{}", prefix, key),
+            None => key,
+        };
+
+        match unwrap_newtypes(value) {
+            Value::Map(entries) => flatten(Some(&name), entries, cells)?,
+            value => write_cell(name, value, cells)?,
+        }
+    }
+
+    Ok(())
+}
+
+fn write_cell(
+    name: String,
+    value: Value,
+    cells: &mut BTreeMap<String, String>,
+) -> Result<(), Error> {
+    let kind = match value {
+        Value::Seq(_) => "a sequence",
+        Value::Bytes(_) => "bytes",
+        Value::Map(_) => "a map",
+        value => {
+            let text = cell_text(value).unwrap_or_default();
+            if cells.insert(name.clone(), text).is_some() {
+                return Err(Error::custom(format_args!("duplicate column `{}`", name)));
+            }
+            return Ok(());
+        }
+    };
+
+    Err(Error::custom(format_args!(
+        "can't write {} to column `{}`",
+        kind, name
+    )))
+}
+
+fn cell_text(value: Value) -> Option<String> {
+    Some(match value {
+        Value::Bool(v) => v.to_string(),
+        Value::U8(v) => v.to_string(),
+        Value::U16(v) => v.to_string(),
+        Value::U32(v) => v.to_string(),
+        Value::U64(v) => v.to_string(),
+        Value::I8(v) => v.to_string(),
+        Value::I16(v) => v.to_string(),
+        Value::I32(v) => v.to_string(),
+        Value::I64(v) => v.to_string(),
+        Value::F32(v) => v.to_string(),
+        Value::F64(v) => v.to_string(),
+        Value::Char(v) => v.to_string(),
+        Value::String(v) => v,
+        Value::Unit | Value::Option(None) => String::new(),
+        _ => return None,
+    })
+}
+
+/// Puts the cell into the nested map at the path given by the column name.
+fn insert_cell(fields: &mut BTreeMap<Value, Value>, name: &str, cell: &str) -> Result<(), Error> {
+    let conflict = || Error::custom(format_args!("column `{}` conflicts with another", name));
+
+    let mut fields = fields;
+    let mut segments = name.split('.').peekable();
+    while let Some(segment) = segments.next() {
+        let key = Value::String(segment.to_owned());
+        if segments.peek().is_none() {
+            match fields.get(&key) {
+                None => {
+                    fields.insert(key, Value::String(cell.to_owned()));
+                }
+                // An option written as `None` leaves its column blank, which
+                // the columns of the same option written as `Some` replace.
+                Some(Value::Map(_)) if cell.is_empty() => {}
+                Some(_) => return Err(conflict()),
+            }
+        } else {
+            let entry = fields
+                .entry(key)
+                .or_insert_with(|| Value::Map(BTreeMap::new()));
+            if matches!(entry, Value::String(cell) if cell.is_empty()) {
+                *entry = Value::Map(BTreeMap::new());
+            }
+            match entry {
+                Value::Map(entries) => fields = entries,
+                _ => return Err(conflict()),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Deserializes the cells of a row, giving the fields to structs and maps
+/// and the value column to anything else.
+struct RowDeserializer {
+    fields: BTreeMap<Value, Value>,
+    content: &'static str,
+}
+
+impl RowDeserializer {
+    fn into_value(mut self) -> TextValueDeserializer<Error> {
+        let value = match self.fields.remove(&Value::String(self.content.to_owned())) {
+            Some(value) => value,
+            None if self.fields.values().all(is_blank) => Value::Unit,
+            None => Value::Map(self.fields),
+        };
+        TextValueDeserializer::empty_as_none(value)
+    }
+
+    fn into_fields(self) -> TextValueDeserializer<Error> {
+        TextValueDeserializer::empty_as_none(Value::Map(self.fields))
+    }
+}
+
+macro_rules! forward_to_value {
+    ($($method:ident($($arg:ident: $ty:ty),*))*) => {
+        $(
+            fn $method<V>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value, Error>
+            where
+                V: Visitor<'de>,
+            {
+                self.into_value().$method($($arg,)* visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for RowDeserializer {
+    type Error = Error;
+
+    forward_to_value! {
+        deserialize_any()
+        deserialize_bool()
+        deserialize_i8()
+        deserialize_i16()
+        deserialize_i32()
+        deserialize_i64()
+        deserialize_i128()
+        deserialize_u8()
+        deserialize_u16()
+        deserialize_u32()
+        deserialize_u64()
+        deserialize_u128()
+        deserialize_f32()
+        deserialize_f64()
+        deserialize_char()
+        deserialize_str()
+        deserialize_string()
+        deserialize_bytes()
+        deserialize_byte_buf()
+        deserialize_option()
+        deserialize_unit()
+        deserialize_unit_struct(name: &'static str)
+        deserialize_newtype_struct(name: &'static str)
+        deserialize_seq()
+        deserialize_tuple(len: usize)
+        deserialize_tuple_struct(name: &'static str, len: usize)
+        deserialize_enum(name: &'static str, variants: &'static [&'static str])
+        deserialize_identifier()
+        deserialize_ignored_any()
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.into_fields().deserialize_map(visitor)
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.into_fields().deserialize_struct(name, fields, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use once_cell::sync::Lazy;
+    use serde::Serialize;
+
+    use crate::{deserialize_into_boxed_trait, DesFnSync};
+
+    trait Event: erased_serde::Serialize {
+        fn kind(&self) -> &'static str;
+        fn describe(&self) -> String;
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Click {
+        at: Point,
+        button: Option<u8>,
+        label: String,
+    }
+
+    impl Event for Click {
+        fn kind(&self) -> &'static str {
+            "click"
+        }
+
+        fn describe(&self) -> String {
+            format!(
+                "click {},{} {:?} {:?}",
+                self.at.x, self.at.y, self.button, self.label
+            )
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Scroll(f64);
+
+    impl Event for Scroll {
+        fn kind(&self) -> &'static str {
+            "scroll"
+        }
+
+        fn describe(&self) -> String {
+            format!("scroll {}", self.0)
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Tap {
+        at: Option<Point>,
+    }
+
+    impl Event for Tap {
+        fn kind(&self) -> &'static str {
+            "tap"
+        }
+
+        fn describe(&self) -> String {
+            match &self.at {
+                Some(at) => format!("tap {},{}", at.x, at.y),
+                None => "tap".to_string(),
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Tags {
+        tags: Vec<String>,
+    }
+
+    impl Event for Tags {
+        fn kind(&self) -> &'static str {
+            "tags"
+        }
+
+        fn describe(&self) -> String {
+            self.tags.join(",")
+        }
+    }
+
+    static MAP: Lazy<HashMap<String, DesFnSync<Box<dyn Event>>>> = Lazy::new(|| {
+        let mut map = HashMap::<String, DesFnSync<Box<dyn Event>>>::new();
+        map.insert("click".to_string(), deserialize_into_boxed_trait!(Click));
+        map.insert("scroll".to_string(), deserialize_into_boxed_trait!(Scroll));
+        map.insert("tap".to_string(), deserialize_into_boxed_trait!(Tap));
+        map
+    });
+
+    struct Events;
+
+    impl Registry for Events {
+        type Object = dyn Event;
+        type Key = String;
+
+        const TYPE_NAME: &'static str = "Box<dyn Event>";
+        const FIELD_NAMES: &'static [&'static str; 2] = &["kind", "value"];
+
+        fn key(object: &dyn Event) -> String {
+            object.kind().to_string()
+        }
+
+        fn deserialize(
+            key: String,
+            deserializer: &mut dyn erased_serde::Deserializer,
+        ) -> Result<Box<dyn Event>, Error> {
+            MAP.get(&key)
+                .ok_or_else(crate::unknown_key)
+                .and_then(|f| f(deserializer))
+        }
+    }
+
+    fn events() -> Vec<Box<dyn Event>> {
+        vec![
+            Box::new(Click {
+                at: Point { x: 1, y: 2 },
+                button: Some(0),
+                label: "ok".to_string(),
+            }),
+            Box::new(Click {
+                at: Point { x: -3, y: 4 },
+                button: None,
+                label: String::new(),
+            }),
+            Box::new(Scroll(1.5)),
+        ]
+    }
+
+    fn write(records: &[impl AsRef<dyn Event>], columns: Columns) -> String {
+        let mut bytes = Vec::new();
+        write_records::<Events, _, _>(&mut bytes, records, columns).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    fn describe(csv: &str) -> Result<Vec<String>, Error> {
+        let events = read_records::<Events, _>(csv.as_bytes())?;
+        Ok(events.iter().map(|e| e.describe()).collect())
+    }
+
+    #[test]
+    fn rows_round_trip_with_either_columns() {
+        let expected = [
+            r#"click 1,2 Some(0) "ok""#,
+            r#"click -3,4 None """#,
+            "scroll 1.5",
+        ];
+
+        let csv = write(&events(), Columns::PerKey);
+        assert_eq!(
+            csv,
+            "kind,at.x,at.y,button,label\n\
+             click,1,2,0,ok\n\
+             click,-3,4,,\n\
+             kind,value\n\
+             scroll,1.5\n"
+        );
+        assert_eq!(describe(&csv).unwrap(), expected);
+
+        let csv = write(&events(), Columns::Union);
+        assert_eq!(
+            csv,
+            "kind,at.x,at.y,button,label,value\n\
+             click,1,2,0,ok,\n\
+             click,-3,4,,,\n\
+             scroll,,,,,1.5\n"
+        );
+        assert_eq!(describe(&csv).unwrap(), expected);
+
+        let shared: Vec<Arc<dyn Event>> = vec![Arc::new(Scroll(2.0))];
+        assert_eq!(write(&shared, Columns::Union), "kind,value\nscroll,2\n");
+    }
+
+    #[test]
+    fn optional_structs_round_trip_with_either_columns() {
+        let records: Vec<Box<dyn Event>> = vec![
+            Box::new(Tap { at: None }),
+            Box::new(Tap {
+                at: Some(Point { x: 1, y: 2 }),
+            }),
+            Box::new(Scroll(1.5)),
+        ];
+        let expected = ["tap", "tap 1,2", "scroll 1.5"];
+
+        let csv = write(&records, Columns::PerKey);
+        assert_eq!(
+            csv,
+            "kind,at,at.x,at.y\n\
+             tap,,,\n\
+             tap,,1,2\n\
+             kind,value\n\
+             scroll,1.5\n"
+        );
+        assert_eq!(describe(&csv).unwrap(), expected);
+
+        let csv = write(&records, Columns::Union);
+        assert_eq!(
+            csv,
+            "kind,at,at.x,at.y,value\n\
+             tap,,,,\n\
+             tap,,1,2,\n\
+             scroll,,,,1.5\n"
+        );
+        assert_eq!(describe(&csv).unwrap(), expected);
+    }
+
+    #[test]
+    fn rows_return_errors() {
+        let mut bytes = Vec::new();
+        let records: Vec<Box<dyn Event>> = vec![Box::new(Tags {
+            tags: vec!["a".to_string()],
+        })];
+        let error = write_records::<Events, _, _>(&mut bytes, &records, Columns::PerKey)
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "can't write a sequence to column `tags`");
+
+        let error = describe("click,1,2,0,ok\n").err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "line 1: missing header with a `kind` column"
+        );
+
+        let error = describe("kind,value\nscroll,1.5,2\n").err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "line 2: row has more cells than its header"
+        );
+
+        let error = describe("kind,value\ndrag,1.5\n").err().unwrap();
+        assert_eq!(error.to_string(), "line 2: unknown deserialization key");
+
+        let error = describe("kind,at,at.x\nclick,1,2\n").err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "line 2: column `at.x` conflicts with another"
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Registry and codecs shared by the tests of several modules.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use bincode::Options;
+use once_cell::sync::Lazy;
+use serde::ser::Error as _;
+use serde::{Deserialize, Serialize};
+
+use crate::envelope::Codec;
+use crate::{deserialize_into_boxed_trait, DesFnSync, Error, Registry};
+
+pub(crate) trait Shape: erased_serde::Serialize + Debug {
+    fn name(&self) -> &'static str;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Circle {
+    pub(crate) radius: f64,
+    pub(crate) label: Option<String>,
+}
+
+impl Shape for Circle {
+    fn name(&self) -> &'static str {
+        "circle"
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) enum Path {
+    Closed,
+    Points(Vec<(i32, i32)>),
+}
+
+impl Shape for Path {
+    fn name(&self) -> &'static str {
+        "path"
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Label(pub(crate) String);
+
+impl Shape for Label {
+    fn name(&self) -> &'static str {
+        "label"
+    }
+}
+
+/// Always reads back as zero, to test failures.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Counter {
+    #[serde(skip_deserializing)]
+    pub(crate) count: u32,
+}
+
+impl Shape for Counter {
+    fn name(&self) -> &'static str {
+        "counter"
+    }
+}
+
+static MAP: Lazy<HashMap<&'static str, DesFnSync<Box<dyn Shape>>>> = Lazy::new(|| {
+    let mut map = HashMap::<&'static str, DesFnSync<Box<dyn Shape>>>::new();
+    map.insert("circle", deserialize_into_boxed_trait!(Circle));
+    map.insert("path", deserialize_into_boxed_trait!(Path));
+    map.insert("label", deserialize_into_boxed_trait!(Label));
+    map.insert("counter", deserialize_into_boxed_trait!(Counter));
+    map
+});
+
+pub(crate) struct Shapes;
+
+impl Registry for Shapes {
+    type Object = dyn Shape;
+    type Key = String;
+
+    const TYPE_NAME: &'static str = "Box<dyn Shape>";
+    const FIELD_NAMES: &'static [&'static str; 2] = &["kind", "shape"];
+
+    fn key(object: &dyn Shape) -> String {
+        object.name().to_owned()
+    }
+
+    fn deserialize(
+        key: String,
+        deserializer: &mut dyn erased_serde::Deserializer,
+    ) -> Result<Box<dyn Shape>, Error> {
+        MAP.get(key.as_str())
+            .ok_or_else(crate::unknown_key)
+            .and_then(|f| f(deserializer))
+    }
+}
+
+pub(crate) struct Json;
+
+impl Codec for Json {
+    fn encode(value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, Error> {
+        serde_json::to_vec(value).map_err(Error::custom)
+    }
+
+    fn decode<T, F>(bytes: &[u8], f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut dyn erased_serde::Deserializer) -> Result<T, Error>,
+    {
+        let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+        f(&mut <dyn erased_serde::Deserializer>::erase(
+            &mut deserializer,
+        ))
+    }
+}
+
+pub(crate) struct Bincode;
+
+impl Codec for Bincode {
+    fn encode(value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, Error> {
+        bincode::DefaultOptions::new()
+            .serialize(value)
+            .map_err(Error::custom)
+    }
+
+    fn decode<T, F>(bytes: &[u8], f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut dyn erased_serde::Deserializer) -> Result<T, Error>,
+    {
+        let mut deserializer =
+            bincode::Deserializer::from_slice(bytes, bincode::DefaultOptions::new());
+        f(&mut <dyn erased_serde::Deserializer>::erase(
+            &mut deserializer,
+        ))
+    }
+}
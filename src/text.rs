@@ -0,0 +1,304 @@
+//! Deserialization of buffered values from formats where everything is text.
+//!
+//! XML and CSV give every scalar as a string, so a value that was buffered
+//! before its key is known has to be parsed into whatever type is requested
+//! when it's read back.
+
+use std::marker::PhantomData;
+
+use serde::de::{DeserializeSeed, MapAccess, SeqAccess, Unexpected, Visitor};
+use serde::Deserializer;
+use serde_value::{Value, ValueDeserializer};
+
+/// The field names `quick-xml` uses for the text or content of an element.
+pub(crate) const TEXT_FIELDS: &[&str] = &["$text", "$value"];
+
+/// Whether the value is an empty string or a map of nothing but those, like
+/// the cells of an absent value.
+#[cfg_attr(not(feature = "csv"), allow(dead_code))]
+pub(crate) fn is_blank(value: &Value) -> bool {
+    match value {
+        Value::String(s) => s.is_empty(),
+        Value::Map(entries) => entries.values().all(is_blank),
+        _ => false,
+    }
+}
+
+/// Deserializes a buffered value, parsing strings into the requested type
+/// where needed.
+pub(crate) struct TextValueDeserializer<E> {
+    value: Value,
+    empty_as_none: bool,
+    _error: PhantomData<fn() -> E>,
+}
+
+impl<E> TextValueDeserializer<E> {
+    pub(crate) fn new(value: Value) -> Self {
+        Self::nested(value, false)
+    }
+
+    /// Like [`new`](Self::new), but empty strings, or maps of nothing but
+    /// empty strings, will be read as `None` when an option is requested.
+    #[cfg_attr(not(feature = "csv"), allow(dead_code))]
+    pub(crate) fn empty_as_none(value: Value) -> Self {
+        Self::nested(value, true)
+    }
+
+    /// Elements with nothing but text are read as a map with a single text
+    /// field, which is replaced by the text itself.
+    fn nested(value: Value, empty_as_none: bool) -> Self {
+        let value = match value {
+            Value::Map(entries) if entries.len() == 1 => match entries.keys().next() {
+                Some(Value::String(field)) if TEXT_FIELDS.contains(&field.as_str()) => {
+                    entries.into_iter().next().unwrap().1
+                }
+                _ => Value::Map(entries),
+            },
+            value => value,
+        };
+
+        TextValueDeserializer {
+            value,
+            empty_as_none,
+            _error: PhantomData,
+        }
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> Result<V::Value, E>
+            where
+                V: Visitor<'de>,
+            {
+                match self.value {
+                    Value::String(s) => match s.trim().parse() {
+                        Ok(v) => visitor.$visit(v),
+                        Err(_) => Err(E::invalid_type(Unexpected::Str(&s), &visitor)),
+                    },
+                    value => TextValueDeserializer::nested(value, self.empty_as_none)
+                        .deserialize_any(visitor),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de, E> Deserializer<'de> for TextValueDeserializer<E>
+where
+    E: serde::de::Error,
+{
+    type Error = E;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            Value::Option(None) => visitor.visit_none(),
+            Value::Option(Some(value)) => {
+                visitor.visit_some(TextValueDeserializer::nested(*value, self.empty_as_none))
+            }
+            Value::Newtype(value) => visitor
+                .visit_newtype_struct(TextValueDeserializer::nested(*value, self.empty_as_none)),
+            Value::Seq(values) => visitor.visit_seq(TextSeqAccess::<E> {
+                values: values.into_iter(),
+                empty_as_none: self.empty_as_none,
+                _error: PhantomData,
+            }),
+            Value::Map(entries) => visitor.visit_map(TextMapAccess::<E> {
+                entries: entries.into_iter(),
+                value: None,
+                empty_as_none: self.empty_as_none,
+                _error: PhantomData,
+            }),
+            value => ValueDeserializer::<E>::new(value).deserialize_any(visitor),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            Value::Option(None) | Value::Unit => visitor.visit_none(),
+            ref value if self.empty_as_none && is_blank(value) => visitor.visit_none(),
+            Value::Option(Some(value)) => {
+                visitor.visit_some(TextValueDeserializer::nested(*value, self.empty_as_none))
+            }
+            value => visitor.visit_some(TextValueDeserializer::nested(value, self.empty_as_none)),
+        }
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            Value::String(s) if s.is_empty() => visitor.visit_unit(),
+            value => {
+                TextValueDeserializer::nested(value, self.empty_as_none).deserialize_any(visitor)
+            }
+        }
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            Value::Newtype(value) => visitor
+                .visit_newtype_struct(TextValueDeserializer::nested(*value, self.empty_as_none)),
+            value => visitor
+                .visit_newtype_struct(TextValueDeserializer::nested(value, self.empty_as_none)),
+        }
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+    {
+        // A single child element can't be told apart from a sequence of one.
+        match self.value {
+            Value::Seq(values) => {
+                TextValueDeserializer::nested(Value::Seq(values), self.empty_as_none)
+                    .deserialize_any(visitor)
+            }
+            value => visitor.visit_seq(TextSeqAccess::<E> {
+                values: vec![value].into_iter(),
+                empty_as_none: self.empty_as_none,
+                _error: PhantomData,
+            }),
+        }
+    }
+
+    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, E>
+    where
+        V: Visitor<'de>,
+    {
+        ValueDeserializer::<E>::new(self.value).deserialize_enum(name, variants, visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        i128 u128 str string bytes byte_buf map struct identifier ignored_any
+    }
+}
+
+struct TextSeqAccess<E> {
+    values: std::vec::IntoIter<Value>,
+    empty_as_none: bool,
+    _error: PhantomData<fn() -> E>,
+}
+
+impl<'de, E> SeqAccess<'de> for TextSeqAccess<E>
+where
+    E: serde::de::Error,
+{
+    type Error = E;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, E>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.values.next() {
+            Some(value) => seed
+                .deserialize(TextValueDeserializer::nested(value, self.empty_as_none))
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.values.len())
+    }
+}
+
+struct TextMapAccess<E> {
+    entries: std::collections::btree_map::IntoIter<Value, Value>,
+    value: Option<Value>,
+    empty_as_none: bool,
+    _error: PhantomData<fn() -> E>,
+}
+
+impl<'de, E> MapAccess<'de> for TextMapAccess<E>
+where
+    E: serde::de::Error,
+{
+    type Error = E;
+
+    fn next_key_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, E>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(TextValueDeserializer::new(key)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<T>(&mut self, seed: T) -> Result<T::Value, E>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.value.take() {
+            Some(value) => {
+                seed.deserialize(TextValueDeserializer::nested(value, self.empty_as_none))
+            }
+            None => Err(E::custom("value is missing")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
@@ -0,0 +1,478 @@
+//! TypeScript types for the keyed values of a catalog.
+//!
+//! Requires the `serde-reflection` feature.
+//!
+//! [`to_typescript`] writes the payload formats of a [`Catalog`], traced or
+//! declared by hand, as TypeScript types of the JSON written by
+//! [`serialize_with_key`](crate::serialize_with_key). The keyed values are a
+//! union discriminated by the tag field, along with a type guard for each
+//! key and one for the whole union:
+//!
+//! ```ts
+//! export type Circle = {
+//!   radius: number;
+//! };
+//!
+//! export type Shape =
+//!   | { kind: "circle"; shape: Circle };
+//!
+//! export const SHAPE_KEYS = ["circle"] as const;
+//!
+//! export function isShape(value: unknown): value is Shape { ... }
+//!
+//! export function isCircleShape(value: Shape): value is Extract<Shape, { kind: "circle" }> { ... }
+//! ```
+//!
+//! The containers of the catalog are written as types of the same name,
+//! with enums as the externally tagged unions serde uses by default. Keys
+//! are written as number literals if the [keys of the
+//! catalog](Catalog::numeric_keys) are numbers and as string literals
+//! otherwise, while maps are written as records, following JSON.
+//!
+//! Keys whose guards would have the same name as another guard, like
+//! `tag-list` and `tag_list`, or as the guard of the whole union, like an
+//! empty key, get a number added to the end of the name.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use serde_reflection::{ContainerFormat, Format, Named, VariantFormat};
+
+use crate::reflection::Catalog;
+
+/// Writes the TypeScript types of the containers and keyed values of
+/// `catalog`, with the union of keyed values named `name`.
+#[must_use]
+pub fn to_typescript(catalog: &Catalog, name: &str) -> String {
+    let [tag, content] = &catalog.field_names;
+    let mut out = String::new();
+
+    for (container, format) in &catalog.containers {
+        write_container(&mut out, container, format);
+        out.push('\n');
+    }
+
+    let _ = write!(out, "export type {} =", name);
+    if catalog.payloads.is_empty() {
+        out.push_str(" never");
+    }
+    for (key, format) in &catalog.payloads {
+        let _ = write!(
+            out,
+            "\n  | {{ {}: {}; {}: {} }}",
+            property(tag),
+            literal(catalog, key),
+            property(content),
+            type_of(format)
+        );
+    }
+    out.push_str(";\n\n");
+
+    let keys = format!("{}_KEYS", upper_snake_case(name));
+    let _ = writeln!(
+        out,
+        "export const {} = [{}] as const;\n",
+        keys,
+        catalog
+            .payloads
+            .keys()
+            .map(|key| literal(catalog, key))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let _ = writeln!(
+        out,
+        "export function is{name}(value: unknown): value is {name} {{\n  \
+        return (\n    typeof value === \"object\" &&\n    value !== null &&\n    \
+        (({keys} as readonly unknown[]).includes((value as {{ {tag}?: unknown }}){access}))\n  \
+        );\n}}",
+        name = name,
+        keys = keys,
+        tag = property(tag),
+        access = access(tag),
+    );
+
+    let mut guards = HashSet::new();
+    guards.insert(format!("is{}", name));
+    for key in catalog.payloads.keys() {
+        let guard = unique(&mut guards, format!("is{}{}", pascal_case(key), name));
+        let _ = writeln!(
+            out,
+            "\nexport function {guard}(value: {name}): value is Extract<{name}, {{ {tag}: {key} }}> {{\n  \
+            return value{access} === {key};\n}}",
+            guard = guard,
+            name = name,
+            tag = property(tag),
+            access = access(tag),
+            key = literal(catalog, key),
+        );
+    }
+
+    out
+}
+
+/// Adds a number to the end of `name` if it's already in `taken`, then adds
+/// it to `taken`.
+fn unique(taken: &mut HashSet<String>, name: String) -> String {
+    let name = if taken.contains(&name) {
+        (2..)
+            .map(|i| format!("{}{}", name, i))
+            .find(|candidate| !taken.contains(candidate))
+            .unwrap()
+    } else {
+        name
+    };
+    taken.insert(name.clone());
+    name
+}
+
+/// Writes a key of the catalog as a number or string literal.
+fn literal(catalog: &Catalog, key: &str) -> String {
+    if catalog.numeric_keys {
+        key.to_owned()
+    } else {
+        format!("{:?}", key)
+    }
+}
+
+fn write_container(out: &mut String, name: &str, format: &ContainerFormat) {
+    let _ = write!(out, "export type {} =", name);
+    match format {
+        ContainerFormat::UnitStruct => out.push_str(" null"),
+        ContainerFormat::NewTypeStruct(format) => {
+            let _ = write!(out, " {}", type_of(format));
+        }
+        ContainerFormat::TupleStruct(formats) => {
+            let _ = write!(out, " {}", tuple(formats));
+        }
+        ContainerFormat::Struct(fields) => {
+            out.push_str(" {\n");
+            for field in fields {
+                let _ = writeln!(
+                    out,
+                    "  {}: {};",
+                    property(&field.name),
+                    type_of(&field.value)
+                );
+            }
+            out.push('}');
+        }
+        ContainerFormat::Enum(variants) => {
+            if variants.is_empty() {
+                out.push_str(" never");
+            }
+            for variant in variants.values() {
+                let _ = write!(out, "\n  | {}", variant_type(variant));
+            }
+        }
+    }
+    out.push_str(";\n");
+}
+
+fn variant_type(variant: &Named<VariantFormat>) -> String {
+    let payload = match &variant.value {
+        VariantFormat::Unit => return format!("{:?}", variant.name),
+        VariantFormat::NewType(format) => type_of(format),
+        VariantFormat::Tuple(formats) => tuple(formats),
+        VariantFormat::Struct(fields) => object(fields),
+        VariantFormat::Variable(_) => "unknown".to_owned(),
+    };
+    format!("{{ {}: {} }}", property(&variant.name), payload)
+}
+
+fn type_of(format: &Format) -> String {
+    match format {
+        Format::Variable(_) => "unknown".to_owned(),
+        Format::TypeName(name) => name.clone(),
+        Format::Unit => "null".to_owned(),
+        Format::Bool => "boolean".to_owned(),
+        Format::I8
+        | Format::I16
+        | Format::I32
+        | Format::I64
+        | Format::I128
+        | Format::U8
+        | Format::U16
+        | Format::U32
+        | Format::U64
+        | Format::U128
+        | Format::F32
+        | Format::F64 => "number".to_owned(),
+        Format::Char | Format::Str => "string".to_owned(),
+        Format::Bytes => "number[]".to_owned(),
+        Format::Option(format) => format!("{} | null", type_of(format)),
+        Format::Seq(format) => format!("Array<{}>", type_of(format)),
+        Format::Map { value, .. } => format!("Record<string, {}>", type_of(value)),
+        Format::Tuple(formats) => tuple(formats),
+        Format::TupleArray { content, size } => tuple(&vec![content.as_ref().clone(); *size]),
+    }
+}
+
+fn tuple(formats: &[Format]) -> String {
+    let types: Vec<_> = formats.iter().map(type_of).collect();
+    format!("[{}]", types.join(", "))
+}
+
+fn object(fields: &[Named<Format>]) -> String {
+    if fields.is_empty() {
+        return "{}".to_owned();
+    }
+    let fields: Vec<_> = fields
+        .iter()
+        .map(|field| format!("{}: {}", property(&field.name), type_of(&field.value)))
+        .collect();
+    format!("{{ {} }}", fields.join("; "))
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+/// Quotes the name of a property unless it's an identifier.
+fn property(name: &str) -> String {
+    if is_identifier(name) {
+        name.to_owned()
+    } else {
+        format!("{:?}", name)
+    }
+}
+
+/// Accesses a property with a dot if it's an identifier, or with brackets
+/// otherwise.
+fn access(name: &str) -> String {
+    if is_identifier(name) {
+        format!(".{}", name)
+    } else {
+        format!("[{:?}]", name)
+    }
+}
+
+fn words(name: &str) -> impl Iterator<Item = &str> {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+}
+
+fn pascal_case(name: &str) -> String {
+    words(name)
+        .map(|word| word[..1].to_ascii_uppercase() + &word[1..])
+        .collect()
+}
+
+fn upper_snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            out.push('_');
+        }
+        out.push(c.to_ascii_uppercase());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    fn named<T>(name: &str, value: T) -> Named<T> {
+        Named {
+            name: name.to_owned(),
+            value,
+        }
+    }
+
+    #[test]
+    fn writes_discriminated_unions() {
+        let catalog = Catalog {
+            type_name: "Box<dyn Shape>".to_owned(),
+            field_names: ["kind".to_owned(), "shape".to_owned()],
+            numeric_keys: false,
+            payloads: vec![
+                ("circle".to_owned(), Format::TypeName("Circle".to_owned())),
+                ("path".to_owned(), Format::TypeName("Path".to_owned())),
+                (
+                    "tag-list".to_owned(),
+                    Format::Seq(Box::new(Format::Option(Box::new(Format::Str)))),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+            containers: vec![
+                (
+                    "Circle".to_owned(),
+                    ContainerFormat::Struct(vec![
+                        named("radius", Format::F64),
+                        named(
+                            "center",
+                            Format::TupleArray {
+                                content: Box::new(Format::I32),
+                                size: 2,
+                            },
+                        ),
+                    ]),
+                ),
+                (
+                    "Path".to_owned(),
+                    ContainerFormat::Enum(
+                        vec![
+                            (0, named("Closed", VariantFormat::Unit)),
+                            (
+                                1,
+                                named(
+                                    "Points",
+                                    VariantFormat::NewType(Box::new(Format::Seq(Box::new(
+                                        Format::TypeName("Point".to_owned()),
+                                    )))),
+                                ),
+                            ),
+                            (
+                                2,
+                                named(
+                                    "Arc",
+                                    VariantFormat::Struct(vec![named("end angle", Format::F32)]),
+                                ),
+                            ),
+                        ]
+                        .into_iter()
+                        .collect(),
+                    ),
+                ),
+                (
+                    "Point".to_owned(),
+                    ContainerFormat::TupleStruct(vec![Format::I32, Format::I32]),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        };
+
+        assert_eq!(
+            to_typescript(&catalog, "Shape"),
+            r#"export type Circle = {
+  radius: number;
+  center: [number, number];
+};
+
+export type Path =
+  | "Closed"
+  | { Points: Array<Point> }
+  | { Arc: { "end angle": number } };
+
+export type Point = [number, number];
+
+export type Shape =
+  | { kind: "circle"; shape: Circle }
+  | { kind: "path"; shape: Path }
+  | { kind: "tag-list"; shape: Array<string | null> };
+
+export const SHAPE_KEYS = ["circle", "path", "tag-list"] as const;
+
+export function isShape(value: unknown): value is Shape {
+  return (
+    typeof value === "object" &&
+    value !== null &&
+    ((SHAPE_KEYS as readonly unknown[]).includes((value as { kind?: unknown }).kind))
+  );
+}
+
+export function isCircleShape(value: Shape): value is Extract<Shape, { kind: "circle" }> {
+  return value.kind === "circle";
+}
+
+export function isPathShape(value: Shape): value is Extract<Shape, { kind: "path" }> {
+  return value.kind === "path";
+}
+
+export function isTagListShape(value: Shape): value is Extract<Shape, { kind: "tag-list" }> {
+  return value.kind === "tag-list";
+}
+"#
+        );
+    }
+
+    #[test]
+    fn writes_empty_catalogs() {
+        let catalog = Catalog {
+            type_name: "Box<dyn Shape>".to_owned(),
+            field_names: ["data-value".to_owned(), "data".to_owned()],
+            numeric_keys: false,
+            payloads: BTreeMap::new(),
+            containers: BTreeMap::new(),
+        };
+        let typescript = to_typescript(&catalog, "ShapeValue");
+        assert!(typescript.starts_with(
+            "export type ShapeValue = never;\n\nexport const SHAPE_VALUE_KEYS = [] as const;\n"
+        ));
+        assert!(typescript.contains(r#"(value as { "data-value"?: unknown })["data-value"]"#));
+    }
+
+    #[test]
+    fn writes_numeric_keys_as_numbers() {
+        let catalog = Catalog {
+            type_name: "Box<dyn Message>".to_owned(),
+            field_names: ["kind".to_owned(), "body".to_owned()],
+            numeric_keys: true,
+            payloads: vec![
+                ("1".to_owned(), Format::Str),
+                ("20".to_owned(), Format::Unit),
+            ]
+            .into_iter()
+            .collect(),
+            containers: BTreeMap::new(),
+        };
+        let typescript = to_typescript(&catalog, "Message");
+        assert!(typescript.starts_with(
+            "export type Message =\n  \
+             | { kind: 1; body: string }\n  \
+             | { kind: 20; body: null };\n\n\
+             export const MESSAGE_KEYS = [1, 20] as const;\n"
+        ));
+        assert!(typescript.contains(
+            "export function is1Message(value: Message): value is Extract<Message, { kind: 1 }> {\n  \
+             return value.kind === 1;\n}"
+        ));
+    }
+
+    #[test]
+    fn disambiguates_guard_names() {
+        let catalog = Catalog {
+            type_name: "Box<dyn Shape>".to_owned(),
+            field_names: ["kind".to_owned(), "shape".to_owned()],
+            numeric_keys: false,
+            payloads: vec![
+                ("".to_owned(), Format::Unit),
+                ("tag-list".to_owned(), Format::Unit),
+                ("tag_list".to_owned(), Format::Unit),
+                ("tag list".to_owned(), Format::Unit),
+            ]
+            .into_iter()
+            .collect(),
+            containers: BTreeMap::new(),
+        };
+        let typescript = to_typescript(&catalog, "Shape");
+        let guards: Vec<_> = typescript
+            .lines()
+            .filter_map(|line| line.strip_prefix("export function "))
+            .map(|line| &line[..line.find('(').unwrap()])
+            .collect();
+        assert_eq!(
+            guards,
+            [
+                "isShape",
+                "isShape2",
+                "isTagListShape",
+                "isTagListShape2",
+                "isTagListShape3"
+            ]
+        );
+        assert!(typescript.contains(
+            "export function isTagListShape3(value: Shape): value is Extract<Shape, { kind: \"tag_list\" }> {"
+        ));
+    }
+}
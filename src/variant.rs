@@ -0,0 +1,1236 @@
+//! Representation of keyed values as enum variants.
+//!
+//! Instead of a struct with key and value fields, the key is written as the
+//! name of an enum variant with the value as its newtype payload. Formats
+//! that have native syntax for enums will use it; for example `serde_yaml`
+//! writes them as tags:
+//!
+//! ```yaml
+//! !circle
+//! radius: 2.0
+//! ```
+//!
+//! The [`serialize_as_native_variant()`] and [`deserialize_from_native_variant()`]
+//! functions instead choose the kind of variant from the shape of the value:
+//! a struct becomes a struct variant, a tuple becomes a tuple variant, a unit
+//! becomes a unit variant, and anything else is a newtype variant. This is
+//! what formats like RON expect to read and write by hand:
+//!
+//! ```ron
+//! circle(radius: 2.0)
+//! ```
+//!
+//! Note that RON requires the type name to be a valid identifier, so
+//! `"Shape"` works where `"Box<dyn Shape>"` would not.
+//!
+//! Since serde requires variant names to be `&'static str`, the possible keys
+//! must be provided up front. Their position in the list is used as the
+//! variant index by formats that don't write names, so keys should only ever
+//! be appended to it.
+//!
+//! ```
+//! # mod outer {
+//! use serde::{Deserializer, Serializer};
+//!
+//! trait Shape: erased_serde::Serialize {
+//!     fn key(&self) -> &'static str;
+//! }
+//!
+//! const KEYS: &[&str] = &["circle", "square"];
+//!
+//! pub fn serialize<S>(value: &Box<dyn Shape>, serializer: S) -> Result<S::Ok, S::Error>
+//! where
+//!     S: Serializer,
+//! {
+//!     keyedes::variant::serialize_as_variant(
+//!         "Box<dyn Shape>",
+//!         KEYS,
+//!         value.key(),
+//!         &**value,
+//!         serializer,
+//!     )
+//! }
+//!
+//! pub fn deserialize<'de, D>(deserializer: D) -> Result<Box<dyn Shape>, D::Error>
+//! where
+//!     D: Deserializer<'de>,
+//! {
+//!     keyedes::variant::deserialize_from_variant(
+//!         "Box<dyn Shape>",
+//!         KEYS,
+//!         |key, deserializer| {
+//!             // look up the key in a map
+//! #           let _ = (key, deserializer);
+//!             Err(keyedes::unknown_key())
+//!         },
+//!         deserializer,
+//!     )
+//! }
+//! # }
+//! ```
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{DeserializeSeed, EnumAccess, Unexpected, VariantAccess, Visitor};
+use serde::ser::{
+    Impossible, Serialize, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant,
+    SerializeTuple, SerializeTupleStruct, SerializeTupleVariant,
+};
+use serde::{Deserializer, Serializer};
+
+use crate::private::{ErasedSerdeSerializeWrapper, ValueDeserializeSeed};
+use crate::Error;
+
+/// Will serialize the value as a newtype variant named by the key.
+///
+/// The key must be one of the `variants`, otherwise an error is returned.
+pub fn serialize_as_variant<S, V>(
+    type_name: &'static str,
+    variants: &'static [&'static str],
+    key: &str,
+    value: &V,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    V: ?Sized + erased_serde::Serialize,
+    S: Serializer,
+{
+    let (index, variant) = find_variant::<S::Error>(variants, key)?;
+    serializer.serialize_newtype_variant(
+        type_name,
+        index,
+        variant,
+        &ErasedSerdeSerializeWrapper(value),
+    )
+}
+
+/// Will deserialize a newtype variant, using its name as the key.
+///
+/// The function `f` will be called with the key and a deserializer for the
+/// payload that can be used to get the final value. Variants not in
+/// `variants` will return an error.
+pub fn deserialize_from_variant<'de, D, V, F>(
+    type_name: &'static str,
+    variants: &'static [&'static str],
+    f: F,
+    deserializer: D,
+) -> Result<V, D::Error>
+where
+    D: Deserializer<'de>,
+    F: Fn(&'static str, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+{
+    deserializer.deserialize_enum(
+        type_name,
+        variants,
+        VariantVisitor {
+            variants,
+            native: false,
+            deserialization_fn: f,
+            _dummy: PhantomData,
+        },
+    )
+}
+
+/// Will serialize the value as a variant named by the key, with the kind of
+/// variant matching the shape of the value.
+///
+/// The key must be one of the `variants`, otherwise an error is returned.
+pub fn serialize_as_native_variant<S, V>(
+    type_name: &'static str,
+    variants: &'static [&'static str],
+    key: &str,
+    value: &V,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    V: ?Sized + erased_serde::Serialize,
+    S: Serializer,
+{
+    use serde::ser::Error;
+
+    let (variant_index, variant) = find_variant::<S::Error>(variants, key)?;
+    let shape = erased_serde::serialize(value, ShapeSerializer).map_err(S::Error::custom)?;
+
+    match shape {
+        Shape::Other => serializer.serialize_newtype_variant(
+            type_name,
+            variant_index,
+            variant,
+            &ErasedSerdeSerializeWrapper(value),
+        ),
+        Shape::Lifted => erased_serde::serialize(
+            value,
+            LiftingSerializer {
+                inner: serializer,
+                type_name,
+                variant_index,
+                variant,
+            },
+        ),
+    }
+}
+
+/// Will deserialize a variant of any kind, using its name as the key.
+///
+/// This is the counterpart to [`serialize_as_native_variant()`]. The kind of
+/// variant expected is chosen by what the value asks to deserialize; values
+/// that use `deserialize_any` will only work for newtype variants. See
+/// [`deserialize_from_variant()`] for the meaning of the parameters.
+pub fn deserialize_from_native_variant<'de, D, V, F>(
+    type_name: &'static str,
+    variants: &'static [&'static str],
+    f: F,
+    deserializer: D,
+) -> Result<V, D::Error>
+where
+    D: Deserializer<'de>,
+    F: Fn(&'static str, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+{
+    deserializer.deserialize_enum(
+        type_name,
+        variants,
+        VariantVisitor {
+            variants,
+            native: true,
+            deserialization_fn: f,
+            _dummy: PhantomData,
+        },
+    )
+}
+
+pub(crate) fn find_variant<E>(
+    variants: &'static [&'static str],
+    key: &str,
+) -> Result<(u32, &'static str), E>
+where
+    E: serde::ser::Error,
+{
+    variants
+        .iter()
+        .position(|variant| *variant == key)
+        .map(|index| (index as u32, variants[index]))
+        .ok_or_else(|| E::custom(format_args!("key {:?} is not a known variant", key)))
+}
+
+/// Deserializes a variant identifier by name or index into one of the
+/// `variants`.
+pub(crate) struct VariantKeySeed {
+    pub variants: &'static [&'static str],
+}
+
+impl<'de> DeserializeSeed<'de> for VariantKeySeed {
+    type Value = &'static str;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_identifier(self)
+    }
+}
+
+impl<'de> Visitor<'de> for VariantKeySeed {
+    type Value = &'static str;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("variant identifier")
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.variants
+            .get(value as usize)
+            .copied()
+            .ok_or_else(|| E::invalid_value(Unexpected::Unsigned(value), &self))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        self.variants
+            .iter()
+            .find(|variant| **variant == value)
+            .copied()
+            .ok_or_else(|| E::unknown_variant(value, self.variants))
+    }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        match std::str::from_utf8(value) {
+            Ok(value) => self.visit_str(value),
+            Err(_) => Err(E::invalid_value(Unexpected::Bytes(value), &self)),
+        }
+    }
+}
+
+struct VariantVisitor<F, V> {
+    variants: &'static [&'static str],
+    native: bool,
+    deserialization_fn: F,
+    _dummy: PhantomData<fn() -> V>,
+}
+
+impl<'de, F, V> Visitor<'de> for VariantVisitor<F, V>
+where
+    F: Fn(&'static str, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+{
+    type Value = V;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("newtype variant")
+    }
+
+    fn visit_enum<A>(self, data: A) -> Result<Self::Value, A::Error>
+    where
+        A: EnumAccess<'de>,
+    {
+        use serde::de::Error;
+
+        let (key, variant) = data.variant_seed(VariantKeySeed {
+            variants: self.variants,
+        })?;
+
+        if self.native {
+            (self.deserialization_fn)(
+                key,
+                &mut <dyn erased_serde::Deserializer>::erase(NativeVariantDeserializer(variant)),
+            )
+            .map_err(A::Error::custom)
+        } else {
+            variant.newtype_variant_seed(ValueDeserializeSeed {
+                field: key,
+                deserialization_fn: &self.deserialization_fn,
+                _dummy: PhantomData,
+            })
+        }
+    }
+}
+
+/// Whether a value can be lifted into a unit, tuple, newtype, or struct
+/// variant or must be wrapped in a newtype variant.
+enum Shape {
+    Lifted,
+    Other,
+}
+
+/// Serializer that only records the [`Shape`] of a value without serializing
+/// any of its contents.
+struct ShapeSerializer;
+
+impl Serializer for ShapeSerializer {
+    type Ok = Shape;
+    type Error = Error;
+    type SerializeSeq = ShapeSerializer;
+    type SerializeTuple = ShapeSerializer;
+    type SerializeTupleStruct = ShapeSerializer;
+    type SerializeTupleVariant = ShapeSerializer;
+    type SerializeMap = ShapeSerializer;
+    type SerializeStruct = ShapeSerializer;
+    type SerializeStructVariant = ShapeSerializer;
+
+    fn serialize_bool(self, _v: bool) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_i8(self, _v: i8) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_i16(self, _v: i16) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_i32(self, _v: i32) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_i64(self, _v: i64) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_u8(self, _v: u8) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_u16(self, _v: u16) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_u32(self, _v: u32) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_u64(self, _v: u64) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_f32(self, _v: f32) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_f64(self, _v: f64) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_char(self, _v: char) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_str(self, _v: &str) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_none(self) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_some<T: ?Sized + Serialize>(self, _value: &T) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_unit(self) -> Result<Shape, Error> {
+        Ok(Shape::Lifted)
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Shape, Error> {
+        Ok(Shape::Lifted)
+    }
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _value: &T,
+    ) -> Result<Shape, Error> {
+        Ok(Shape::Lifted)
+    }
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_seq(self, _len: Option<usize>) -> Result<ShapeSerializer, Error> {
+        Ok(ShapeSerializer)
+    }
+    fn serialize_tuple(self, _len: usize) -> Result<ShapeSerializer, Error> {
+        Ok(ShapeSerializer)
+    }
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<ShapeSerializer, Error> {
+        Ok(ShapeSerializer)
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<ShapeSerializer, Error> {
+        Ok(ShapeSerializer)
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<ShapeSerializer, Error> {
+        Ok(ShapeSerializer)
+    }
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<ShapeSerializer, Error> {
+        Ok(ShapeSerializer)
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<ShapeSerializer, Error> {
+        Ok(ShapeSerializer)
+    }
+}
+
+// The shape of compound values is decided by which `SerializeX` trait `end()`
+// is called through, since the state type is shared between all of them.
+
+impl SerializeSeq for ShapeSerializer {
+    type Ok = Shape;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, _value: &T) -> Result<(), Error> {
+        Ok(())
+    }
+    fn end(self) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+}
+
+impl SerializeTuple for ShapeSerializer {
+    type Ok = Shape;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, _value: &T) -> Result<(), Error> {
+        Ok(())
+    }
+    fn end(self) -> Result<Shape, Error> {
+        Ok(Shape::Lifted)
+    }
+}
+
+impl SerializeTupleStruct for ShapeSerializer {
+    type Ok = Shape;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, _value: &T) -> Result<(), Error> {
+        Ok(())
+    }
+    fn end(self) -> Result<Shape, Error> {
+        Ok(Shape::Lifted)
+    }
+}
+
+impl SerializeTupleVariant for ShapeSerializer {
+    type Ok = Shape;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, _value: &T) -> Result<(), Error> {
+        Ok(())
+    }
+    fn end(self) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+}
+
+impl SerializeMap for ShapeSerializer {
+    type Ok = Shape;
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, _key: &T) -> Result<(), Error> {
+        Ok(())
+    }
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, _value: &T) -> Result<(), Error> {
+        Ok(())
+    }
+    fn end(self) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+}
+
+impl SerializeStruct for ShapeSerializer {
+    type Ok = Shape;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        _key: &'static str,
+        _value: &T,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+    fn end(self) -> Result<Shape, Error> {
+        Ok(Shape::Lifted)
+    }
+}
+
+impl SerializeStructVariant for ShapeSerializer {
+    type Ok = Shape;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        _key: &'static str,
+        _value: &T,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+    fn end(self) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+}
+
+/// Serializer that turns a unit, tuple, newtype, or struct value into the
+/// same kind of variant. Any other value is an error; those are checked for
+/// ahead of time with the [`ShapeSerializer`].
+struct LiftingSerializer<S> {
+    inner: S,
+    type_name: &'static str,
+    variant_index: u32,
+    variant: &'static str,
+}
+
+impl<S> LiftingSerializer<S>
+where
+    S: Serializer,
+{
+    fn unexpected<T>(self) -> Result<T, S::Error> {
+        use serde::ser::Error;
+
+        Err(S::Error::custom(
+            "value changed shape between serializations",
+        ))
+    }
+}
+
+impl<S> Serializer for LiftingSerializer<S>
+where
+    S: Serializer,
+{
+    type Ok = S::Ok;
+    type Error = S::Error;
+    type SerializeSeq = Impossible<S::Ok, S::Error>;
+    type SerializeTuple = LiftedTuple<S::SerializeTupleVariant>;
+    type SerializeTupleStruct = LiftedTuple<S::SerializeTupleVariant>;
+    type SerializeTupleVariant = Impossible<S::Ok, S::Error>;
+    type SerializeMap = Impossible<S::Ok, S::Error>;
+    type SerializeStruct = LiftedStruct<S::SerializeStructVariant>;
+    type SerializeStructVariant = Impossible<S::Ok, S::Error>;
+
+    fn serialize_unit(self) -> Result<S::Ok, S::Error> {
+        self.inner
+            .serialize_unit_variant(self.type_name, self.variant_index, self.variant)
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<S::Ok, S::Error> {
+        self.serialize_unit()
+    }
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_newtype_variant(
+            self.type_name,
+            self.variant_index,
+            self.variant,
+            value,
+        )
+    }
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, S::Error> {
+        self.inner
+            .serialize_tuple_variant(self.type_name, self.variant_index, self.variant, len)
+            .map(LiftedTuple)
+    }
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, S::Error> {
+        self.serialize_tuple(len)
+    }
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, S::Error> {
+        self.inner
+            .serialize_struct_variant(self.type_name, self.variant_index, self.variant, len)
+            .map(LiftedStruct)
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<S::Ok, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_i8(self, _v: i8) -> Result<S::Ok, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_i16(self, _v: i16) -> Result<S::Ok, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_i32(self, _v: i32) -> Result<S::Ok, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_i64(self, _v: i64) -> Result<S::Ok, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_u8(self, _v: u8) -> Result<S::Ok, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_u16(self, _v: u16) -> Result<S::Ok, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_u32(self, _v: u32) -> Result<S::Ok, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_u64(self, _v: u64) -> Result<S::Ok, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_f32(self, _v: f32) -> Result<S::Ok, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_f64(self, _v: f64) -> Result<S::Ok, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_char(self, _v: char) -> Result<S::Ok, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_str(self, _v: &str) -> Result<S::Ok, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_bytes(self, _v: &[u8]) -> Result<S::Ok, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_none(self) -> Result<S::Ok, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_some<T: ?Sized + Serialize>(self, _value: &T) -> Result<S::Ok, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<S::Ok, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, S::Error> {
+        self.unexpected()
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, S::Error> {
+        self.unexpected()
+    }
+}
+
+struct LiftedTuple<T>(T);
+
+impl<T: SerializeTupleVariant> SerializeTuple for LiftedTuple<T> {
+    type Ok = T::Ok;
+    type Error = T::Error;
+
+    fn serialize_element<V: ?Sized + Serialize>(&mut self, value: &V) -> Result<(), T::Error> {
+        self.0.serialize_field(value)
+    }
+    fn end(self) -> Result<T::Ok, T::Error> {
+        self.0.end()
+    }
+}
+
+impl<T: SerializeTupleVariant> SerializeTupleStruct for LiftedTuple<T> {
+    type Ok = T::Ok;
+    type Error = T::Error;
+
+    fn serialize_field<V: ?Sized + Serialize>(&mut self, value: &V) -> Result<(), T::Error> {
+        self.0.serialize_field(value)
+    }
+    fn end(self) -> Result<T::Ok, T::Error> {
+        self.0.end()
+    }
+}
+
+struct LiftedStruct<T>(T);
+
+impl<T: SerializeStructVariant> SerializeStruct for LiftedStruct<T> {
+    type Ok = T::Ok;
+    type Error = T::Error;
+
+    fn serialize_field<V: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &V,
+    ) -> Result<(), T::Error> {
+        self.0.serialize_field(key, value)
+    }
+    fn skip_field(&mut self, key: &'static str) -> Result<(), T::Error> {
+        self.0.skip_field(key)
+    }
+    fn end(self) -> Result<T::Ok, T::Error> {
+        self.0.end()
+    }
+}
+
+/// Deserializer for the payload of a variant that picks the kind of variant
+/// based on what is requested.
+struct NativeVariantDeserializer<A>(A);
+
+/// The `deserialize_*` method to call on the payload of a newtype variant.
+enum Method {
+    Any,
+    Bool,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    F32,
+    F64,
+    Char,
+    Str,
+    String,
+    Bytes,
+    ByteBuf,
+    Option,
+    Seq,
+    Map,
+    Enum(&'static str, &'static [&'static str]),
+    Identifier,
+    IgnoredAny,
+}
+
+/// Forwards a `deserialize_*` call to the payload of a newtype variant.
+struct ForwardSeed<V> {
+    method: Method,
+    visitor: V,
+}
+
+impl<'de, V> DeserializeSeed<'de> for ForwardSeed<V>
+where
+    V: Visitor<'de>,
+{
+    type Value = V::Value;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<V::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let visitor = self.visitor;
+        match self.method {
+            Method::Any => deserializer.deserialize_any(visitor),
+            Method::Bool => deserializer.deserialize_bool(visitor),
+            Method::I8 => deserializer.deserialize_i8(visitor),
+            Method::I16 => deserializer.deserialize_i16(visitor),
+            Method::I32 => deserializer.deserialize_i32(visitor),
+            Method::I64 => deserializer.deserialize_i64(visitor),
+            Method::I128 => deserializer.deserialize_i128(visitor),
+            Method::U8 => deserializer.deserialize_u8(visitor),
+            Method::U16 => deserializer.deserialize_u16(visitor),
+            Method::U32 => deserializer.deserialize_u32(visitor),
+            Method::U64 => deserializer.deserialize_u64(visitor),
+            Method::U128 => deserializer.deserialize_u128(visitor),
+            Method::F32 => deserializer.deserialize_f32(visitor),
+            Method::F64 => deserializer.deserialize_f64(visitor),
+            Method::Char => deserializer.deserialize_char(visitor),
+            Method::Str => deserializer.deserialize_str(visitor),
+            Method::String => deserializer.deserialize_string(visitor),
+            Method::Bytes => deserializer.deserialize_bytes(visitor),
+            Method::ByteBuf => deserializer.deserialize_byte_buf(visitor),
+            Method::Option => deserializer.deserialize_option(visitor),
+            Method::Seq => deserializer.deserialize_seq(visitor),
+            Method::Map => deserializer.deserialize_map(visitor),
+            Method::Enum(name, variants) => deserializer.deserialize_enum(name, variants, visitor),
+            Method::Identifier => deserializer.deserialize_identifier(visitor),
+            Method::IgnoredAny => deserializer.deserialize_ignored_any(visitor),
+        }
+    }
+}
+
+struct NewtypeStructSeed<V>(V);
+
+impl<'de, V> DeserializeSeed<'de> for NewtypeStructSeed<V>
+where
+    V: Visitor<'de>,
+{
+    type Value = V::Value;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<V::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.0.visit_newtype_struct(deserializer)
+    }
+}
+
+macro_rules! forward_to_newtype_variant {
+    ($($method:ident => $variant:ident,)*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> Result<V::Value, A::Error>
+            where
+                V: Visitor<'de>,
+            {
+                self.0.newtype_variant_seed(ForwardSeed {
+                    method: Method::$variant,
+                    visitor,
+                })
+            }
+        )*
+    };
+}
+
+impl<'de, A> Deserializer<'de> for NativeVariantDeserializer<A>
+where
+    A: VariantAccess<'de>,
+{
+    type Error = A::Error;
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, A::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.0.unit_variant()?;
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, A::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, A::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.0.newtype_variant_seed(NewtypeStructSeed(visitor))
+    }
+
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value, A::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.0.tuple_variant(len, visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, A::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.0.tuple_variant(len, visitor)
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, A::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.0.struct_variant(fields, visitor)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, A::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.0.newtype_variant_seed(ForwardSeed {
+            method: Method::Enum(name, variants),
+            visitor,
+        })
+    }
+
+    forward_to_newtype_variant! {
+        deserialize_any => Any,
+        deserialize_bool => Bool,
+        deserialize_i8 => I8,
+        deserialize_i16 => I16,
+        deserialize_i32 => I32,
+        deserialize_i64 => I64,
+        deserialize_i128 => I128,
+        deserialize_u8 => U8,
+        deserialize_u16 => U16,
+        deserialize_u32 => U32,
+        deserialize_u64 => U64,
+        deserialize_u128 => U128,
+        deserialize_f32 => F32,
+        deserialize_f64 => F64,
+        deserialize_char => Char,
+        deserialize_str => Str,
+        deserialize_string => String,
+        deserialize_bytes => Bytes,
+        deserialize_byte_buf => ByteBuf,
+        deserialize_option => Option,
+        deserialize_seq => Seq,
+        deserialize_map => Map,
+        deserialize_identifier => Identifier,
+        deserialize_ignored_any => IgnoredAny,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::{deserialize_into_boxed_trait, DesFn};
+
+    trait TestTrait: erased_serde::Serialize {
+        fn key(&self) -> &'static str;
+        fn name(&self) -> &str;
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct TestStructA {
+        name: String,
+    }
+
+    impl TestTrait for TestStructA {
+        fn key(&self) -> &'static str {
+            "A"
+        }
+        fn name(&self) -> &str {
+            self.name.as_str()
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    enum TestEnumB {
+        Pizza,
+        Broccoli,
+    }
+
+    impl TestTrait for TestEnumB {
+        fn key(&self) -> &'static str {
+            "B"
+        }
+        fn name(&self) -> &str {
+            match self {
+                TestEnumB::Pizza => "pizza",
+                TestEnumB::Broccoli => "yuck",
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct TestUnitC;
+
+    impl TestTrait for TestUnitC {
+        fn key(&self) -> &'static str {
+            "C"
+        }
+        fn name(&self) -> &str {
+            "just a c"
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct TestTupleD(u32, u32);
+
+    impl TestTrait for TestTupleD {
+        fn key(&self) -> &'static str {
+            "D"
+        }
+        fn name(&self) -> &str {
+            "a pair"
+        }
+    }
+
+    const KEYS: &[&str] = &["A", "B"];
+    const NATIVE_KEYS: &[&str] = &["A", "B", "C", "D"];
+
+    struct Wrapper(Box<dyn TestTrait>);
+
+    impl Serialize for Wrapper {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            serialize_as_variant(
+                "Box<dyn TestTrait>",
+                KEYS,
+                self.0.key(),
+                &*self.0,
+                serializer,
+            )
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Wrapper {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let mut map = HashMap::<&str, DesFn<Box<dyn TestTrait>>>::new();
+            map.insert("A", deserialize_into_boxed_trait!(TestStructA));
+            map.insert("B", deserialize_into_boxed_trait!(TestEnumB));
+
+            deserialize_from_variant(
+                "Box<dyn TestTrait>",
+                KEYS,
+                |key, deserializer| map[key](deserializer),
+                deserializer,
+            )
+            .map(Wrapper)
+        }
+    }
+
+    #[test]
+    fn variant_round_trips_as_yaml_tags() {
+        let values = vec![
+            Wrapper(Box::new(TestStructA {
+                name: "chuck norris".to_string(),
+            })),
+            Wrapper(Box::new(TestEnumB::Broccoli)),
+        ];
+
+        let yaml = serde_yaml::to_string(&values).unwrap();
+        assert_eq!(yaml, "- !A\n  name: chuck norris\n- !B Broccoli\n");
+
+        let values: Vec<Wrapper> = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(values[0].0.name(), "chuck norris");
+        assert_eq!(values[1].0.name(), "yuck");
+
+        let values: Vec<Wrapper> =
+            serde_yaml::from_str("- !A {name: bruce lee}\n- !B Pizza\n").unwrap();
+        assert_eq!(values[0].0.name(), "bruce lee");
+        assert_eq!(values[1].0.name(), "pizza");
+    }
+
+    #[test]
+    fn variant_returns_error_on_unknown_key() {
+        let result = serde_yaml::from_str::<Wrapper>("!C null\n");
+        assert!(result.is_err());
+
+        let result = serde_yaml::to_string(&Wrapper(Box::new(TestUnitC)));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn variant_is_externally_tagged_in_json() {
+        let value = Wrapper(Box::new(TestEnumB::Pizza));
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(json, r#"{"B":"Pizza"}"#);
+
+        let value: Wrapper = serde_json::from_str(&json).unwrap();
+        assert_eq!(value.0.name(), "pizza");
+    }
+
+    struct NativeWrapper(Box<dyn TestTrait>);
+
+    impl Serialize for NativeWrapper {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            serialize_as_native_variant(
+                "TestTrait",
+                NATIVE_KEYS,
+                self.0.key(),
+                &*self.0,
+                serializer,
+            )
+        }
+    }
+
+    impl<'de> Deserialize<'de> for NativeWrapper {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let mut map = HashMap::<&str, DesFn<Box<dyn TestTrait>>>::new();
+            map.insert("A", deserialize_into_boxed_trait!(TestStructA));
+            map.insert("B", deserialize_into_boxed_trait!(TestEnumB));
+            map.insert("C", deserialize_into_boxed_trait!(TestUnitC));
+            map.insert("D", deserialize_into_boxed_trait!(TestTupleD));
+
+            deserialize_from_native_variant(
+                "TestTrait",
+                NATIVE_KEYS,
+                |key, deserializer| map[key](deserializer),
+                deserializer,
+            )
+            .map(NativeWrapper)
+        }
+    }
+
+    fn native_values() -> Vec<NativeWrapper> {
+        vec![
+            NativeWrapper(Box::new(TestStructA {
+                name: "chuck norris".to_string(),
+            })),
+            NativeWrapper(Box::new(TestEnumB::Broccoli)),
+            NativeWrapper(Box::new(TestUnitC)),
+            NativeWrapper(Box::new(TestTupleD(1, 2))),
+        ]
+    }
+
+    #[test]
+    fn native_variant_round_trips_as_ron_enums() {
+        let ron = ron::to_string(&native_values()).unwrap();
+        assert_eq!(ron, r#"[A(name:"chuck norris"),B(Broccoli),C,D(1,2)]"#);
+
+        let values: Vec<NativeWrapper> = ron::from_str(&ron).unwrap();
+        let names: Vec<_> = values.iter().map(|v| v.0.name()).collect();
+        assert_eq!(names, ["chuck norris", "yuck", "just a c", "a pair"]);
+
+        let ron = r#"[
+            A(name: "bruce lee"),
+            B(Pizza),
+            C,
+        ]"#;
+        let values: Vec<NativeWrapper> = ron::from_str(ron).unwrap();
+        let names: Vec<_> = values.iter().map(|v| v.0.name()).collect();
+        assert_eq!(names, ["bruce lee", "pizza", "just a c"]);
+    }
+
+    #[test]
+    fn native_variant_round_trips_in_other_formats() {
+        let yaml = serde_yaml::to_string(&native_values()).unwrap();
+        assert_eq!(
+            yaml,
+            "- !A\n  name: chuck norris\n- !B Broccoli\n- C\n- !D\n  - 1\n  - 2\n"
+        );
+        let values: Vec<NativeWrapper> = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(values.len(), 4);
+
+        let json = serde_json::to_string(&native_values()).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"A":{"name":"chuck norris"}},{"B":"Broccoli"},"C",{"D":[1,2]}]"#
+        );
+        let values: Vec<NativeWrapper> = serde_json::from_str(&json).unwrap();
+        assert_eq!(values.len(), 4);
+    }
+
+    #[test]
+    fn native_variant_returns_error_on_wrong_kind() {
+        let result = ron::from_str::<NativeWrapper>(r#"A((name:"x"))"#);
+        assert!(result.is_err());
+
+        let result = ron::from_str::<NativeWrapper>("C(5)");
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,456 @@
+//! Representations of keyed values for XML.
+//!
+//! XML has no dedicated syntax for a key next to a value, so keyed objects in
+//! XML documents usually take one of two forms. The key may be an attribute
+//! of the element, with the value's fields as its children:
+//!
+//! ```xml
+//! <shape type="circle"><radius>2</radius></shape>
+//! ```
+//!
+//! This is supported by [`serialize_with_attribute()`] and
+//! [`deserialize_by_attribute()`]. The attribute name must follow the
+//! conventions of the XML backend; for `quick-xml` that means prefixing it
+//! with `@`. A value that isn't a struct or map is written as the text of the
+//! element.
+//!
+//! Or the key may be the name of the element itself:
+//!
+//! ```xml
+//! <circle><radius>2</radius></circle>
+//! ```
+//!
+//! XML backends write enum variants this way, so the functions in
+//! [`variant`](crate::variant) can be used for it. With `quick-xml`, the field
+//! holding the value has to be named `$value`.
+//!
+//! XML also has no types for text, so anything read through
+//! [`deserialize_any()`](serde::Deserializer::deserialize_any) is a string.
+//! This is a problem whenever the value has to be buffered, like when the key
+//! comes after the value in [`deserialize_by_key()`] or with an attribute key
+//! that has to be found first. Buffered values are therefore parsed from
+//! strings on demand when booleans, numbers, or characters are requested.
+//!
+//! ```
+//! # mod outer {
+//! use serde::{Deserializer, Serializer};
+//!
+//! trait Shape: erased_serde::Serialize {
+//!     fn key(&self) -> &'static str;
+//! }
+//!
+//! pub fn serialize<S>(value: &Box<dyn Shape>, serializer: S) -> Result<S::Ok, S::Error>
+//! where
+//!     S: Serializer,
+//! {
+//!     keyedes::xml::serialize_with_attribute("@type", value.key(), &**value, serializer)
+//! }
+//!
+//! pub fn deserialize<'de, D>(deserializer: D) -> Result<Box<dyn Shape>, D::Error>
+//! where
+//!     D: Deserializer<'de>,
+//! {
+//!     keyedes::xml::deserialize_by_attribute(
+//!         "@type",
+//!         |key: String, deserializer| {
+//!             // look up the key in a map
+//! #           let _ = (key, deserializer);
+//!             Err(keyedes::unknown_key())
+//!         },
+//!         deserializer,
+//!     )
+//! }
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+
+use serde::de::Error as _;
+use serde::ser::Serialize;
+use serde::{Deserialize, Deserializer, Serializer};
+use serde_value::Value;
+
+use crate::internal::{deserialize_tagged, serialize_tagged, FieldsDeserializer, TaggedFields};
+use crate::limits::Limits;
+use crate::private::KeyValueVisitor;
+use crate::text::{TextValueDeserializer, TEXT_FIELDS};
+use crate::Error;
+
+/// Will serialize the value as a map with the key in the `attribute` field,
+/// followed by the fields of the value.
+///
+/// If the value isn't a struct or map, it is written in a `$value` field
+/// instead. Sequences and enums can't be written this way and will return an
+/// error.
+pub fn serialize_with_attribute<S, K, V>(
+    attribute: &str,
+    key: &K,
+    value: &V,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    K: ?Sized + Serialize,
+    V: ?Sized + erased_serde::Serialize,
+    S: Serializer,
+{
+    serialize_tagged(attribute, key, Some(TEXT_FIELDS[1]), value, serializer)
+}
+
+/// Will deserialize a map with the key in the `attribute` field, using the
+/// remaining fields as the value.
+///
+/// The function `f` will be called with the deserialized key and a
+/// deserializer that can be used to get the final value. If the only other
+/// field is the text of the element, the value is deserialized from that
+/// instead. Repeated child elements are read as a sequence.
+pub fn deserialize_by_attribute<'de, D, K, V, F>(
+    attribute: &str,
+    f: F,
+    deserializer: D,
+) -> Result<V, D::Error>
+where
+    D: Deserializer<'de>,
+    K: Deserialize<'de>,
+    F: Fn(K, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+{
+    let TaggedFields { key, fields } = deserialize_tagged(attribute, deserializer)?;
+    let key =
+        key.ok_or_else(|| D::Error::custom(format_args!("missing attribute `{}`", attribute)))?;
+
+    f(
+        key,
+        &mut <dyn erased_serde::Deserializer>::erase(FieldsDeserializer::new(
+            join_repeated(fields),
+            TextValueDeserializer::<D::Error>::new,
+        )),
+    )
+    .map_err(D::Error::custom)
+}
+
+/// Joins the values of repeated child elements into a sequence, like
+/// `quick-xml` does for a field that is a sequence.
+fn join_repeated(fields: Vec<(Value, Value)>) -> BTreeMap<Value, Value> {
+    let mut elements = BTreeMap::<Value, Vec<Value>>::new();
+    for (name, value) in fields {
+        elements.entry(name).or_default().push(value);
+    }
+
+    elements
+        .into_iter()
+        .map(|(name, mut values)| match values.len() {
+            1 => (name, values.pop().unwrap()),
+            _ => (name, Value::Seq(values)),
+        })
+        .collect()
+}
+
+/// Will deserialize a struct with the given field names and values, like
+/// [`crate::deserialize_by_key()`], but parse a buffered value from strings
+/// when needed.
+///
+/// This should be used instead for XML, where the value is buffered if the
+/// key comes after it.
+pub fn deserialize_by_key<'de, D, K, V, F>(
+    type_name: &'static str,
+    field_names: &'static [&'static str; 2],
+    f: F,
+    deserializer: D,
+) -> Result<V, D::Error>
+where
+    D: Deserializer<'de>,
+    K: Deserialize<'de>,
+    F: Fn(K, &mut dyn erased_serde::Deserializer) -> Result<V, Error>,
+{
+    deserializer.deserialize_struct(
+        type_name,
+        field_names,
+        KeyValueVisitor {
+            deserialization_fn: f,
+            key_name: field_names[0],
+            value_name: field_names[1],
+            text_values: true,
+            limits: Limits::NONE,
+            _dummy: PhantomData,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use once_cell::sync::Lazy;
+    use serde::{Deserialize, Serialize};
+
+    use crate::{deserialize_into_boxed_trait, DesFnSync};
+
+    trait TestTrait: erased_serde::Serialize {
+        fn key(&self) -> &'static str;
+        fn describe(&self) -> String;
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Circle {
+        name: String,
+        radius: f64,
+    }
+
+    impl TestTrait for Circle {
+        fn key(&self) -> &'static str {
+            "circle"
+        }
+
+        fn describe(&self) -> String {
+            format!("{} {}", self.name, self.radius)
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Count(u32);
+
+    impl TestTrait for Count {
+        fn key(&self) -> &'static str {
+            "count"
+        }
+
+        fn describe(&self) -> String {
+            format!("count {}", self.0)
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Blank;
+
+    impl TestTrait for Blank {
+        fn key(&self) -> &'static str {
+            "blank"
+        }
+
+        fn describe(&self) -> String {
+            "blank".to_string()
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Poly {
+        point: Vec<u32>,
+    }
+
+    impl TestTrait for Poly {
+        fn key(&self) -> &'static str {
+            "poly"
+        }
+
+        fn describe(&self) -> String {
+            format!("poly {:?}", self.point)
+        }
+    }
+
+    static MAP: Lazy<HashMap<String, DesFnSync<Box<dyn TestTrait>>>> = Lazy::new(|| {
+        let mut map = HashMap::<String, DesFnSync<Box<dyn TestTrait>>>::new();
+        map.insert("circle".to_string(), deserialize_into_boxed_trait!(Circle));
+        map.insert("count".to_string(), deserialize_into_boxed_trait!(Count));
+        map.insert("blank".to_string(), deserialize_into_boxed_trait!(Blank));
+        map.insert("poly".to_string(), deserialize_into_boxed_trait!(Poly));
+        map
+    });
+
+    const KEYS: &[&str] = &["circle", "count", "blank", "poly"];
+
+    fn lookup(
+        key: &str,
+        deserializer: &mut dyn erased_serde::Deserializer,
+    ) -> Result<Box<dyn TestTrait>, Error> {
+        MAP.get(key)
+            .ok_or_else(crate::unknown_key)
+            .and_then(|f| f(deserializer))
+    }
+
+    mod attribute {
+        use super::*;
+
+        #[allow(clippy::borrowed_box)]
+        pub fn serialize<S>(value: &Box<dyn TestTrait>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            serialize_with_attribute("@type", value.key(), &**value, serializer)
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Box<dyn TestTrait>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            deserialize_by_attribute(
+                "@type",
+                |key: String, deserializer| lookup(&key, deserializer),
+                deserializer,
+            )
+        }
+    }
+
+    struct Element(Box<dyn TestTrait>);
+
+    impl Serialize for Element {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            crate::variant::serialize_as_variant("Shape", KEYS, self.0.key(), &*self.0, serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Element {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            crate::variant::deserialize_from_variant("Shape", KEYS, lookup, deserializer)
+                .map(Element)
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct AttributeDrawing {
+        #[serde(with = "attribute")]
+        shape: Box<dyn TestTrait>,
+        #[serde(with = "attribute")]
+        label: Box<dyn TestTrait>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct ElementDrawing {
+        #[serde(rename = "$value")]
+        shapes: Vec<Element>,
+    }
+
+    #[test]
+    fn attribute_keys_round_trip() {
+        let drawing = AttributeDrawing {
+            shape: Box::new(Circle {
+                name: "sun".to_string(),
+                radius: 2.5,
+            }),
+            label: Box::new(Count(7)),
+        };
+
+        let xml = quick_xml::se::to_string(&drawing).unwrap();
+        assert_eq!(
+            xml,
+            r#"<AttributeDrawing><shape type="circle"><name>sun</name><radius>2.5</radius></shape><label type="count">7</label></AttributeDrawing>"#
+        );
+
+        let drawing: AttributeDrawing = quick_xml::de::from_str(&xml).unwrap();
+        assert_eq!(drawing.shape.describe(), "sun 2.5");
+        assert_eq!(drawing.label.describe(), "count 7");
+
+        let drawing = AttributeDrawing {
+            shape: Box::new(Blank),
+            label: Box::new(Count(7)),
+        };
+        let xml = quick_xml::se::to_string(&drawing).unwrap();
+        assert_eq!(
+            xml,
+            r#"<AttributeDrawing><shape type="blank"/><label type="count">7</label></AttributeDrawing>"#
+        );
+        let drawing: AttributeDrawing = quick_xml::de::from_str(&xml).unwrap();
+        assert_eq!(drawing.shape.describe(), "blank");
+
+        let xml = r#"<AttributeDrawing><shape type="nope"/><label type="count">7</label></AttributeDrawing>"#;
+        let error = quick_xml::de::from_str::<AttributeDrawing>(xml)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("unknown deserialization key"));
+
+        let xml = r#"<AttributeDrawing><shape><radius>1</radius></shape><label type="count">7</label></AttributeDrawing>"#;
+        let error = quick_xml::de::from_str::<AttributeDrawing>(xml)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("missing attribute `@type`"));
+    }
+
+    #[test]
+    fn repeated_child_elements_are_read_as_sequences() {
+        let drawing = AttributeDrawing {
+            shape: Box::new(Poly {
+                point: vec![1, 2, 3],
+            }),
+            label: Box::new(Count(7)),
+        };
+
+        let xml = quick_xml::se::to_string(&drawing).unwrap();
+        assert_eq!(
+            xml,
+            r#"<AttributeDrawing><shape type="poly"><point>1</point><point>2</point><point>3</point></shape><label type="count">7</label></AttributeDrawing>"#
+        );
+        let drawing: AttributeDrawing = quick_xml::de::from_str(&xml).unwrap();
+        assert_eq!(drawing.shape.describe(), "poly [1, 2, 3]");
+
+        let xml = r#"<AttributeDrawing><shape type="poly"><point>1</point></shape><label type="count">7</label></AttributeDrawing>"#;
+        let drawing: AttributeDrawing = quick_xml::de::from_str(xml).unwrap();
+        assert_eq!(drawing.shape.describe(), "poly [1]");
+    }
+
+    #[test]
+    fn element_name_keys_round_trip() {
+        let drawing = ElementDrawing {
+            shapes: vec![
+                Element(Box::new(Circle {
+                    name: "sun".to_string(),
+                    radius: 2.5,
+                })),
+                Element(Box::new(Count(7))),
+            ],
+        };
+
+        let xml = quick_xml::se::to_string(&drawing).unwrap();
+        assert_eq!(
+            xml,
+            "<ElementDrawing><circle><name>sun</name><radius>2.5</radius></circle><count>7</count></ElementDrawing>"
+        );
+
+        let drawing: ElementDrawing = quick_xml::de::from_str(&xml).unwrap();
+        assert_eq!(drawing.shapes[0].0.describe(), "sun 2.5");
+        assert_eq!(drawing.shapes[1].0.describe(), "count 7");
+    }
+
+    #[test]
+    fn buffered_text_values_are_parsed() {
+        #[derive(Deserialize)]
+        struct Drawing {
+            #[serde(deserialize_with = "adjacent")]
+            shape: Box<dyn TestTrait>,
+        }
+
+        fn adjacent<'de, D>(deserializer: D) -> Result<Box<dyn TestTrait>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            deserialize_by_key(
+                "Shape",
+                &["id", "data"],
+                |key: String, deserializer| lookup(&key, deserializer),
+                deserializer,
+            )
+        }
+
+        for xml in [
+            "<Drawing><shape><id>circle</id><data><name>sun</name><radius>2.5</radius></data></shape></Drawing>",
+            "<Drawing><shape><data><name>sun</name><radius>2.5</radius></data><id>circle</id></shape></Drawing>",
+        ] {
+            let drawing: Drawing = quick_xml::de::from_str(xml).unwrap();
+            assert_eq!(drawing.shape.describe(), "sun 2.5");
+        }
+
+        let xml = "<Drawing><shape><data>7</data><id>count</id></shape></Drawing>";
+        let drawing: Drawing = quick_xml::de::from_str(xml).unwrap();
+        assert_eq!(drawing.shape.describe(), "count 7");
+
+        let xml = "<Drawing><shape><data>seven</data><id>count</id></shape></Drawing>";
+        let error = quick_xml::de::from_str::<Drawing>(xml).err().unwrap();
+        assert!(error.to_string().contains("invalid type"));
+    }
+}